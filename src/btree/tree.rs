@@ -1,3 +1,7 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+
 use crate::prelude::*;
 
 #[derive(Debug, Clone)]
@@ -55,8 +59,62 @@ impl<const KEY_SIZE: usize, const VALUE_SIZE: usize> GenericBTree<KEY_SIZE, VALU
         }
     }
 
+    /// Read records of all the B-Tree pages.
+    ///
+    /// Pages are loaded level by level with a single `ReadPages`
+    /// task per level instead of one round trip per page.
+    pub fn pages(&self) -> anyhow::Result<Vec<(u32, Vec<GenericBTreeRecord<KEY_SIZE, VALUE_SIZE>>)>> {
+        let mut pages = Vec::new();
+        let mut visited = HashSet::from([self.entry_page]);
+        let mut level = vec![self.entry_page];
+
+        while !level.is_empty() {
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            self.handler.send_normal(FilesystemTask::ReadPages {
+                pages: level.iter()
+                    .map(|page_number| (*page_number, 0, self.page_size))
+                    .collect(),
+                response_sender
+            }).map_err(|err| anyhow::anyhow!("Failed to read B-Tree 0x{:08x} pages : filesystem closed : {err}", self.entry_page))?;
+
+            let bodies = response_receiver.recv()
+                .map_err(|err| anyhow::anyhow!("Failed to read B-Tree 0x{:08x} pages : filesystem closed : {err}", self.entry_page))?
+                .with_context(|| format!("Failed to read B-Tree 0x{:08x} pages", self.entry_page))?;
+
+            let mut next_level = Vec::new();
+
+            for (page_number, body) in level.into_iter().zip(bodies) {
+                let mut body = body.as_slice();
+                let mut records = Vec::new();
+
+                while let Some((record, remaining)) = GenericBTreeRecord::<KEY_SIZE, VALUE_SIZE>::from_bytes(body) {
+                    body = remaining;
+
+                    if record.key.is_none() {
+                        break;
+                    }
+
+                    for addr in [record.left_addr, record.right_addr].into_iter().flatten() {
+                        anyhow::ensure!(visited.insert(addr), "B-Tree page 0x{addr:08x} is referenced twice");
+
+                        next_level.push(addr);
+                    }
+
+                    records.push(record);
+                }
+
+                pages.push((page_number, records));
+            }
+
+            level = next_level;
+        }
+
+        Ok(pages)
+    }
+
     /// Insert provided value under the given key to the filesystem.
-    pub fn insert(&self, key: &[u8; KEY_SIZE], value: [u8; VALUE_SIZE]) {
+    pub fn insert(&self, key: &[u8; KEY_SIZE], value: [u8; VALUE_SIZE]) -> anyhow::Result<()> {
         let mut curr_page = self.entry_page;
 
         loop {
@@ -69,14 +127,11 @@ impl<const KEY_SIZE: usize, const VALUE_SIZE: usize> GenericBTree<KEY_SIZE, VALU
                 offset: 0,
                 length: self.page_size,
                 response_sender
-            }).unwrap_or_else(|err| {
-                panic!("Failed to read body of page 0x{curr_page:08x} : filesystem closed : {err}");
-            });
+            }).map_err(|err| anyhow::anyhow!("Failed to read body of page 0x{curr_page:08x} : filesystem closed : {err}"))?;
 
             let page = response_receiver.recv()
-                .unwrap_or_else(|err| {
-                    panic!("Failed to read body of page 0x{curr_page:08x} : filesystem closed : {err}");
-                });
+                .map_err(|err| anyhow::anyhow!("Failed to read body of page 0x{curr_page:08x} : filesystem closed : {err}"))?
+                .with_context(|| format!("Failed to read body of page 0x{curr_page:08x}"))?;
 
             let mut page = page.as_slice();
             let mut prev_record = None;
@@ -89,31 +144,39 @@ impl<const KEY_SIZE: usize, const VALUE_SIZE: usize> GenericBTree<KEY_SIZE, VALU
                     None => {
                         let record = GenericBTreeRecord::<KEY_SIZE, VALUE_SIZE>::new(*key, value);
 
+                        let (response_sender, response_receiver) = flume::bounded(1);
+
                         self.handler.send_normal(FilesystemTask::WritePage {
                             page_number: curr_page,
                             offset: i,
                             bytes: record.to_bytes(),
-                            response_sender: None
-                        }).unwrap_or_else(|err| {
-                            panic!("Failed to create new B-Tree record on page 0x{curr_page:08x}, offset 0x{i:08X} : filesystem closed : {err}");
-                        });
+                            response_sender: Some(response_sender)
+                        }).map_err(|err| anyhow::anyhow!("Failed to create new B-Tree record on page 0x{curr_page:08x}, offset 0x{i:08X} : filesystem closed : {err}"))?;
 
-                        return;
+                        response_receiver.recv()
+                            .map_err(|err| anyhow::anyhow!("Failed to create new B-Tree record on page 0x{curr_page:08x}, offset 0x{i:08X} : filesystem closed : {err}"))?
+                            .with_context(|| format!("Failed to create new B-Tree record on page 0x{curr_page:08x}, offset 0x{i:08X}"))?;
+
+                        return Ok(());
                     }
 
                     Some(record_key) if record_key == key => {
                         record.value = Some(value);
 
+                        let (response_sender, response_receiver) = flume::bounded(1);
+
                         self.handler.send_normal(FilesystemTask::WritePage {
                             page_number: curr_page,
                             offset: i,
                             bytes: record.to_bytes(),
-                            response_sender: None
-                        }).unwrap_or_else(|err| {
-                            panic!("Failed to update B-Tree record value on page 0x{curr_page:08x}, offset 0x{i:08X} : filesystem closed : {err}");
-                        });
+                            response_sender: Some(response_sender)
+                        }).map_err(|err| anyhow::anyhow!("Failed to update B-Tree record value on page 0x{curr_page:08x}, offset 0x{i:08X} : filesystem closed : {err}"))?;
+
+                        response_receiver.recv()
+                            .map_err(|err| anyhow::anyhow!("Failed to update B-Tree record value on page 0x{curr_page:08x}, offset 0x{i:08X} : filesystem closed : {err}"))?
+                            .with_context(|| format!("Failed to update B-Tree record value on page 0x{curr_page:08x}, offset 0x{i:08X}"))?;
 
-                        return;
+                        return Ok(());
                     }
 
                     Some(record_key) if record_key > key => {
@@ -130,25 +193,26 @@ impl<const KEY_SIZE: usize, const VALUE_SIZE: usize> GenericBTree<KEY_SIZE, VALU
                             self.handler.send_normal(FilesystemTask::CreatePage {
                                 parent_page_number: None,
                                 response_sender
-                            }).unwrap_or_else(|err| {
-                                panic!("Failed to create page : filesystem closed : {err}");
-                            });
+                            }).map_err(|err| anyhow::anyhow!("Failed to create page : filesystem closed : {err}"))?;
 
                             let new_page = response_receiver.recv()
-                                .unwrap_or_else(|err| {
-                                    panic!("Failed to create page : filesystem closed : {err}");
-                                });
+                                .map_err(|err| anyhow::anyhow!("Failed to create page : filesystem closed : {err}"))?
+                                .context("Failed to create page")?;
 
                             record.left_addr = Some(new_page.number());
 
+                            let (response_sender, response_receiver) = flume::bounded(1);
+
                             self.handler.send_normal(FilesystemTask::WritePage {
                                 page_number: curr_page,
                                 offset: i,
                                 bytes: record.to_bytes(),
-                                response_sender: None
-                            }).unwrap_or_else(|err| {
-                                panic!("Failed to update left B-Tree leaf address on page 0x{curr_page:08x}, offset {i:08x} : filesystem closed : {err}");
-                            });
+                                response_sender: Some(response_sender)
+                            }).map_err(|err| anyhow::anyhow!("Failed to update left B-Tree leaf address on page 0x{curr_page:08x}, offset {i:08x} : filesystem closed : {err}"))?;
+
+                            response_receiver.recv()
+                                .map_err(|err| anyhow::anyhow!("Failed to update left B-Tree leaf address on page 0x{curr_page:08x}, offset {i:08x} : filesystem closed : {err}"))?
+                                .with_context(|| format!("Failed to update left B-Tree leaf address on page 0x{curr_page:08x}, offset {i:08x}"))?;
 
                             curr_page = new_page.number();
                             jump_to_page = true;
@@ -177,14 +241,18 @@ impl<const KEY_SIZE: usize, const VALUE_SIZE: usize> GenericBTree<KEY_SIZE, VALU
                         // and unset.
                         let new_record = GenericBTreeRecord::new(*key, value);
 
+                        let (response_sender, response_receiver) = flume::bounded(1);
+
                         self.handler.send_normal(FilesystemTask::WritePage {
                             page_number: curr_page,
                             offset: i + Self::RECORD_SHIFT as u64,
                             bytes: new_record.to_bytes(),
-                            response_sender: None
-                        }).unwrap_or_else(|err| {
-                            panic!("Failed to write initial B-Tree record on page 0x{curr_page:08x} : filesystem closed : {err}");
-                        });
+                            response_sender: Some(response_sender)
+                        }).map_err(|err| anyhow::anyhow!("Failed to write initial B-Tree record on page 0x{curr_page:08x} : filesystem closed : {err}"))?;
+
+                        response_receiver.recv()
+                            .map_err(|err| anyhow::anyhow!("Failed to write initial B-Tree record on page 0x{curr_page:08x} : filesystem closed : {err}"))?
+                            .with_context(|| format!("Failed to write initial B-Tree record on page 0x{curr_page:08x}"))?;
                     }
 
                     else {
@@ -193,25 +261,26 @@ impl<const KEY_SIZE: usize, const VALUE_SIZE: usize> GenericBTree<KEY_SIZE, VALU
                         self.handler.send_normal(FilesystemTask::CreatePage {
                             parent_page_number: None,
                             response_sender
-                        }).unwrap_or_else(|err| {
-                            panic!("Failed to create page : filesystem closed : {err}");
-                        });
+                        }).map_err(|err| anyhow::anyhow!("Failed to create page : filesystem closed : {err}"))?;
 
                         let new_page = response_receiver.recv()
-                            .unwrap_or_else(|err| {
-                                panic!("Failed to create page : filesystem closed : {err}");
-                            });
+                            .map_err(|err| anyhow::anyhow!("Failed to create page : filesystem closed : {err}"))?
+                            .context("Failed to create page")?;
 
                         record.right_addr = Some(new_page.number());
 
+                        let (response_sender, response_receiver) = flume::bounded(1);
+
                         self.handler.send_normal(FilesystemTask::WritePage {
                             page_number: curr_page,
                             offset: i,
                             bytes: record.to_bytes(),
-                            response_sender: None
-                        }).unwrap_or_else(|err| {
-                            panic!("Failed to update left B-Tree leaf address on page 0x{curr_page:08x}, offset {i:08x} : filesystem closed : {err}");
-                        });
+                            response_sender: Some(response_sender)
+                        }).map_err(|err| anyhow::anyhow!("Failed to update right B-Tree leaf address on page 0x{curr_page:08x}, offset {i:08x} : filesystem closed : {err}"))?;
+
+                        response_receiver.recv()
+                            .map_err(|err| anyhow::anyhow!("Failed to update right B-Tree leaf address on page 0x{curr_page:08x}, offset {i:08x} : filesystem closed : {err}"))?
+                            .with_context(|| format!("Failed to update right B-Tree leaf address on page 0x{curr_page:08x}, offset {i:08x}"))?;
 
                         curr_page = new_page.number();
                     }
@@ -220,16 +289,20 @@ impl<const KEY_SIZE: usize, const VALUE_SIZE: usize> GenericBTree<KEY_SIZE, VALU
                 else {
                     let new_record = GenericBTreeRecord::new(*key, value);
 
+                    let (response_sender, response_receiver) = flume::bounded(1);
+
                     self.handler.send_normal(FilesystemTask::WritePage {
                         page_number: curr_page,
                         offset: 0,
                         bytes: new_record.to_bytes(),
-                        response_sender: None
-                    }).unwrap_or_else(|err| {
-                        panic!("Failed to write initial B-Tree record on page 0x{curr_page:08x} : filesystem closed : {err}");
-                    });
+                        response_sender: Some(response_sender)
+                    }).map_err(|err| anyhow::anyhow!("Failed to write initial B-Tree record on page 0x{curr_page:08x} : filesystem closed : {err}"))?;
 
-                    return;
+                    response_receiver.recv()
+                        .map_err(|err| anyhow::anyhow!("Failed to write initial B-Tree record on page 0x{curr_page:08x} : filesystem closed : {err}"))?
+                        .with_context(|| format!("Failed to write initial B-Tree record on page 0x{curr_page:08x}"))?;
+
+                    return Ok(());
                 }
             }
         }
@@ -248,13 +321,13 @@ mod tests {
     fn with_btree(name: &str, callback: impl FnOnce(BTree64, FilesystemDriver<BufStorageIO<File>>, PathBuf)) {
         with_fs(name, |fs, path| {
             let handler = fs.handler().clone();
-            let header = fs.read_header();
+            let header = fs.read_header().unwrap();
 
             let (response_sender, response_receiver) = flume::bounded(1);
 
             handler.send(FilesystemTask::CreatePage { parent_page_number: None, response_sender }, FilesystemTaskPriority::High).unwrap();
 
-            let page = response_receiver.recv().unwrap();
+            let page = response_receiver.recv().unwrap().unwrap();
 
             let btree = BTree64::new(page.number(), header.page_size, handler);
 
@@ -263,37 +336,82 @@ mod tests {
     }
 
     #[test]
-    fn insert() {
-        // TODO: check pages vector value
+    fn insert_failure() {
+        // Write 0 stores the filesystem header, writes 1 and 2
+        // append the B-Tree page and write 3 stores the first record.
+        let io = FaultyStorageIO::new(MemoryStorageIO::new())
+            .with_fault(StorageFault::FailWrite(3));
+
+        let mut fs = FilesystemDriver::new(io).unwrap();
+
+        fs.daemonize();
+
+        let header = fs.read_header().unwrap();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
 
+        let btree = BTree64::new(page.number(), header.page_size, fs.handler().clone());
+
+        let err = btree.insert(&1_u64.to_be_bytes(), 2_u64.to_be_bytes()).unwrap_err();
+
+        assert!(err.downcast_ref::<std::io::Error>().is_some());
+
+        // Filesystem keeps working after the failed write.
+        btree.insert(&1_u64.to_be_bytes(), 2_u64.to_be_bytes()).unwrap();
+
+        let record = page.read(0, BTree64::RECORD_SIZE as u64).unwrap();
+
+        assert_eq!(BTreeRecord64::from_bytes(&record).unwrap().0, BTreeRecord64::new(1_u64.to_be_bytes(), 2_u64.to_be_bytes()));
+    }
+
+    #[test]
+    fn insert() {
         const RECORDS: u64 = 128;
 
-        with_btree("btree-linear-asc-insert", |btree, _, path| {
+        with_btree("btree-linear-asc-insert", |btree, fs, path| {
             for i in 0..RECORDS {
                 let value = seahash::hash(&i.to_be_bytes());
 
-                btree.insert(&i.to_be_bytes(), value.to_be_bytes());
+                btree.insert(&i.to_be_bytes(), value.to_be_bytes()).unwrap();
             }
 
-            let pages = (path.metadata().unwrap().len() - FilesystemHeader::LENGTH as u64) / (PageHeader::LENGTH as u64 + btree.page_size);
+            let header = fs.read_header().unwrap();
+
+            let pages = (path.metadata().unwrap().len() - header.length() as u64) / (header.page_header_length() as u64 + btree.page_size);
 
             // keys[n + 1] < keys[n] => records will fill whole pages space.
             assert_eq!(pages, (RECORDS as f64 / btree.max_records() as f64).ceil() as u64);
+
+            let records = btree.pages().unwrap();
+
+            assert_eq!(records.len() as u64, pages);
+            assert_eq!(records.iter().map(|(_, records)| records.len() as u64).sum::<u64>(), RECORDS);
         });
 
-        with_btree("btree-linear-desc-insert", |btree, _, path| {
+        with_btree("btree-linear-desc-insert", |btree, fs, path| {
             for i in 0..RECORDS {
                 let i = RECORDS - i;
                 let value = seahash::hash(&i.to_be_bytes());
 
-                btree.insert(&i.to_be_bytes(), value.to_be_bytes());
+                btree.insert(&i.to_be_bytes(), value.to_be_bytes()).unwrap();
             }
 
-            let pages = (path.metadata().unwrap().len() - FilesystemHeader::LENGTH as u64) / (PageHeader::LENGTH as u64 + btree.page_size);
+            let header = fs.read_header().unwrap();
+
+            let pages = (path.metadata().unwrap().len() - header.length() as u64) / (header.page_header_length() as u64 + btree.page_size);
 
             // keys[n + 1] > keys[n] => all the records will be put
             // on new pages.
             assert_eq!(pages, RECORDS);
+
+            let records = btree.pages().unwrap();
+
+            assert_eq!(records.len() as u64, RECORDS);
+            assert!(records.iter().all(|(_, records)| records.len() == 1));
         });
 
         // FIXME: infinite loop?
@@ -0,0 +1,153 @@
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+/// Pages allocation bitmap.
+///
+/// Bitmap is stored in the bodies of a chain of pages linked
+/// in forward direction, starting from the page referenced by
+/// the filesystem header. Bit `i % 8` of the byte `i / 8`
+/// is set if the page `i` is in use. Pages of the bitmap
+/// itself are marked as used.
+pub struct AllocationBitmap {
+    /// Numbers of the pages storing the bitmap.
+    pages: Vec<u32>,
+
+    bits: Vec<u8>,
+
+    /// Total amount of pages in the filesystem.
+    total_pages: u64
+}
+
+impl AllocationBitmap {
+    #[inline]
+    pub(crate) fn new(pages: Vec<u32>, bits: Vec<u8>, total_pages: u64) -> Self {
+        Self {
+            pages,
+            bits,
+            total_pages
+        }
+    }
+
+    #[inline]
+    /// Get numbers of the pages storing the bitmap.
+    pub fn pages(&self) -> &[u32] {
+        &self.pages
+    }
+
+    #[inline]
+    /// Get raw bitmap bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bits
+    }
+
+    #[inline]
+    /// Get amount of pages which can be stored in the bitmap.
+    pub fn capacity(&self) -> u64 {
+        self.bits.len() as u64 * 8
+    }
+
+    #[inline]
+    /// Get total amount of pages in the filesystem.
+    pub const fn total_pages(&self) -> u64 {
+        self.total_pages
+    }
+
+    #[inline]
+    /// Get amount of pages in use.
+    pub fn allocated_pages(&self) -> u64 {
+        self.bits.iter().map(|byte| byte.count_ones() as u64).sum()
+    }
+
+    #[inline]
+    /// Get amount of pages which can be reused
+    /// without growing the filesystem.
+    pub fn free_pages(&self) -> u64 {
+        self.total_pages.saturating_sub(self.allocated_pages())
+    }
+
+    #[inline]
+    pub fn is_allocated(&self, page_number: u32) -> bool {
+        let i = page_number as usize;
+
+        self.bits.get(i / 8).is_some_and(|byte| byte & (1 << (i % 8)) != 0)
+    }
+
+    /// Find the first page of the filesystem which is not in use.
+    pub fn first_free(&self) -> Option<u32> {
+        let (i, byte) = self.bits.iter()
+            .enumerate()
+            .find(|(_, byte)| **byte != u8::MAX)?;
+
+        let page_number = i as u64 * 8 + byte.trailing_ones() as u64;
+
+        (page_number < self.total_pages).then_some(page_number as u32)
+    }
+
+    #[inline]
+    pub(crate) fn set_total_pages(&mut self, total_pages: u64) {
+        self.total_pages = total_pages;
+    }
+
+    #[inline]
+    /// Add page storing the following `page_size` bytes of the bitmap.
+    pub(crate) fn push_page(&mut self, page_number: u32, page_size: u64) {
+        self.pages.push(page_number);
+        self.bits.resize(self.bits.len() + page_size as usize, 0);
+    }
+
+    /// Change allocation status of the page, returning
+    /// index of the changed byte.
+    ///
+    /// Page must be within the bitmap's capacity.
+    pub(crate) fn set(&mut self, page_number: u32, allocated: bool) -> usize {
+        let i = page_number as usize;
+
+        if allocated {
+            self.bits[i / 8] |= 1 << (i % 8);
+        } else {
+            self.bits[i / 8] &= !(1 << (i % 8));
+        }
+
+        i / 8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bits() {
+        let mut bitmap = AllocationBitmap::new(vec![], vec![], 0);
+
+        bitmap.push_page(0, 2);
+        bitmap.set_total_pages(12);
+
+        assert_eq!(bitmap.capacity(), 16);
+
+        for page_number in 0..10 {
+            bitmap.set(page_number, true);
+        }
+
+        assert_eq!(bitmap.set(3, false), 0);
+        assert_eq!(bitmap.set(9, false), 1);
+
+        assert!(!bitmap.is_allocated(3));
+        assert!(bitmap.is_allocated(8));
+        assert!(!bitmap.is_allocated(100));
+
+        assert_eq!(bitmap.as_bytes(), &[0b11110111, 0b00000001]);
+        assert_eq!(bitmap.allocated_pages(), 8);
+        assert_eq!(bitmap.free_pages(), 4);
+        assert_eq!(bitmap.first_free(), Some(3));
+
+        bitmap.set(3, true);
+
+        assert_eq!(bitmap.first_free(), Some(9));
+
+        for page_number in 9..12 {
+            bitmap.set(page_number, true);
+        }
+
+        // All the existing pages are used.
+        assert_eq!(bitmap.first_free(), None);
+    }
+}
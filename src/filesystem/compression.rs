@@ -74,7 +74,7 @@ impl Compression {
                 let data = data.as_ref();
 
                 lz4_flex::decompress(data, data.len())
-                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+                    .map_err(std::io::Error::other)
             }
 
             Self::Brotli => {
@@ -1,3 +1,5 @@
+use anyhow::Context;
+
 use crate::prelude::*;
 
 #[derive(Debug, Clone)]
@@ -7,20 +9,137 @@ pub struct FilesystemDriver<T> {
 }
 
 impl<T: StorageIO> FilesystemDriver<T> {
-    pub fn new(mut io: T) -> Self {
-        // If file was just created - put header in it.
-        if io.len() < FilesystemHeader::LENGTH as u64 {
-            io.write(0, FilesystemHeader::default().to_bytes());
+    pub fn new(mut io: T) -> anyhow::Result<Self> {
+        // If file was just created - put header in it. It's synced
+        // so the image can be opened again after a crash.
+        if io.len()? < FilesystemHeader::LEGACY_LENGTH as u64 {
+            io.write(0, FilesystemHeader::default().to_bytes())?;
+            io.sync()?;
+        }
+
+        let (scheduler, handler) = FilesystemTasksScheduler::new();
+
+        let worker = FilesystemWorker::new(io, scheduler, handler.clone())?;
+
+        Ok(Self {
+            worker: Some(worker),
+            handler
+        })
+    }
+
+    /// Open existing filesystem in read-only mode.
+    ///
+    /// The IO is never written. All the tasks which modify
+    /// the filesystem (`WriteFilesystemHeader`, `CreatePage`,
+    /// `FreePage`, `LinkPageForward`, `WritePageHeader`, `WritePage`,
+    /// `WritePages`) are rejected with `ErrorKind::ReadOnlyFilesystem`
+    /// error.
+    pub fn open_read_only(mut io: T) -> anyhow::Result<Self> {
+        if io.len()? < FilesystemHeader::LEGACY_LENGTH as u64 {
+            anyhow::bail!("Failed to open filesystem : storage is too short to contain filesystem header");
         }
 
         let (scheduler, handler) = FilesystemTasksScheduler::new();
 
-        let worker = FilesystemWorker::new(io, scheduler, handler.clone());
+        let mut worker = FilesystemWorker::new(io, scheduler, handler.clone())?;
+
+        worker.set_read_only(true);
 
-        Self {
+        Ok(Self {
             worker: Some(worker),
             handler
+        })
+    }
+
+    /// Open filesystem with the pages cache of the given size.
+    pub fn with_pages_cache(io: T, limit: PagesCacheLimit) -> anyhow::Result<Self> {
+        let mut driver = Self::new(io)?;
+
+        if let Some(worker) = &mut driver.worker {
+            worker.set_pages_cache_limit(limit);
+        }
+
+        Ok(driver)
+    }
+
+    /// Open filesystem which tracks used pages with the allocation
+    /// bitmap instead of the free pages list. Bitmap is created
+    /// if the filesystem doesn't have it yet.
+    pub fn with_allocation_bitmap(io: T) -> anyhow::Result<Self> {
+        let mut driver = Self::new(io)?;
+
+        if let Some(worker) = &mut driver.worker {
+            worker.enable_allocation_bitmap()
+                .context("Failed to create allocation bitmap")?;
+        }
+
+        Ok(driver)
+    }
+
+    /// Open filesystem which stores checksums of the pages'
+    /// bodies and verifies them on reads.
+    ///
+    /// Checksums can be enabled or changed only
+    /// for filesystems without pages.
+    pub fn with_page_checksums(io: T, checksum: Checksum) -> anyhow::Result<Self> {
+        let mut driver = Self::new(io)?;
+
+        if let Some(worker) = &mut driver.worker {
+            worker.enable_page_checksums(checksum)
+                .context("Failed to enable page checksums")?;
+        }
+
+        Ok(driver)
+    }
+
+    /// Open filesystem which stores pages' bodies compressed
+    /// if it reduces their size. Logical page size doesn't
+    /// change, while unused space of the compressed pages
+    /// is discarded from the IO.
+    ///
+    /// Compression algorithm can be enabled or changed only
+    /// for filesystems without pages.
+    pub fn with_page_compression(io: T, compression: Compression, level: CompressionLevel) -> anyhow::Result<Self> {
+        let mut driver = Self::new(io)?;
+
+        if let Some(worker) = &mut driver.worker {
+            worker.enable_page_compression(compression, level)
+                .context("Failed to enable page compression")?;
+        }
+
+        Ok(driver)
+    }
+
+    /// Open filesystem which polls up to `batch_size` tasks
+    /// at once and submits their page reads and writes to the
+    /// IO as batches.
+    ///
+    /// Useful with IO backends which can process multiple
+    /// operations in parallel, e.g. `UringStorageIO`.
+    pub fn with_tasks_batch(io: T, batch_size: usize) -> anyhow::Result<Self> {
+        let mut driver = Self::new(io)?;
+
+        if let Some(worker) = &mut driver.worker {
+            worker.set_batch_size(batch_size);
+        }
+
+        Ok(driver)
+    }
+
+    /// Open filesystem which limits amount of bytes read and written
+    /// by the tasks. Limits can be changed at runtime using the given
+    /// throttle handle.
+    ///
+    /// Tasks which exceed the budget wait in the scheduler, so the
+    /// page reads can be executed before the throttled writes.
+    pub fn with_throttle(io: T, throttle: StorageThrottle) -> anyhow::Result<Self> {
+        let mut driver = Self::new(io)?;
+
+        if let Some(worker) = &mut driver.worker {
+            worker.set_throttle(Some(throttle));
         }
+
+        Ok(driver)
     }
 
     #[inline]
@@ -40,33 +159,89 @@ impl<T: StorageIO> FilesystemDriver<T> {
     }
 
     /// Read header of the filesystem.
-    pub fn read_header(&self) -> FilesystemHeader {
+    pub fn read_header(&self) -> anyhow::Result<FilesystemHeader> {
         let (response_sender, response_receiver) = flume::bounded(1);
 
         self.handler.send_high(FilesystemTask::ReadFilesystemHeader { response_sender })
-            .unwrap_or_else(|err| {
-                panic!("Failed to read filesystem header : filesystem closed : {err}");
-            });
+            .map_err(|err| anyhow::anyhow!("Failed to read filesystem header : filesystem closed : {err}"))?;
 
         response_receiver.recv()
-            .unwrap_or_else(|err| {
-                panic!("Failed to read filesystem header : filesystem closed : {err}");
-            })
+            .map_err(|err| anyhow::anyhow!("Failed to read filesystem header : filesystem closed : {err}"))
     }
 
     /// Write header of the filesystem.
-    pub fn write_header(&self, header: FilesystemHeader) {
-        self.handler.send_high(FilesystemTask::WriteFilesystemHeader { header })
-            .unwrap_or_else(|err| {
-                panic!("Failed to write filesystem header : filesystem closed : {err}");
-            });
+    pub fn write_header(&self, header: FilesystemHeader) -> anyhow::Result<()> {
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        self.handler.send_high(FilesystemTask::WriteFilesystemHeader {
+            header,
+            response_sender: Some(response_sender)
+        }).map_err(|err| anyhow::anyhow!("Failed to write filesystem header : filesystem closed : {err}"))?;
+
+        response_receiver.recv()
+            .map_err(|err| anyhow::anyhow!("Failed to write filesystem header : filesystem closed : {err}"))?
+            .context("Failed to write filesystem header")
+    }
+
+    /// Read usage statistics of the pages cache.
+    pub fn pages_cache_stats(&self) -> anyhow::Result<PagesCacheStats> {
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        self.handler.send_high(FilesystemTask::ReadPagesCacheStats { response_sender })
+            .map_err(|err| anyhow::anyhow!("Failed to read pages cache stats : filesystem closed : {err}"))?;
+
+        response_receiver.recv()
+            .map_err(|err| anyhow::anyhow!("Failed to read pages cache stats : filesystem closed : {err}"))
+    }
+
+    /// Read execution statistics of the filesystem tasks.
+    pub fn stats(&self) -> anyhow::Result<FilesystemStats> {
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        self.handler.send_high(FilesystemTask::ReadStats { response_sender })
+            .map_err(|err| anyhow::anyhow!("Failed to read filesystem stats : filesystem closed : {err}"))?;
+
+        response_receiver.recv()
+            .map_err(|err| anyhow::anyhow!("Failed to read filesystem stats : filesystem closed : {err}"))
+    }
+
+    /// Read allocation bitmap of the filesystem.
+    /// `None` is returned if it's not enabled.
+    pub fn allocation_bitmap(&self) -> anyhow::Result<Option<AllocationBitmap>> {
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        self.handler.send_high(FilesystemTask::ReadAllocationBitmap { response_sender })
+            .map_err(|err| anyhow::anyhow!("Failed to read allocation bitmap : filesystem closed : {err}"))?;
+
+        response_receiver.recv()
+            .map_err(|err| anyhow::anyhow!("Failed to read allocation bitmap : filesystem closed : {err}"))
+    }
+
+    /// Stop the filesystem. Scheduled tasks are dropped, and the IO
+    /// is closed when the worker finishes the current task.
+    pub fn close(&self) -> anyhow::Result<()> {
+        self.handler.close()
+            .map_err(|err| anyhow::anyhow!("Failed to close filesystem : filesystem closed : {err}"))
+    }
+
+    /// Write all the pending changes of the storage IO to the disk.
+    pub fn sync(&self) -> anyhow::Result<()> {
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        self.handler.send_normal(FilesystemTask::Sync {
+            response_sender: Some(response_sender)
+        }).map_err(|err| anyhow::anyhow!("Failed to sync filesystem : filesystem closed : {err}"))?;
+
+        response_receiver.recv()
+            .map_err(|err| anyhow::anyhow!("Failed to sync filesystem : filesystem closed : {err}"))?
+            .context("Failed to sync filesystem")
     }
 }
 
 impl<T: StorageIO + Send + Sync + 'static> FilesystemDriver<T> {
     #[inline]
     /// Daemonize filesystem worker.
-    pub fn daemonize(&mut self) -> Option<std::thread::JoinHandle<()>> {
+    pub fn daemonize(&mut self) -> Option<std::thread::JoinHandle<anyhow::Result<()>>> {
         self.worker.take().map(FilesystemWorker::daemonize)
     }
 }
@@ -94,9 +269,11 @@ pub(crate) mod tests {
             .open(&path)
             .expect("Failed to open file");
 
-        let buf = BufStorageIO::new(file, 1024 * 1024 * 16);
+        let buf = BufStorageIO::new(file, 1024 * 1024 * 16)
+            .expect("Failed to buffer file");
 
-        let mut fs = FilesystemDriver::new(buf);
+        let mut fs = FilesystemDriver::new(buf)
+            .expect("Failed to open filesystem");
 
         fs.daemonize();
 
@@ -105,28 +282,496 @@ pub(crate) mod tests {
         std::fs::remove_file(path).expect("Failed to delete filesystem");
     }
 
+    #[test]
+    fn read_only() {
+        with_fs("read-only", |fs, path| {
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+            let page = response_receiver.recv().unwrap().unwrap();
+
+            page.write(0, vec![1, 2, 3]).unwrap();
+
+            fs.sync().unwrap();
+
+            let image = std::fs::read(&path).unwrap();
+
+            let mut fs = FilesystemDriver::open_read_only(File::open(&path).unwrap())
+                .expect("Failed to open filesystem");
+
+            fs.daemonize();
+
+            assert_eq!(fs.read_header().unwrap(), FilesystemHeader::default());
+
+            let page = Page::new(0, fs.handler().clone());
+
+            assert_eq!(page.read(0, 3).unwrap(), &[1, 2, 3]);
+
+            let err = page.write(0, vec![4]).unwrap_err();
+
+            assert_eq!(err.downcast_ref::<std::io::Error>().map(std::io::Error::kind), Some(std::io::ErrorKind::ReadOnlyFilesystem));
+
+            assert!(fs.write_header(FilesystemHeader::default()).is_err());
+
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+            assert!(response_receiver.recv().unwrap().is_err());
+
+            // Tasks without response senders are rejected as well.
+            fs.handler().send_normal(FilesystemTask::WritePage {
+                page_number: 0,
+                offset: 0,
+                bytes: vec![4],
+                response_sender: None
+            }).unwrap();
+
+            fs.handler().send_normal(FilesystemTask::WritePages {
+                pages: vec![(0, 0, vec![4])],
+                response_sender: None
+            }).unwrap();
+
+            fs.handler().send_normal(FilesystemTask::WritePageHeader {
+                page_number: 0,
+                header: page.read_header().unwrap(),
+                response_sender: None
+            }).unwrap();
+
+            // Worker is still alive after rejected tasks.
+            assert_eq!(page.read(0, 3).unwrap(), &[1, 2, 3]);
+
+            let stats = fs.stats().unwrap();
+
+            for task in ["WritePage", "WritePages", "WritePageHeader"] {
+                assert_eq!(stats.task(task).unwrap().unreported_errors, 1);
+            }
+
+            assert!(stats.last_unreported_error.unwrap().contains("read-only"));
+
+            assert_eq!(std::fs::read(&path).unwrap(), image);
+        });
+
+        assert!(FilesystemDriver::open_read_only(MemoryStorageIO::new()).is_err());
+    }
+
+    #[test]
+    fn stats() {
+        with_fs("stats", |fs, _| {
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+            let page = response_receiver.recv().unwrap().unwrap();
+
+            for i in 0..4 {
+                page.write(i, vec![i as u8]).unwrap();
+            }
+
+            assert_eq!(page.read(0, 4).unwrap(), &[0, 1, 2, 3]);
+
+            fs.sync().unwrap();
+
+            let stats = fs.stats().unwrap();
+
+            assert_eq!(stats.task("CreatePage").unwrap().count, 1);
+            assert_eq!(stats.task("WritePage").unwrap().count, 4);
+            assert_eq!(stats.task("WritePage").unwrap().latency.count(), 4);
+            assert_eq!(stats.task("ReadPage").unwrap().count, 1);
+            assert_eq!(stats.task("Sync").unwrap().count, 1);
+            assert_eq!(stats.task("ReadStats"), None);
+
+            assert_eq!(fs.stats().unwrap().task("ReadStats").unwrap().count, 1);
+        });
+    }
+
+    #[test]
+    fn free_pages() {
+        with_fs("free-pages", |fs, path| {
+            let create_page = || {
+                let (response_sender, response_receiver) = flume::bounded(1);
+
+                fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+                response_receiver.recv().unwrap().unwrap()
+            };
+
+            let pages = (0..3).map(|_| create_page()).collect::<Vec<_>>();
+
+            pages[1].write(0, vec![1; 16]).unwrap();
+
+            let len = std::fs::metadata(&path).unwrap().len();
+
+            pages[1].clone().free().unwrap();
+            pages[2].clone().free().unwrap();
+
+            assert!(pages[1].clone().free().is_err());
+            assert!(Page::new(100, fs.handler().clone()).free().is_err());
+
+            assert_eq!(fs.read_header().unwrap().free_page_number, Some(2));
+            assert!(pages[2].read_header().unwrap().is_free);
+
+            // Free list can't be changed by the header writes.
+            assert!(fs.write_header(FilesystemHeader::default()).is_err());
+
+            fs.write_header(fs.read_header().unwrap()).unwrap();
+
+            // Freed pages are reused in reversed order.
+            assert_eq!(create_page().number(), 2);
+
+            let page = create_page();
+
+            assert_eq!(page.number(), 1);
+            assert_eq!(page.read(0, 16).unwrap(), vec![0; 16]);
+            assert!(!page.read_header().unwrap().is_free);
+
+            // Image didn't grow.
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+
+            assert_eq!(fs.read_header().unwrap().free_page_number, None);
+            assert_eq!(create_page().number(), 3);
+        });
+    }
+
+    #[test]
+    fn allocation_bitmap() {
+        fn open_fs(path: &PathBuf, with_bitmap: bool) -> FilesystemDriver<File> {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .open(path)
+                .expect("Failed to open file");
+
+            let mut fs = if with_bitmap {
+                FilesystemDriver::with_allocation_bitmap(file)
+            } else {
+                FilesystemDriver::new(file)
+            }.expect("Failed to open filesystem");
+
+            fs.daemonize();
+
+            fs
+        }
+
+        fn create_page(fs: &FilesystemDriver<File>) -> Page {
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+            response_receiver.recv().unwrap().unwrap()
+        }
+
+        let path = std::env::temp_dir().join(".animefs-test-allocation-bitmap");
+
+        // Every bitmap page stores 128 bits.
+        std::fs::write(&path, FilesystemHeader { page_size: 16, ..FilesystemHeader::default() }.to_bytes()).unwrap();
+
+        let fs = open_fs(&path, false);
+
+        for _ in 0..4 {
+            create_page(&fs);
+        }
+
+        Page::new(1, fs.handler().clone()).free().unwrap();
+        Page::new(2, fs.handler().clone()).free().unwrap();
+
+        assert_eq!(fs.allocation_bitmap().unwrap(), None);
+
+        // Pages from the free list are marked as unused.
+        let fs = open_fs(&path, true);
+
+        let bitmap = fs.allocation_bitmap().unwrap().unwrap();
+
+        assert_eq!(bitmap.pages(), &[4]);
+        assert_eq!(bitmap.total_pages(), 5);
+        assert_eq!(bitmap.allocated_pages(), 3);
+        assert_eq!(bitmap.free_pages(), 2);
+
+        assert_eq!(fs.read_header().unwrap().free_page_number, None);
+
+        assert_eq!(create_page(&fs).number(), 1);
+        assert_eq!(create_page(&fs).number(), 2);
+
+        for page_number in 5..128 {
+            assert_eq!(create_page(&fs).number(), page_number);
+        }
+
+        // Page 128 extends the bitmap.
+        assert_eq!(create_page(&fs).number(), 129);
+
+        Page::new(7, fs.handler().clone()).free().unwrap();
+
+        assert!(Page::new(7, fs.handler().clone()).free().is_err());
+        assert!(Page::new(4, fs.handler().clone()).free().is_err());
+
+        // Bitmap is restored from the filesystem.
+        let fs = open_fs(&path, false);
+
+        let bitmap = fs.allocation_bitmap().unwrap().unwrap();
+
+        assert_eq!(bitmap.pages(), &[4, 128]);
+        assert_eq!(bitmap.total_pages(), 130);
+        assert_eq!(bitmap.free_pages(), 1);
+        assert!(!bitmap.is_allocated(7));
+
+        assert_eq!(create_page(&fs).number(), 7);
+        assert_eq!(fs.allocation_bitmap().unwrap().unwrap().free_pages(), 0);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn page_checksums() {
+        let path = std::env::temp_dir().join(".animefs-test-page-checksums");
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .expect("Failed to open file");
+
+        let mut fs = FilesystemDriver::with_page_checksums(file, Checksum::Xxh3)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let header = fs.read_header().unwrap();
+
+        assert_eq!(header.page_checksum, Some(Checksum::Xxh3));
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
+        let book = page.clone().into_book().unwrap();
+
+        let bytes = (0..header.page_size * 2)
+            .map(|i| i as u8)
+            .collect::<Vec<_>>();
+
+        book.write(0, bytes.clone()).unwrap();
+
+        assert_eq!(book.read(0, header.page_size * 2).unwrap(), bytes);
+
+        let page_header = page.read_header().unwrap();
+
+        assert_eq!(page_header.checksum, Checksum::Xxh3.checksum(&bytes[..header.page_size as usize]));
+
+        // Checksum is managed by the filesystem.
+        page.write_header(PageHeader { checksum: 0, ..page_header }).unwrap();
+
+        assert_eq!(page.read_header().unwrap(), page_header);
+
+        // Checksums can't be changed for filesystem with pages.
+        let file = File::options().read(true).write(true).open(&path).unwrap();
+
+        assert!(FilesystemDriver::with_page_checksums(file, Checksum::Seahash).is_err());
+
+        // Corrupt the second page.
+        let offset = header.length() as u64 + header.page_header_length() as u64 * 2 + header.page_size + 10;
+
+        File::options().write(true).open(&path).unwrap().write(offset, [0xFF]).unwrap();
+
+        let err = book.read(0, header.page_size * 2).unwrap_err();
+
+        assert_eq!(err.downcast_ref::<std::io::Error>().map(std::io::Error::kind), Some(std::io::ErrorKind::InvalidData));
+
+        assert_eq!(page.read(0, 4).unwrap(), &bytes[..4]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn page_compression() {
+        let path = std::env::temp_dir().join(".animefs-test-page-compression");
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .expect("Failed to open file");
+
+        let mut fs = FilesystemDriver::with_page_compression(file, Compression::Zstd, CompressionLevel::Auto)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let header = fs.read_header().unwrap();
+
+        assert_eq!(header.page_compression, Some(Compression::Zstd));
+        assert_eq!(header.page_compression_level, CompressionLevel::Auto);
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
+        let book = page.clone().into_book().unwrap();
+
+        let bytes = (0..header.page_size * 3)
+            .map(|i| (i / 100) as u8)
+            .collect::<Vec<_>>();
+
+        book.write(0, bytes.clone()).unwrap();
+
+        assert_eq!(book.read(0, header.page_size * 3).unwrap(), bytes);
+
+        let page_header = page.read_header().unwrap();
+
+        assert!(page_header.compressed_size.is_some_and(|size| (size as u64) < header.page_size));
+
+        // Compressed size is managed by the filesystem.
+        page.write_header(PageHeader { compressed_size: None, ..page_header }).unwrap();
+
+        assert_eq!(page.read_header().unwrap(), page_header);
+
+        // Incompressible bodies are stored as is.
+        let mut state = 0x2545F4914F6CDD1D_u64;
+
+        let noise = (0..header.page_size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+
+                state as u8
+            })
+            .collect::<Vec<_>>();
+
+        page.write(0, noise.clone()).unwrap();
+
+        assert_eq!(page.read_header().unwrap().compressed_size, None);
+        assert_eq!(page.read(0, header.page_size).unwrap(), noise);
+
+        // Compression can't be changed for filesystem with pages.
+        let file = File::options().read(true).write(true).open(&path).unwrap();
+
+        assert!(FilesystemDriver::with_page_compression(file, Compression::Lz4, CompressionLevel::Fast).is_err());
+
+        // Pages are decompressed after reopening the filesystem.
+        let file = File::options().read(true).write(true).open(&path).unwrap();
+
+        let mut fs = FilesystemDriver::with_page_compression(file, Compression::Zstd, CompressionLevel::Max)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let book = Book::open(Page::new(page.number(), fs.handler().clone()), header.page_size);
+
+        assert_eq!(book.read(0, header.page_size).unwrap(), noise);
+        assert_eq!(book.read(header.page_size, header.page_size * 2).unwrap(), &bytes[header.page_size as usize..]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn header() {
         with_fs("header", |fs, _| {
-            let header = fs.read_header();
+            let header = fs.read_header().unwrap();
 
             assert_eq!(header.names_checksum, Checksum::Seahash);
             assert_eq!(header.names_compression, None);
             assert_eq!(header.names_compression_level, CompressionLevel::Auto);
 
             fs.write_header(FilesystemHeader {
+                version: FilesystemHeader::VERSION,
                 page_size: 123,
                 names_checksum: Checksum::Siphash,
                 names_compression: Some(Compression::Lz4),
-                names_compression_level: CompressionLevel::Balanced
-            });
+                names_compression_level: CompressionLevel::Balanced,
+                page_checksum: None,
+                page_compression: None,
+                page_compression_level: CompressionLevel::Auto,
+                free_page_number: None,
+                allocation_bitmap_page_number: None
+            }).unwrap();
+
+            fs.sync().unwrap();
 
-            let header = fs.read_header();
+            let header = fs.read_header().unwrap();
 
             assert_eq!(header.page_size, 123);
             assert_eq!(header.names_checksum, Checksum::Siphash);
             assert_eq!(header.names_compression, Some(Compression::Lz4));
             assert_eq!(header.names_compression_level, CompressionLevel::Balanced);
+
+            // Format version is managed by the filesystem.
+            let err = fs.write_header(FilesystemHeader { version: 0, ..header }).unwrap_err();
+
+            assert_eq!(err.downcast_ref::<std::io::Error>().map(std::io::Error::kind), Some(std::io::ErrorKind::InvalidInput));
         });
+
+        // Unknown format versions are refused.
+        let mut io = MemoryStorageIO::new();
+
+        let mut bytes = FilesystemHeader::default().to_bytes();
+
+        bytes[10..12].copy_from_slice(&(FilesystemHeader::VERSION + 1).to_le_bytes());
+
+        io.write(0, bytes).unwrap();
+
+        let err = FilesystemDriver::new(io).unwrap_err();
+
+        assert_eq!(err.downcast_ref::<std::io::Error>().map(std::io::Error::kind), Some(std::io::ErrorKind::Unsupported));
+    }
+
+    #[test]
+    fn legacy_header() {
+        let path = std::env::temp_dir().join(".animefs-test-legacy-header");
+
+        // Version 0 header: 16 bytes pages with seahash names checksum.
+        let mut bytes = 16_u64.to_le_bytes().to_vec();
+
+        bytes.extend_from_slice(&FilesystemHeader::FLAG_NAMES_CHECKSUM_SEAHASH.to_le_bytes());
+
+        std::fs::write(&path, &bytes).unwrap();
+
+        let file = File::options().read(true).write(true).open(&path).unwrap();
+
+        let mut fs = FilesystemDriver::new(file).unwrap();
+
+        fs.daemonize();
+
+        let header = fs.read_header().unwrap();
+
+        assert_eq!(header.version, 0);
+        assert_eq!(header.length(), FilesystemHeader::LEGACY_LENGTH);
+        assert_eq!(header.page_header_length(), PageHeader::BASE_LENGTH);
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
+
+        page.write(0, vec![1; 16]).unwrap();
+
+        fs.sync().unwrap();
+
+        // Pages are stored using the legacy layout.
+        let image = std::fs::read(&path).unwrap();
+
+        assert_eq!(image.len(), FilesystemHeader::LEGACY_LENGTH + PageHeader::BASE_LENGTH + 16);
+        assert_eq!(&image[..FilesystemHeader::LEGACY_LENGTH], bytes);
+        assert_eq!(&image[FilesystemHeader::LEGACY_LENGTH + PageHeader::BASE_LENGTH..], &[1; 16]);
+
+        // Newer features are not supported by the legacy format.
+        let err = page.free().unwrap_err();
+
+        assert_eq!(err.downcast_ref::<std::io::Error>().map(std::io::Error::kind), Some(std::io::ErrorKind::Unsupported));
+
+        drop(fs);
+
+        let file = File::options().read(true).write(true).open(&path).unwrap();
+
+        assert!(FilesystemDriver::with_page_checksums(file, Checksum::Xxh3).is_err());
+
+        std::fs::remove_file(path).unwrap();
     }
 }
@@ -150,17 +150,29 @@ pub struct FilesystemTreeReader<const BUF_SIZE: u64> {
     offset: u64,
     buf_offset: u64,
     buf: Vec<u8>,
-    mode: FilesystemTreeReaderMode
+    mode: FilesystemTreeReaderMode,
+    finished: bool
 }
 
 impl<const BUF_SIZE: u64> Iterator for FilesystemTreeReader<BUF_SIZE> {
-    type Item = (u64, FilesystemEntry);
+    type Item = anyhow::Result<(u64, FilesystemEntry)>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
         if self.buf.is_empty() || (self.offset > self.buf_offset && self.offset - self.buf_offset > BUF_SIZE - FilesystemEntry::LENGTH as u64) || self.buf_offset > self.offset {
             self.buf_offset = self.offset;
 
-            self.buf = self.book.read(self.offset, BUF_SIZE);
+            self.buf = match self.book.read(self.offset, BUF_SIZE) {
+                Ok(buf) => buf,
+                Err(err) => {
+                    self.finished = true;
+
+                    return Some(Err(err));
+                }
+            };
         }
 
         let i = (self.offset - self.buf_offset) as usize;
@@ -181,13 +193,13 @@ impl<const BUF_SIZE: u64> Iterator for FilesystemTreeReader<BUF_SIZE> {
             FilesystemTreeReaderMode::Sibling if entry.sibling_addr != 0 => {
                 self.offset = entry.sibling_addr;
 
-                Some((offset, entry))
+                Some(Ok((offset, entry)))
             }
 
             FilesystemTreeReaderMode::Child if entry.child_addr != 0 => {
                 self.offset = entry.child_addr;
 
-                Some((offset, entry))
+                Some(Ok((offset, entry)))
             }
 
             _ => None
@@ -209,14 +221,14 @@ impl FilesystemTree {
     pub const ROOT_OFFSET: u64 = 8;
 
     /// Open filesystem tree reader from the given book.
-    pub fn open(book: Book) -> Self {
+    pub fn open(book: Book) -> anyhow::Result<Self> {
         let mut last_entry_addr = [0; 8];
 
-        last_entry_addr.copy_from_slice(&book.read(0, 8));
+        last_entry_addr.copy_from_slice(&book.read(0, 8)?);
 
         let last_entry_addr = u64::from_be_bytes(last_entry_addr);
 
-        Self {
+        Ok(Self {
             book,
 
             last_entry_addr: if last_entry_addr == 0 {
@@ -224,22 +236,22 @@ impl FilesystemTree {
             } else {
                 last_entry_addr
             }
-        }
+        })
     }
 
     /// Read filesystem entry at the given offset.
-    pub fn read(&self, offset: u64) -> FilesystemEntry {
+    pub fn read(&self, offset: u64) -> anyhow::Result<FilesystemEntry> {
         let mut entry = [0; FilesystemEntry::LENGTH];
 
-        entry.copy_from_slice(&self.book.read(offset, FilesystemEntry::LENGTH as u64));
+        entry.copy_from_slice(&self.book.read(offset, FilesystemEntry::LENGTH as u64)?);
 
-        FilesystemEntry::from_bytes(&entry)
+        Ok(FilesystemEntry::from_bytes(&entry))
     }
 
     #[inline]
     /// Write filesystem entry to the given offset.
-    pub fn write(&self, offset: u64, entry: FilesystemEntry) {
-        self.book.write(offset, entry.to_bytes());
+    pub fn write(&self, offset: u64, entry: FilesystemEntry) -> anyhow::Result<()> {
+        self.book.write(offset, entry.to_bytes())
     }
 
     #[inline]
@@ -264,7 +276,8 @@ impl FilesystemTree {
             offset,
             buf_offset: 0,
             buf: vec![],
-            mode
+            mode,
+            finished: false
         }
     }
 
@@ -288,23 +301,23 @@ impl FilesystemTree {
     /// **WARNING**: First bytes of the page are used to hold metadata about the entries tree.
     /// Make sure to use `FilesystemTree::ROOT_OFFSET` as the first entry offset.
     /// Also be accurate to not to create cycle references.
-    pub fn insert_child<const BUF_SIZE: u64>(&mut self, offset: u64, entry: FilesystemEntry) -> u64 {
-        let mut parent = self.read(offset);
+    pub fn insert_child<const BUF_SIZE: u64>(&mut self, offset: u64, entry: FilesystemEntry) -> anyhow::Result<u64> {
+        let mut parent = self.read(offset)?;
 
         // Write entry node to the disk.
         let i = self.last_entry_addr + FilesystemEntry::LENGTH as u64;
 
-        self.last_entry_addr = i;
+        self.book.write(i, entry.to_bytes())?;
+        self.book.write(0, i.to_be_bytes())?;
 
-        self.book.write(i, entry.to_bytes());
-        self.book.write(0, self.last_entry_addr.to_be_bytes());
+        self.last_entry_addr = i;
 
         // If parent entry doesn't have any children yet - just
         // update its first reference.
         if parent.child_addr == 0 {
             parent.child_addr = i;
 
-            self.write(offset, parent);
+            self.write(offset, parent)?;
         }
 
         // Otherwise read to the last child of the given entry
@@ -312,23 +325,23 @@ impl FilesystemTree {
         else {
             let reader = self.reader::<BUF_SIZE>(parent.child_addr, FilesystemTreeReaderMode::Sibling);
 
-            match reader.last() {
+            match reader.last().transpose()? {
                 Some((offset, mut child)) => {
                     child.sibling_addr = i;
 
-                    self.write(offset, child);
+                    self.write(offset, child)?;
                 }
 
                 // Must be impossible but whatever.
                 None => {
                     parent.child_addr = i;
 
-                    self.write(offset, parent);
+                    self.write(offset, parent)?;
                 }
             }
         }
 
-        i
+        Ok(i)
     }
 
     /// Insert given entry as a sibling of the entry under the provided offset.
@@ -349,30 +362,29 @@ impl FilesystemTree {
     /// **WARNING**: First bytes of the page are used to hold metadata about the entries tree.
     /// Make sure to use `FilesystemTree::ROOT_OFFSET` as the first entry offset.
     /// Also be accurate to not to create cycle references.
-    pub fn insert_sibling<const BUF_SIZE: u64>(&mut self, offset: u64, entry: FilesystemEntry) -> u64 {
+    pub fn insert_sibling<const BUF_SIZE: u64>(&mut self, offset: u64, entry: FilesystemEntry) -> anyhow::Result<u64> {
         let reader = self.reader::<BUF_SIZE>(offset, FilesystemTreeReaderMode::Sibling);
 
-        match reader.last() {
+        match reader.last().transpose()? {
             Some((offset, mut parent)) => {
                 let i = self.last_entry_addr + FilesystemEntry::LENGTH as u64;
 
                 parent.sibling_addr = i;
 
-                self.book.write(i, entry.to_bytes());
-                self.book.write(offset, parent.to_bytes());
+                self.book.write(i, entry.to_bytes())?;
+                self.book.write(offset, parent.to_bytes())?;
+                self.book.write(0, i.to_be_bytes())?;
 
                 self.last_entry_addr = i;
 
-                self.book.write(0, self.last_entry_addr.to_be_bytes());
-
-                i
+                Ok(i)
             }
 
             // There's no entry under the offset so we can freely make a new one.
             None => {
-                self.write(offset, entry);
+                self.write(offset, entry)?;
 
-                offset
+                Ok(offset)
             }
         }
     }
@@ -387,22 +399,22 @@ pub mod tests {
     #[test]
     fn children() {
         with_fs("entry-children", |fs, _| {
-            let book = Page::new(0, fs.handler().clone()).into_book();
+            let book = Page::new(0, fs.handler().clone()).into_book().unwrap();
 
-            let mut tree = FilesystemTree::open(book);
+            let mut tree = FilesystemTree::open(book).unwrap();
             let mut offset = FilesystemTree::ROOT_OFFSET;
 
             for i in 1..128 {
                 let entry = FilesystemEntry::new(i, 0);
 
-                offset = tree.insert_child::<1024>(FilesystemTree::ROOT_OFFSET, entry);
+                offset = tree.insert_child::<1024>(FilesystemTree::ROOT_OFFSET, entry).unwrap();
             }
 
             assert_eq!(offset, FilesystemTree::ROOT_OFFSET + 127 * FilesystemEntry::LENGTH as u64);
 
-            let root = tree.read(FilesystemTree::ROOT_OFFSET);
+            let root = tree.read(FilesystemTree::ROOT_OFFSET).unwrap();
 
-            let (offset, last_child) = tree.reader::<1024>(root.child_addr, FilesystemTreeReaderMode::Sibling).last().unwrap();
+            let (offset, last_child) = tree.reader::<1024>(root.child_addr, FilesystemTreeReaderMode::Sibling).last().unwrap().unwrap();
 
             assert_eq!(offset, FilesystemTree::ROOT_OFFSET + 127 * FilesystemEntry::LENGTH as u64);
             assert_eq!(last_child, FilesystemEntry::new(127, 0));
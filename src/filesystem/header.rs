@@ -1,33 +1,86 @@
+use std::io::{Error, ErrorKind};
+
 use crate::prelude::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Header stored at the beginning of the filesystem.
+///
+/// Filesystems of the legacy version 0 store only page size
+/// and names flags in the 10 bytes long header and don't
+/// support free pages list, allocation bitmap, page checksums
+/// and compression. Newer versions are marked with the
+/// `FLAG_HAS_VERSION` flag and store the version number
+/// right after the flags.
+///
+/// Length of the page header depends on the enabled
+/// features, see `FilesystemHeader::page_header_length`.
 pub struct FilesystemHeader {
+    /// Version of the filesystem format.
+    /// Managed by the filesystem worker.
+    pub version: u16,
+
     /// Size in bytes of the page's body.
     /// Physical size of the page equals to
-    /// page_size + page_header_size.
+    /// page_size + page_header_length.
     pub page_size: u64,
 
     pub names_checksum: Checksum,
     pub names_compression: Option<Compression>,
-    pub names_compression_level: CompressionLevel
+    pub names_compression_level: CompressionLevel,
+
+    /// Algorithm of the pages' body checksums.
+    /// Checksums are not stored if it's not set.
+    pub page_checksum: Option<Checksum>,
+
+    /// Algorithm of the pages' body compression.
+    /// Bodies are stored as is if it's not set.
+    /// Managed by the filesystem worker.
+    pub page_compression: Option<Compression>,
+    pub page_compression_level: CompressionLevel,
+
+    /// Number of the first page of the free pages list.
+    /// Managed by the filesystem worker.
+    pub free_page_number: Option<u32>,
+
+    /// Number of the first page of the allocation bitmap.
+    /// Free pages list is not used when it's set.
+    /// Managed by the filesystem worker.
+    pub allocation_bitmap_page_number: Option<u32>
 }
 
 impl Default for FilesystemHeader {
     #[inline]
     fn default() -> Self {
         Self {
+            version: Self::VERSION,
+
             // Better ideas?
             page_size: 1024,
 
             names_checksum: Checksum::Seahash,
             names_compression: None,
-            names_compression_level: CompressionLevel::Auto
+            names_compression_level: CompressionLevel::Auto,
+
+            page_checksum: None,
+
+            page_compression: None,
+            page_compression_level: CompressionLevel::Auto,
+
+            free_page_number: None,
+            allocation_bitmap_page_number: None
         }
     }
 }
 
 impl FilesystemHeader {
-    pub const LENGTH: usize = 10;
+    /// Current version of the filesystem format.
+    pub const VERSION: u16 = 1;
+
+    /// Length of the current version's header.
+    pub const LENGTH: usize = 20;
+
+    /// Length of the version 0 header.
+    pub const LEGACY_LENGTH: usize = 10;
 
     pub const FLAG_NAMES_CHECKSUM_MASK: u16    = 0b00000000_00000011;
     pub const FLAG_NAMES_CHECKSUM_NONE: u16    = 0b00000000_00000000;
@@ -47,8 +100,111 @@ impl FilesystemHeader {
     pub const FLAG_NAMES_COMPRESSION_LEVEL_BALANCED: u16 = 0b00000000_00100000;
     pub const FLAG_NAMES_COMPRESSION_LEVEL_MAX: u16      = 0b00000000_00110000;
 
+    pub const FLAG_PAGE_CHECKSUM_MASK: u16    = 0b00000011_00000000;
+    pub const FLAG_PAGE_CHECKSUM_NONE: u16    = 0b00000000_00000000;
+    pub const FLAG_PAGE_CHECKSUM_SEAHASH: u16 = 0b00000001_00000000;
+    pub const FLAG_PAGE_CHECKSUM_SIPHASH: u16 = 0b00000010_00000000;
+    pub const FLAG_PAGE_CHECKSUM_XXH3: u16    = 0b00000011_00000000;
+
+    pub const FLAG_PAGE_COMPRESSION_MASK: u16   = 0b00001100_00000000;
+    pub const FLAG_PAGE_COMPRESSION_NONE: u16   = 0b00000000_00000000;
+    pub const FLAG_PAGE_COMPRESSION_LZ4: u16    = 0b00000100_00000000;
+    pub const FLAG_PAGE_COMPRESSION_BROTLI: u16 = 0b00001000_00000000;
+    pub const FLAG_PAGE_COMPRESSION_ZSTD: u16   = 0b00001100_00000000;
+
+    pub const FLAG_PAGE_COMPRESSION_LEVEL_MASK: u16     = 0b00110000_00000000;
+    pub const FLAG_PAGE_COMPRESSION_LEVEL_AUTO: u16     = 0b00000000_00000000;
+    pub const FLAG_PAGE_COMPRESSION_LEVEL_FAST: u16     = 0b00010000_00000000;
+    pub const FLAG_PAGE_COMPRESSION_LEVEL_BALANCED: u16 = 0b00100000_00000000;
+    pub const FLAG_PAGE_COMPRESSION_LEVEL_MAX: u16      = 0b00110000_00000000;
+
+    pub const FLAG_HAS_FREE_PAGES: u16        = 0b00000000_01000000;
+    pub const FLAG_HAS_ALLOCATION_BITMAP: u16 = 0b00000000_10000000;
+    pub const FLAG_HAS_VERSION: u16           = 0b10000000_00000000;
+
+    /// Flags supported by the version 0 filesystems.
+    pub const LEGACY_FLAGS_MASK: u16 = 0b00000000_00111111;
+
+    /// Flags supported by the current version filesystems.
+    pub const FLAGS_MASK: u16 = 0b10111111_11111111;
+
+    #[inline]
+    /// Get length of the header.
+    pub const fn length(&self) -> usize {
+        if self.version == 0 {
+            Self::LEGACY_LENGTH
+        } else {
+            Self::LENGTH
+        }
+    }
+
+    #[inline]
+    /// Get length of the page header. Page checksum and
+    /// compressed size are stored only when checksums
+    /// or compression are enabled, respectively. Checksum
+    /// bytes are reserved if only compression is enabled.
+    pub const fn page_header_length(&self) -> usize {
+        if self.page_compression.is_some() {
+            PageHeader::LENGTH
+        } else if self.page_checksum.is_some() {
+            PageHeader::CHECKSUM_LENGTH
+        } else {
+            PageHeader::BASE_LENGTH
+        }
+    }
+
+    /// Verify body checksum of the page stored in the given
+    /// bytes read from the `offset` of the filesystem's IO.
+    ///
+    /// Return `true` if page checksums are disabled or the bytes
+    /// don't cover a whole page with its header, so they can't
+    /// be verified. Can be used as the `MirrorStorageIO` verifier.
+    pub fn verify_page(&self, offset: u64, bytes: &[u8]) -> bool {
+        let Some(checksum) = self.page_checksum else {
+            return true;
+        };
+
+        let page_header_length = self.page_header_length();
+        let page_length = page_header_length as u64 + self.page_size;
+
+        let Some(page_offset) = offset.checked_sub(self.length() as u64) else {
+            return true;
+        };
+
+        if page_offset % page_length != 0 || bytes.len() as u64 != page_length {
+            return true;
+        }
+
+        let mut page_header = [0; PageHeader::LENGTH];
+
+        page_header[..page_header_length].copy_from_slice(&bytes[..page_header_length]);
+
+        let page_header = PageHeader::from_bytes(&page_header);
+
+        let body = &bytes[page_header_length..];
+
+        match (page_header.compressed_size, self.page_compression) {
+            (None, _) => checksum.checksum(body) == page_header.checksum,
+
+            (Some(compressed_size), Some(compression)) if compressed_size as u64 <= self.page_size => {
+                compression.decompress(&body[..compressed_size as usize])
+                    .is_ok_and(|body| checksum.checksum(body) == page_header.checksum)
+            }
+
+            (Some(_), _) => false
+        }
+    }
+
     /// Parse filesystem header from the given bytes slice.
-    pub fn from_bytes(bytes: &[u8; Self::LENGTH]) -> Self {
+    ///
+    /// Return `ErrorKind::Unsupported` error for unknown
+    /// format versions and `ErrorKind::InvalidData` for
+    /// the corrupted headers.
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Self> {
+        if bytes.len() < Self::LEGACY_LENGTH {
+            return Err(Error::new(ErrorKind::InvalidData, "filesystem header is too short"));
+        }
+
         let page_size = u64::from_le_bytes([
             bytes[0], bytes[1], bytes[2], bytes[3],
             bytes[4], bytes[5], bytes[6], bytes[7]
@@ -56,15 +212,54 @@ impl FilesystemHeader {
 
         let flags = u16::from_le_bytes([bytes[8], bytes[9]]);
 
+        let version = if flags & Self::FLAG_HAS_VERSION == Self::FLAG_HAS_VERSION {
+            if bytes.len() < Self::LENGTH {
+                return Err(Error::new(ErrorKind::InvalidData, "filesystem header is too short"));
+            }
+
+            let version = u16::from_le_bytes([bytes[10], bytes[11]]);
+
+            if version == 0 || version > Self::VERSION {
+                return Err(Error::new(ErrorKind::Unsupported, format!("unknown filesystem format version {version}")));
+            }
+
+            version
+        } else {
+            0
+        };
+
+        let flags_mask = if version == 0 {
+            Self::LEGACY_FLAGS_MASK
+        } else {
+            Self::FLAGS_MASK
+        };
+
+        if flags & !flags_mask != 0 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("unknown filesystem header flags 0x{:04x}", flags & !flags_mask)));
+        }
+
         let names_checksum = flags & Self::FLAG_NAMES_CHECKSUM_MASK;
         let names_compression = flags & Self::FLAG_NAMES_COMPRESSION_MASK;
         let names_compression_level = flags & Self::FLAG_NAMES_COMPRESSION_LEVEL_MASK;
+        let page_checksum = flags & Self::FLAG_PAGE_CHECKSUM_MASK;
+        let page_compression = flags & Self::FLAG_PAGE_COMPRESSION_MASK;
+        let page_compression_level = flags & Self::FLAG_PAGE_COMPRESSION_LEVEL_MASK;
 
-        Self {
+        let (free_page_number, allocation_bitmap_page_number) = if version == 0 {
+            (0, 0)
+        } else {
+            (
+                u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+                u32::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19]])
+            )
+        };
+
+        Ok(Self {
+            version,
             page_size,
 
             names_checksum: match names_checksum {
-                Self::FLAG_NAMES_CHECKSUM_NONE => panic!("Failed to read filesystem header : invalid names checksum variant"),
+                Self::FLAG_NAMES_CHECKSUM_NONE => return Err(Error::new(ErrorKind::InvalidData, "invalid names checksum variant")),
 
                 Self::FLAG_NAMES_CHECKSUM_SEAHASH => Checksum::Seahash,
                 Self::FLAG_NAMES_CHECKSUM_SIPHASH => Checksum::Siphash,
@@ -89,13 +284,50 @@ impl FilesystemHeader {
                 Self::FLAG_NAMES_COMPRESSION_LEVEL_MAX      => CompressionLevel::Max,
 
                 _ => unreachable!()
-            }
-        }
+            },
+
+            page_checksum: match page_checksum {
+                Self::FLAG_PAGE_CHECKSUM_NONE    => None,
+                Self::FLAG_PAGE_CHECKSUM_SEAHASH => Some(Checksum::Seahash),
+                Self::FLAG_PAGE_CHECKSUM_SIPHASH => Some(Checksum::Siphash),
+                Self::FLAG_PAGE_CHECKSUM_XXH3    => Some(Checksum::Xxh3),
+
+                _ => unreachable!()
+            },
+
+            page_compression: match page_compression {
+                Self::FLAG_PAGE_COMPRESSION_NONE   => None,
+                Self::FLAG_PAGE_COMPRESSION_LZ4    => Some(Compression::Lz4),
+                Self::FLAG_PAGE_COMPRESSION_BROTLI => Some(Compression::Brotli),
+                Self::FLAG_PAGE_COMPRESSION_ZSTD   => Some(Compression::Zstd),
+
+                _ => unreachable!()
+            },
+
+            page_compression_level: match page_compression_level {
+                Self::FLAG_PAGE_COMPRESSION_LEVEL_AUTO     => CompressionLevel::Auto,
+                Self::FLAG_PAGE_COMPRESSION_LEVEL_FAST     => CompressionLevel::Fast,
+                Self::FLAG_PAGE_COMPRESSION_LEVEL_BALANCED => CompressionLevel::Balanced,
+                Self::FLAG_PAGE_COMPRESSION_LEVEL_MAX      => CompressionLevel::Max,
+
+                _ => unreachable!()
+            },
+
+            free_page_number: (flags & Self::FLAG_HAS_FREE_PAGES == Self::FLAG_HAS_FREE_PAGES)
+                .then_some(free_page_number),
+
+            allocation_bitmap_page_number: (flags & Self::FLAG_HAS_ALLOCATION_BITMAP == Self::FLAG_HAS_ALLOCATION_BITMAP)
+                .then_some(allocation_bitmap_page_number)
+        })
     }
 
-    /// Encode filesystem header into the bytes slice.
-    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
-        let mut bytes = [0; Self::LENGTH];
+    /// Encode filesystem header into the bytes slice
+    /// of the header's length.
+    ///
+    /// Features which are not supported by the version 0
+    /// filesystems are not stored in their headers.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; Self::LENGTH];
 
         bytes[..8].copy_from_slice(&self.page_size.to_le_bytes());
 
@@ -121,8 +353,51 @@ impl FilesystemHeader {
             CompressionLevel::Max      => flags |= Self::FLAG_NAMES_COMPRESSION_LEVEL_MAX
         }
 
+        match self.page_checksum {
+            None                    => flags |= Self::FLAG_PAGE_CHECKSUM_NONE,
+            Some(Checksum::Seahash) => flags |= Self::FLAG_PAGE_CHECKSUM_SEAHASH,
+            Some(Checksum::Siphash) => flags |= Self::FLAG_PAGE_CHECKSUM_SIPHASH,
+            Some(Checksum::Xxh3)    => flags |= Self::FLAG_PAGE_CHECKSUM_XXH3
+        }
+
+        match self.page_compression {
+            None                      => flags |= Self::FLAG_PAGE_COMPRESSION_NONE,
+            Some(Compression::Lz4)    => flags |= Self::FLAG_PAGE_COMPRESSION_LZ4,
+            Some(Compression::Brotli) => flags |= Self::FLAG_PAGE_COMPRESSION_BROTLI,
+            Some(Compression::Zstd)   => flags |= Self::FLAG_PAGE_COMPRESSION_ZSTD
+        }
+
+        match self.page_compression_level {
+            CompressionLevel::Auto     => flags |= Self::FLAG_PAGE_COMPRESSION_LEVEL_AUTO,
+            CompressionLevel::Fast     => flags |= Self::FLAG_PAGE_COMPRESSION_LEVEL_FAST,
+            CompressionLevel::Balanced => flags |= Self::FLAG_PAGE_COMPRESSION_LEVEL_BALANCED,
+            CompressionLevel::Max      => flags |= Self::FLAG_PAGE_COMPRESSION_LEVEL_MAX
+        }
+
+        if let Some(free_page_number) = self.free_page_number {
+            flags |= Self::FLAG_HAS_FREE_PAGES;
+
+            bytes[12..16].copy_from_slice(&free_page_number.to_le_bytes());
+        }
+
+        if let Some(allocation_bitmap_page_number) = self.allocation_bitmap_page_number {
+            flags |= Self::FLAG_HAS_ALLOCATION_BITMAP;
+
+            bytes[16..20].copy_from_slice(&allocation_bitmap_page_number.to_le_bytes());
+        }
+
+        if self.version == 0 {
+            flags &= Self::LEGACY_FLAGS_MASK;
+        } else {
+            flags |= Self::FLAG_HAS_VERSION;
+
+            bytes[10..12].copy_from_slice(&self.version.to_le_bytes());
+        }
+
         bytes[8..10].copy_from_slice(&flags.to_le_bytes());
 
+        bytes.truncate(self.length());
+
         bytes
     }
 }
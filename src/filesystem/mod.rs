@@ -1,4 +1,5 @@
 pub mod checksum;
+pub mod bitmap;
 pub mod compression;
 pub mod tasks;
 pub mod header;
@@ -7,6 +8,7 @@ pub mod entry;
 
 pub mod prelude {
     pub use super::checksum::*;
+    pub use super::bitmap::*;
     pub use super::compression::*;
     pub use super::tasks::prelude::*;
     pub use super::header::*;
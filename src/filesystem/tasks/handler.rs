@@ -40,6 +40,19 @@ impl FilesystemTasksHandler {
         self.send(task, FilesystemTaskPriority::Low)
     }
 
+    /// Stop the scheduler. Scheduled tasks are dropped.
+    pub fn close(&self) -> anyhow::Result<()> {
+        self.sender.send(FilesystemSchedulerTask::Close)?;
+
+        Ok(())
+    }
+
+    #[inline]
+    /// Check if the scheduler was stopped.
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_disconnected()
+    }
+
     /// Poll filesystem task from the scheduler.
     pub fn poll(&self) -> anyhow::Result<FilesystemTask> {
         let (send, recv) = flume::bounded(1);
@@ -48,4 +61,25 @@ impl FilesystemTasksHandler {
 
         Ok(recv.recv()?)
     }
+
+    /// Poll up to `limit` filesystem tasks from the scheduler.
+    ///
+    /// Waits for the first task and takes the rest
+    /// only if they're already scheduled.
+    pub fn poll_many(&self, limit: usize) -> anyhow::Result<Vec<FilesystemTask>> {
+        let mut tasks = vec![self.poll()?];
+
+        if limit > 1 {
+            let (send, recv) = flume::bounded(1);
+
+            self.sender.send(FilesystemSchedulerTask::TryPollTasks {
+                limit: limit - 1,
+                sender: send
+            })?;
+
+            tasks.extend(recv.recv()?);
+        }
+
+        Ok(tasks)
+    }
 }
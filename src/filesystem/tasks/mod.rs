@@ -1,12 +1,14 @@
 #![allow(clippy::module_inception)]
 
 pub mod tasks;
+pub mod stats;
 pub mod handler;
 pub mod scheduler;
 pub mod worker;
 
 pub mod prelude {
     pub use super::tasks::*;
+    pub use super::stats::*;
     pub use super::handler::*;
     pub use super::scheduler::*;
     pub use super::worker::*;
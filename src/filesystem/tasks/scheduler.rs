@@ -1,4 +1,5 @@
-use std::collections::VecDeque;
+use std::collections::{VecDeque, HashSet};
+use std::time::Duration;
 
 use flume::{Sender, Receiver};
 
@@ -28,7 +29,18 @@ pub enum FilesystemSchedulerTask {
     },
 
     /// Poll next task from the scheduler.
-    PollTask(Sender<FilesystemTask>)
+    PollTask(Sender<FilesystemTask>),
+
+    /// Take up to `limit` currently scheduled tasks
+    /// without waiting for new ones.
+    TryPollTasks {
+        limit: usize,
+        sender: Sender<Vec<FilesystemTask>>
+    },
+
+    /// Stop the scheduler. Scheduled tasks are dropped
+    /// and the following polls fail.
+    Close
 }
 
 #[derive(Debug, Clone)]
@@ -39,7 +51,15 @@ pub struct FilesystemTasksScheduler {
 
     tasks_polls: VecDeque<Sender<FilesystemTask>>,
 
-    listener: Receiver<FilesystemSchedulerTask>
+    listener: Receiver<FilesystemSchedulerTask>,
+
+    /// Limits of the read and written bytes of the polled tasks.
+    throttle: Option<StorageThrottle>,
+
+    /// Time until the throttled tasks can be polled.
+    throttled: Option<Duration>,
+
+    closed: bool
 }
 
 impl FilesystemTasksScheduler {
@@ -56,7 +76,12 @@ impl FilesystemTasksScheduler {
 
             tasks_polls: VecDeque::new(),
 
-            listener
+            listener,
+
+            throttle: None,
+            throttled: None,
+
+            closed: false
         };
 
         (scheduler, handler)
@@ -70,29 +95,99 @@ impl FilesystemTasksScheduler {
                 if !self.update() {
                     break;
                 }
+
+                // Wait for the next incoming task instead of spinning
+                // if there's nothing to do right now.
+                if self.is_idle() {
+                    match self.listener.recv() {
+                        Ok(task) => self.handle(task),
+                        Err(_) => break
+                    }
+                }
+
+                // Or until the throttled tasks can be polled.
+                else if let Some(delay) = self.throttled {
+                    match self.listener.recv_timeout(delay) {
+                        Ok(task) => self.handle(task),
+                        Err(flume::RecvTimeoutError::Timeout) => (),
+                        Err(flume::RecvTimeoutError::Disconnected) => break
+                    }
+                }
             }
+
+            // Close the listener before the pending polls so the
+            // worker sees the scheduler closed when its poll fails.
+            let Self { listener, tasks_polls, .. } = self;
+
+            drop(listener);
+            drop(tasks_polls);
         })
     }
 
+    #[inline]
+    /// Limit amount of bytes read and written by the polled tasks.
+    ///
+    /// Tasks which exceed the budget stay in the scheduler, so the
+    /// worker is never blocked by the throttle. Page reads are
+    /// polled before throttled tasks of the same priority if they
+    /// don't read pages modified by them, so throttled writes
+    /// don't delay reads.
+    pub fn set_throttle(&mut self, throttle: Option<StorageThrottle>) {
+        self.throttle = throttle;
+    }
+
+    #[inline]
+    /// Check if scheduler can't do anything until
+    /// it receives new incoming tasks.
+    pub fn is_idle(&self) -> bool {
+        self.tasks_polls.is_empty() || (
+            self.tasks_high.is_empty() &&
+            self.tasks_normal.is_empty() &&
+            self.tasks_low.is_empty()
+        )
+    }
+
+    /// Put incoming scheduler task to the appropriate queue.
+    fn handle(&mut self, task: FilesystemSchedulerTask) {
+        match task {
+            FilesystemSchedulerTask::PushTask { task, priority } => self.push(task, priority),
+            FilesystemSchedulerTask::PollTask(sender) => self.tasks_polls.push_back(sender),
+
+            FilesystemSchedulerTask::TryPollTasks { limit, sender } => {
+                let mut tasks = Vec::new();
+
+                while tasks.len() < limit {
+                    match self.poll() {
+                        Some((task, _)) => tasks.push(task),
+                        None => break
+                    }
+                }
+
+                let _ = sender.send(tasks);
+            }
+
+            FilesystemSchedulerTask::Close => self.closed = true
+        }
+    }
+
     /// Listen for incoming tasks and put them in
     /// appropriate queues using their priority.
     ///
-    /// Return false if all the tasks handlers were closed.
+    /// Return false if the scheduler or all the tasks handlers were closed.
     pub fn update(&mut self) -> bool {
         loop {
             match self.listener.try_recv() {
-                Ok(task) => {
-                    match task {
-                        FilesystemSchedulerTask::PushTask { task, priority } => self.push(task, priority),
-                        FilesystemSchedulerTask::PollTask(sender) => self.tasks_polls.push_back(sender)
-                    }
-                }
+                Ok(task) => self.handle(task),
 
                 Err(flume::TryRecvError::Disconnected) => return false,
                 Err(flume::TryRecvError::Empty) => break
             }
         }
 
+        if self.closed {
+            return false;
+        }
+
         // If somebody requested tasks from the scheduler.
         if !self.tasks_polls.is_empty() {
             let mut last_task = None;
@@ -161,15 +256,192 @@ impl FilesystemTasksScheduler {
 
     /// Try to poll a task from the scheduler.
     pub fn poll(&mut self) -> Option<(FilesystemTask, FilesystemTaskPriority)> {
-        self.tasks_high.pop_front()
-            .map(|task| (task, FilesystemTaskPriority::High))
-            .or_else(|| {
-                self.tasks_normal.pop_front()
-                    .map(|task| (task, FilesystemTaskPriority::Normal))
-            })
-            .or_else(|| {
-                self.tasks_low.pop_front()
-                    .map(|task| (task, FilesystemTaskPriority::Low))
-            })
+        self.throttled = None;
+
+        let Some(throttle) = &self.throttle else {
+            return self.tasks_high.pop_front()
+                .map(|task| (task, FilesystemTaskPriority::High))
+                .or_else(|| {
+                    self.tasks_normal.pop_front()
+                        .map(|task| (task, FilesystemTaskPriority::Normal))
+                })
+                .or_else(|| {
+                    self.tasks_low.pop_front()
+                        .map(|task| (task, FilesystemTaskPriority::Low))
+                });
+        };
+
+        let queues = [
+            (&mut self.tasks_high, FilesystemTaskPriority::High),
+            (&mut self.tasks_normal, FilesystemTaskPriority::Normal),
+            (&mut self.tasks_low, FilesystemTaskPriority::Low)
+        ];
+
+        for (queue, priority) in queues {
+            match Self::poll_throttled(queue, throttle) {
+                Ok(task) => return Some((task, priority)),
+
+                Err(Some(delay)) => {
+                    self.throttled = Some(self.throttled.map_or(delay, |throttled| throttled.min(delay)));
+                }
+
+                Err(None) => ()
+            }
+        }
+
+        None
+    }
+
+    /// Take the first task of the queue which fits the throttle
+    /// budget. Tasks are taken in order, except page reads which
+    /// can be taken before throttled tasks if they don't read
+    /// pages modified by them.
+    ///
+    /// Return time until the budget is refilled if
+    /// there's no task which can be taken now.
+    fn poll_throttled(queue: &mut VecDeque<FilesystemTask>, throttle: &StorageThrottle) -> Result<FilesystemTask, Option<Duration>> {
+        let mut delay = None;
+        let mut modified = HashSet::new();
+
+        for i in 0..queue.len() {
+            let task = &queue[i];
+
+            let (read, written) = task.transferred_bytes();
+
+            if let Some(pages) = task.read_pages() {
+                // Read must wait for the throttled writes of its pages.
+                if pages.iter().any(|page_number| modified.contains(page_number)) {
+                    continue;
+                }
+
+                let read_delay = throttle.try_acquire(StorageThrottle::READ, read);
+
+                if read_delay.is_zero() {
+                    return queue.remove(i).ok_or(delay);
+                }
+
+                // Following tasks can't be taken before this read.
+                return Err(Some(delay.map_or(read_delay, |delay: Duration| delay.min(read_delay))));
+            }
+
+            // Other tasks are taken only in order.
+            if i == 0 {
+                let write_delay = throttle.try_acquire(StorageThrottle::WRITE, written);
+
+                if write_delay.is_zero() {
+                    return queue.pop_front().ok_or(delay);
+                }
+
+                delay = Some(write_delay);
+            }
+
+            // Pages modified by the task can't be read before it.
+            match task.written_pages() {
+                Some(pages) => modified.extend(pages),
+                None => break
+            }
+        }
+
+        Err(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_page(page_number: u32) -> FilesystemTask {
+        FilesystemTask::ReadPage {
+            page_number,
+            offset: 0,
+            length: 100,
+            response_sender: flume::bounded(1).0
+        }
+    }
+
+    fn write_page(page_number: u32) -> FilesystemTask {
+        FilesystemTask::WritePage {
+            page_number,
+            offset: 0,
+            bytes: vec![0; 1000],
+            response_sender: None
+        }
+    }
+
+    fn polled_page(scheduler: &mut FilesystemTasksScheduler) -> Option<u32> {
+        match scheduler.poll()?.0 {
+            FilesystemTask::ReadPage { page_number, .. } |
+            FilesystemTask::WritePage { page_number, .. } => Some(page_number),
+
+            task => panic!("unexpected task {}", task.name())
+        }
+    }
+
+    #[test]
+    fn close() {
+        let (scheduler, handler) = FilesystemTasksScheduler::new();
+
+        let scheduler = scheduler.daemonize();
+
+        handler.send_normal(read_page(0)).unwrap();
+
+        assert!(matches!(handler.poll().unwrap(), FilesystemTask::ReadPage { page_number: 0, .. }));
+
+        handler.send_normal(read_page(1)).unwrap();
+        handler.close().unwrap();
+
+        scheduler.join().unwrap();
+
+        assert!(handler.is_closed());
+        assert!(handler.poll().is_err());
+    }
+
+    #[test]
+    fn throttle() {
+        let (mut scheduler, _handler) = FilesystemTasksScheduler::new();
+
+        let clock = ManualThrottleClock::default();
+
+        scheduler.set_throttle(Some(StorageThrottle::with_clock(Some(100), Some(500), clock.clone())));
+
+        let tasks = [
+            write_page(0), write_page(1), read_page(2), read_page(1),
+            read_page(3), write_page(4), read_page(5)
+        ];
+
+        for task in tasks {
+            scheduler.push(task, FilesystemTaskPriority::Normal);
+        }
+
+        // First write puts the bucket in debt.
+        assert_eq!(polled_page(&mut scheduler), Some(0));
+
+        // Reads bypass throttled writes of other pages.
+        assert_eq!(polled_page(&mut scheduler), Some(2));
+        assert_eq!(polled_page(&mut scheduler), Some(3));
+
+        // Other tasks wait for the budget.
+        assert_eq!(polled_page(&mut scheduler), None);
+        assert_eq!(scheduler.throttled, Some(Duration::from_secs(1)));
+
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(polled_page(&mut scheduler), Some(1));
+        assert_eq!(polled_page(&mut scheduler), Some(1));
+
+        // Both budgets are spent.
+        assert_eq!(polled_page(&mut scheduler), None);
+
+        clock.advance(Duration::from_secs(1));
+
+        // Second write is still paid for.
+        assert_eq!(polled_page(&mut scheduler), Some(5));
+        assert_eq!(polled_page(&mut scheduler), None);
+
+        clock.advance(Duration::from_secs(1));
+
+        assert_eq!(polled_page(&mut scheduler), Some(4));
+        assert_eq!(polled_page(&mut scheduler), None);
+        assert_eq!(scheduler.throttled, None);
     }
 }
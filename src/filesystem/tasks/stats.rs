@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Histogram of latencies with power of two microsecond buckets.
+///
+/// Bucket 0 counts latencies below 1 microsecond, bucket `i`
+/// counts latencies in `[2^(i - 1), 2^i)` microseconds range.
+/// The last bucket counts all the larger latencies.
+pub struct LatencyHistogram {
+    buckets: [u64; Self::BUCKETS]
+}
+
+impl LatencyHistogram {
+    pub const BUCKETS: usize = 32;
+
+    #[inline]
+    pub fn record(&mut self, micros: u64) {
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+
+        self.buckets[std::cmp::min(bucket, Self::BUCKETS - 1)] += 1;
+    }
+
+    #[inline]
+    pub const fn buckets(&self) -> &[u64; Self::BUCKETS] {
+        &self.buckets
+    }
+
+    #[inline]
+    /// Get amount of recorded latencies.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Get upper bound of the given percentile (`0.0..=1.0`)
+    /// of the recorded latencies in microseconds.
+    ///
+    /// Returns `None` if nothing was recorded.
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        let count = self.count();
+
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((count as f64 * percentile.clamp(0.0, 1.0)).ceil() as u64).max(1);
+
+        let mut seen = 0;
+
+        for (i, amount) in self.buckets.iter().enumerate() {
+            seen += amount;
+
+            if seen >= rank {
+                return Some(if i == Self::BUCKETS - 1 { u64::MAX } else { 1 << i });
+            }
+        }
+
+        None
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Execution statistics of a single filesystem task variant.
+pub struct FilesystemTaskStats {
+    /// Amount of executed tasks.
+    pub count: u64,
+
+    /// Total execution time in microseconds.
+    pub micros: u64,
+
+    /// Maximal execution time in microseconds.
+    pub max_micros: u64,
+
+    /// Amount of failed tasks without response sender,
+    /// so their errors couldn't be reported to the caller.
+    pub unreported_errors: u64,
+
+    pub latency: LatencyHistogram
+}
+
+impl FilesystemTaskStats {
+    #[inline]
+    pub fn record(&mut self, micros: u64) {
+        self.count += 1;
+        self.micros += micros;
+        self.max_micros = std::cmp::max(self.max_micros, micros);
+
+        self.latency.record(micros);
+    }
+
+    #[inline]
+    /// Get average execution time in microseconds.
+    pub fn avg_micros(&self) -> u64 {
+        self.micros.checked_div(self.count).unwrap_or_default()
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+/// Execution statistics of the filesystem tasks
+/// grouped by their variant names.
+pub struct FilesystemStats {
+    pub tasks: BTreeMap<&'static str, FilesystemTaskStats>,
+
+    /// The last error of a task without response sender.
+    pub last_unreported_error: Option<String>
+}
+
+impl FilesystemStats {
+    #[inline]
+    /// Record execution time of the task with given name.
+    pub fn record(&mut self, task: &'static str, micros: u64) {
+        self.tasks.entry(task)
+            .or_default()
+            .record(micros);
+    }
+
+    /// Remember error of the task with given name
+    /// which doesn't have a response sender.
+    pub fn record_unreported_error(&mut self, task: &'static str, error: &std::io::Error) {
+        self.tasks.entry(task)
+            .or_default()
+            .unreported_errors += 1;
+
+        self.last_unreported_error = Some(format!("{task} task failed : {error}"));
+    }
+
+    #[inline]
+    /// Get statistics of the task variant with given name.
+    pub fn task(&self, task: &str) -> Option<&FilesystemTaskStats> {
+        self.tasks.get(task)
+    }
+
+    #[inline]
+    /// Get total amount of executed tasks.
+    pub fn count(&self) -> u64 {
+        self.tasks.values().map(|task| task.count).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = LatencyHistogram::default();
+
+        assert_eq!(histogram.percentile(0.5), None);
+
+        for micros in [0, 1, 3, 3, 100, 5000] {
+            histogram.record(micros);
+        }
+
+        histogram.record(u64::MAX);
+
+        assert_eq!(histogram.count(), 7);
+        assert_eq!(&histogram.buckets()[..4], &[1, 1, 2, 0]);
+
+        assert_eq!(histogram.percentile(0.0), Some(1));
+        assert_eq!(histogram.percentile(0.5), Some(4));
+        assert_eq!(histogram.percentile(0.7), Some(128));
+        assert_eq!(histogram.percentile(1.0), Some(u64::MAX));
+
+        let mut stats = FilesystemStats::default();
+
+        stats.record("ReadPage", 10);
+        stats.record("ReadPage", 30);
+        stats.record("Sync", 1000);
+
+        assert_eq!(stats.count(), 3);
+        assert_eq!(stats.task("ReadPage").unwrap().avg_micros(), 20);
+        assert_eq!(stats.task("ReadPage").unwrap().max_micros, 30);
+        assert_eq!(stats.task("WritePage"), None);
+
+        stats.record_unreported_error("WritePage", &std::io::Error::other("disk is full"));
+
+        assert_eq!(stats.task("WritePage").unwrap().count, 0);
+        assert_eq!(stats.task("WritePage").unwrap().unreported_errors, 1);
+        assert_eq!(stats.last_unreported_error.as_deref(), Some("WritePage task failed : disk is full"));
+    }
+}
@@ -4,18 +4,56 @@ use crate::prelude::*;
 
 #[derive(Debug, Clone)]
 /// Low-level filesystem operation task.
+///
+/// Tasks which perform IO operations return their results
+/// wrapped in `std::io::Result` so storage failures are
+/// reported back to the caller. When an optional response
+/// sender is not given - IO error is recorded in the
+/// filesystem stats and the worker keeps running.
 pub enum FilesystemTask {
     ReadFilesystemHeader {
         response_sender: Sender<FilesystemHeader>
     },
 
+    /// Write filesystem header. Format version, free pages list,
+    /// allocation bitmap, page checksums and compression are
+    /// managed by the filesystem, and headers which change them
+    /// are rejected with `ErrorKind::InvalidInput` error.
     WriteFilesystemHeader {
-        header: FilesystemHeader
+        header: FilesystemHeader,
+        response_sender: Option<Sender<std::io::Result<()>>>
     },
 
-    /// Create new filesystem page. It will be assigned to the next
-    /// available number, so if the last page has number N - the new
-    /// one will have number N + 1.
+    /// Write all the pending changes of the storage IO
+    /// (e.g. dirty write-back buffers) to the disk.
+    Sync {
+        response_sender: Option<Sender<std::io::Result<()>>>
+    },
+
+    /// Read usage statistics of the pages cache.
+    ReadPagesCacheStats {
+        response_sender: Sender<PagesCacheStats>
+    },
+
+    /// Read execution statistics of the filesystem tasks.
+    ReadStats {
+        response_sender: Sender<FilesystemStats>
+    },
+
+    /// Read allocation bitmap of the filesystem.
+    /// `None` is returned if it's not enabled.
+    ReadAllocationBitmap {
+        response_sender: Sender<Option<AllocationBitmap>>
+    },
+
+    /// Create new filesystem page. A free page is reused if there's
+    /// any: the first unused page of the allocation bitmap if it's
+    /// enabled, or the first page of the free pages list otherwise.
+    /// If there are no free pages - the new one will be assigned to
+    /// the next available number, so if the last page has number
+    /// N - the new one will have number N + 1.
+    ///
+    /// Body of the created page is filled with zeros.
     CreatePage {
         /// Number of the parent page to link the new one with.
         /// Parent page will not be linked with this one so you have
@@ -26,7 +64,19 @@ pub enum FilesystemTask {
         parent_page_number: Option<u32>,
 
         /// Where to send the created page.
-        response_sender: Sender<Page>
+        response_sender: Sender<std::io::Result<Page>>
+    },
+
+    /// Return page to the free pages list, or mark it as unused
+    /// in the allocation bitmap, so it's reused by the following
+    /// `CreatePage` tasks.
+    ///
+    /// Page is not unlinked from its neighbours, so their
+    /// headers must be updated by the caller. Freeing a page
+    /// which is already free returns `ErrorKind::InvalidInput`.
+    FreePage {
+        page_number: u32,
+        response_sender: Option<Sender<std::io::Result<()>>>
     },
 
     /// Link `page_number` page with the `next_page_number` page
@@ -36,17 +86,21 @@ pub enum FilesystemTask {
     /// of the `page_number` page equal to the `next_page_number` value.
     LinkPageForward {
         page_number: u32,
-        next_page_number: u32
+        next_page_number: u32,
+        response_sender: Option<Sender<std::io::Result<()>>>
     },
 
     ReadPageHeader {
         page_number: u32,
-        response_sender: Sender<PageHeader>
+        response_sender: Sender<std::io::Result<PageHeader>>
     },
 
+    /// Write header of the page. Checksum of the given
+    /// header is ignored if page checksums are enabled.
     WritePageHeader {
         page_number: u32,
-        header: PageHeader
+        header: PageHeader,
+        response_sender: Option<Sender<std::io::Result<()>>>
     },
 
     /// Read bytes from the page's body.
@@ -59,11 +113,15 @@ pub enum FilesystemTask {
     ///
     /// If requested length + offset is larger than the body
     /// size - only available bytes will be returned.
+    ///
+    /// If page checksums are enabled - the whole body is
+    /// verified and `ErrorKind::InvalidData` is returned
+    /// when it's corrupted.
     ReadPage {
         page_number: u32,
         offset: u64,
         length: u64,
-        response_sender: Sender<Vec<u8>>
+        response_sender: Sender<std::io::Result<Vec<u8>>>
     },
 
     /// Write bytes to the page's body.
@@ -77,6 +135,116 @@ pub enum FilesystemTask {
         page_number: u32,
         offset: u64,
         bytes: Vec<u8>,
-        response_sender: Option<Sender<Vec<u8>>>
+        response_sender: Option<Sender<std::io::Result<Vec<u8>>>>
+    },
+
+    /// Read bytes from bodies of multiple pages using
+    /// a single vectored IO operation.
+    ///
+    /// Every `(page_number, offset, length)` entry is read
+    /// the same way as with the `ReadPage` task. Results
+    /// are returned in the same order.
+    ReadPages {
+        pages: Vec<(u32, u64, u64)>,
+        response_sender: Sender<std::io::Result<Vec<Vec<u8>>>>
+    },
+
+    /// Write bytes to bodies of multiple pages using
+    /// a single vectored IO operation.
+    ///
+    /// Every `(page_number, offset, bytes)` entry is written
+    /// in the given order. Bytes which don't fit the page's
+    /// body are ignored.
+    WritePages {
+        pages: Vec<(u32, u64, Vec<u8>)>,
+        response_sender: Option<Sender<std::io::Result<()>>>
+    }
+}
+
+impl FilesystemTask {
+    /// Get name of the task variant.
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::ReadFilesystemHeader { .. }  => "ReadFilesystemHeader",
+            Self::WriteFilesystemHeader { .. } => "WriteFilesystemHeader",
+            Self::Sync { .. }                  => "Sync",
+            Self::ReadPagesCacheStats { .. }   => "ReadPagesCacheStats",
+            Self::ReadStats { .. }             => "ReadStats",
+            Self::ReadAllocationBitmap { .. }  => "ReadAllocationBitmap",
+            Self::CreatePage { .. }            => "CreatePage",
+            Self::FreePage { .. }              => "FreePage",
+            Self::LinkPageForward { .. }       => "LinkPageForward",
+            Self::ReadPageHeader { .. }        => "ReadPageHeader",
+            Self::WritePageHeader { .. }       => "WritePageHeader",
+            Self::ReadPage { .. }              => "ReadPage",
+            Self::WritePage { .. }             => "WritePage",
+            Self::ReadPages { .. }             => "ReadPages",
+            Self::WritePages { .. }            => "WritePages"
+        }
+    }
+
+    /// Get amount of bytes read and written by the task.
+    ///
+    /// Only bodies of the pages are counted, so tasks which
+    /// transfer headers or pages of unknown size (e.g. `CreatePage`)
+    /// return zeros.
+    pub fn transferred_bytes(&self) -> (u64, u64) {
+        match self {
+            Self::ReadPage { length, .. } => (*length, 0),
+            Self::WritePage { bytes, .. } => (0, bytes.len() as u64),
+
+            Self::ReadPages { pages, .. } => {
+                (pages.iter().map(|(_, _, length)| *length).sum(), 0)
+            }
+
+            Self::WritePages { pages, .. } => {
+                (0, pages.iter().map(|(_, _, bytes)| bytes.len() as u64).sum())
+            }
+
+            _ => (0, 0)
+        }
+    }
+
+    /// Get numbers of the pages read by the task,
+    /// or `None` if it doesn't read pages.
+    pub fn read_pages(&self) -> Option<Vec<u32>> {
+        match self {
+            Self::ReadPageHeader { page_number, .. } |
+            Self::ReadPage { page_number, .. } => Some(vec![*page_number]),
+
+            Self::ReadPages { pages, .. } => {
+                Some(pages.iter().map(|(page_number, _, _)| *page_number).collect())
+            }
+
+            _ => None
+        }
+    }
+
+    /// Get numbers of the pages modified by the task, or `None` if
+    /// they're not known before the task is executed or the task
+    /// modifies the filesystem itself (header, free pages, etc.).
+    pub fn written_pages(&self) -> Option<Vec<u32>> {
+        match self {
+            Self::ReadFilesystemHeader { .. } |
+            Self::ReadPagesCacheStats { .. } |
+            Self::ReadStats { .. } |
+            Self::ReadAllocationBitmap { .. } |
+            Self::ReadPageHeader { .. } |
+            Self::ReadPage { .. } |
+            Self::ReadPages { .. } => Some(vec![]),
+
+            Self::LinkPageForward { page_number, .. } |
+            Self::WritePageHeader { page_number, .. } |
+            Self::WritePage { page_number, .. } => Some(vec![*page_number]),
+
+            Self::WritePages { pages, .. } => {
+                Some(pages.iter().map(|(page_number, _, _)| *page_number).collect())
+            }
+
+            Self::WriteFilesystemHeader { .. } |
+            Self::Sync { .. } |
+            Self::CreatePage { .. } |
+            Self::FreePage { .. } => None
+        }
     }
 }
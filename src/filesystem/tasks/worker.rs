@@ -1,3 +1,9 @@
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind};
+use std::time::Instant;
+
+use flume::Sender;
+
 use crate::prelude::*;
 
 #[derive(Debug, Clone)]
@@ -8,22 +14,60 @@ pub struct FilesystemWorker<T> {
     handler: FilesystemTasksHandler,
 
     /// Hot cache of the filesystem header.
-    header: FilesystemHeader
+    header: FilesystemHeader,
+
+    /// Cache of the recently used pages.
+    pages: PagesCache,
+
+    /// Reject all the tasks which modify the IO.
+    read_only: bool,
+
+    /// Maximal amount of tasks polled from the scheduler at once.
+    batch_size: usize,
+
+    /// Execution statistics of the tasks.
+    stats: FilesystemStats,
+
+    /// Pages allocation bitmap if it's enabled.
+    bitmap: Option<AllocationBitmap>
+}
+
+#[derive(Debug)]
+/// Page IO task submitted to the storage as a part of a batch.
+enum BatchedTask {
+    Read(Sender<std::io::Result<Vec<u8>>>),
+
+    Write {
+        /// Bytes which didn't fit the page.
+        remaining: Vec<u8>,
+        response_sender: Option<Sender<std::io::Result<Vec<u8>>>>
+    }
 }
 
 impl<T: StorageIO> FilesystemWorker<T> {
-    pub fn new(mut io: T, scheduler: FilesystemTasksScheduler, handler: FilesystemTasksHandler) -> Self {
-        let mut header = [0; FilesystemHeader::LENGTH];
+    pub fn new(mut io: T, scheduler: FilesystemTasksScheduler, handler: FilesystemTasksHandler) -> std::io::Result<Self> {
+        let length = std::cmp::min(io.len()?, FilesystemHeader::LENGTH as u64);
 
-        header.copy_from_slice(&io.read(0, FilesystemHeader::LENGTH));
+        let header = FilesystemHeader::from_bytes(&io.read(0, length as usize)?)?;
 
-        Self {
+        let mut worker = Self {
             io,
             scheduler: Some(scheduler),
             handler,
 
-            header: FilesystemHeader::from_bytes(&header)
-        }
+            pages: PagesCache::new(PagesCacheLimit::default(), header.page_size),
+            header,
+
+            read_only: false,
+            batch_size: 1,
+
+            stats: FilesystemStats::default(),
+            bitmap: None
+        };
+
+        worker.load_allocation_bitmap()?;
+
+        Ok(worker)
     }
 
     #[inline]
@@ -31,9 +75,874 @@ impl<T: StorageIO> FilesystemWorker<T> {
         &self.handler
     }
 
+    #[inline]
+    /// Change limit of the pages cache. Cache is disabled by default.
+    pub fn set_pages_cache_limit(&mut self, limit: PagesCacheLimit) {
+        self.pages.set_limit(limit);
+    }
+
+    #[inline]
+    pub const fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    #[inline]
+    /// Reject all the tasks which modify the IO
+    /// with `ErrorKind::ReadOnlyFilesystem` error.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    #[inline]
+    pub const fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    #[inline]
+    /// Change maximal amount of tasks polled from the scheduler
+    /// at once. Consecutive page reads and writes of the polled
+    /// tasks are submitted to the IO as a single batch (see
+    /// `StorageIO::submit`) when the pages cache is disabled.
+    ///
+    /// Tasks are polled one by one by default.
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size.max(1);
+    }
+
+    #[inline]
+    /// Limit amount of bytes read and written by the tasks
+    /// (see `FilesystemTasksScheduler::set_throttle`).
+    pub fn set_throttle(&mut self, throttle: Option<StorageThrottle>) {
+        if let Some(scheduler) = &mut self.scheduler {
+            scheduler.set_throttle(throttle);
+        }
+    }
+
+    #[inline]
+    /// Get execution statistics of the tasks.
+    pub const fn stats(&self) -> &FilesystemStats {
+        &self.stats
+    }
+
+    #[inline]
+    /// Get position of the page's header in the IO.
+    fn page_pos(&self, page_number: u32) -> u64 {
+        self.header.length() as u64 + page_number as u64 * (self.header.page_header_length() as u64 + self.header.page_size)
+    }
+
+    #[inline]
+    /// Get position of the page's body in the IO.
+    fn body_pos(&self, page_number: u32) -> u64 {
+        self.page_pos(page_number) + self.header.page_header_length() as u64
+    }
+
+    #[inline]
+    /// Encode page header into the bytes stored in the IO.
+    fn encode_page_header(&self, header: &PageHeader) -> Vec<u8> {
+        header.to_bytes()[..self.header.page_header_length()].to_vec()
+    }
+
+    #[inline]
+    /// Decode page header from the bytes stored in the IO.
+    /// Fields which are not stored are zeroed.
+    fn decode_page_header(&self, bytes: &[u8]) -> PageHeader {
+        let mut page_header = [0; PageHeader::LENGTH];
+
+        let length = self.header.page_header_length();
+
+        page_header[..length].copy_from_slice(&bytes[..length]);
+
+        PageHeader::from_bytes(&page_header)
+    }
+
+    #[inline]
+    /// Return error if the filesystem format version
+    /// doesn't support the given feature.
+    fn ensure_versioned(&self, feature: &str) -> std::io::Result<()> {
+        if self.header.version == 0 {
+            return Err(Error::new(ErrorKind::Unsupported, format!("{feature} is not supported by the filesystem format version 0")));
+        }
+
+        Ok(())
+    }
+
+    /// Send result of the task to the response sender if it's
+    /// given, or record its error in the stats otherwise, so
+    /// the worker keeps running.
+    fn respond<R>(&mut self, task: &'static str, response_sender: Option<Sender<std::io::Result<R>>>, result: std::io::Result<R>) {
+        match response_sender {
+            Some(response_sender) => {
+                let _ = response_sender.send(result);
+            }
+
+            None => {
+                if let Err(err) = result {
+                    self.stats.record_unreported_error(task, &err);
+                }
+            }
+        }
+    }
+
+    #[inline]
+    /// Return error if the worker is in read-only mode.
+    fn ensure_writable(&self, task: &str) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(Error::new(ErrorKind::ReadOnlyFilesystem, format!("{task} task is rejected in read-only mode")));
+        }
+
+        Ok(())
+    }
+
+    /// Get amount of pages stored in the IO.
+    fn total_pages(&mut self) -> std::io::Result<u64> {
+        let len = self.io.len()?;
+
+        if len <= self.header.length() as u64 {
+            return Ok(0);
+        }
+
+        Ok((len - self.header.length() as u64) / (self.header.page_header_length() as u64 + self.header.page_size))
+    }
+
+    /// Append new page with given header and
+    /// zeroed body to the end of the IO.
+    fn append_page(&mut self, header: PageHeader) -> std::io::Result<u32> {
+        let page_number = self.total_pages()?;
+
+        let body = vec![0; self.header.page_size as usize];
+
+        let header = PageHeader {
+            checksum: self.body_checksum(&body),
+            compressed_size: None,
+            ..header
+        };
+
+        self.io.append(self.encode_page_header(&header))?;
+        self.io.append(&body)?;
+
+        if let Some(bitmap) = &mut self.bitmap {
+            bitmap.set_total_pages(page_number + 1);
+        }
+
+        // Page could be cached before it was created
+        // if somebody tried to read it.
+        self.pages.insert(page_number as u32, CachedPage {
+            header,
+            body
+        });
+
+        Ok(page_number as u32)
+    }
+
+    /// Create new page, reusing a free page if there's any.
+    fn create_page(&mut self, parent_page_number: Option<u32>) -> std::io::Result<Page> {
+        self.ensure_writable("CreatePage")?;
+
+        let page_header = PageHeader {
+            prev_page_number: parent_page_number.unwrap_or_default(),
+            next_page_number: 0,
+
+            has_prev: parent_page_number.is_some(),
+            has_next: false,
+            is_free: false,
+            checksum: 0,
+            compressed_size: None,
+
+            has_book_header: false
+        };
+
+        let free_page_number = if self.bitmap.is_some() {
+            self.pop_bitmap_page()?
+        } else {
+            self.pop_free_page()?
+        };
+
+        let page_number = match free_page_number {
+            Some(page_number) => {
+                self.write_whole_page(page_number, page_header, vec![0; self.header.page_size as usize])?;
+
+                page_number
+            }
+
+            None if self.bitmap.is_some() => {
+                self.reserve_bitmap_capacity()?;
+
+                let page_number = self.append_page(page_header)?;
+
+                self.write_allocation_bit(page_number, true)?;
+
+                page_number
+            }
+
+            None => self.append_page(page_header)?
+        };
+
+        Ok(Page::new(page_number, self.handler.clone()))
+    }
+
+    /// Return page to the free pages list or mark
+    /// it as unused in the allocation bitmap.
+    fn free_page(&mut self, page_number: u32) -> std::io::Result<()> {
+        self.ensure_writable("FreePage")?;
+        self.ensure_versioned("free pages list")?;
+
+        if self.page_pos(page_number) >= self.io.len()? {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("page 0x{page_number:08x} doesn't exist")));
+        }
+
+        let is_free = match &self.bitmap {
+            Some(bitmap) if bitmap.pages().contains(&page_number) => {
+                return Err(Error::new(ErrorKind::InvalidInput, format!("page 0x{page_number:08x} stores allocation bitmap")));
+            }
+
+            Some(bitmap) => !bitmap.is_allocated(page_number),
+            None => self.read_page_header(page_number)?.is_free
+        };
+
+        if is_free {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("page 0x{page_number:08x} is already free")));
+        }
+
+        // Free pages list is not used with the allocation bitmap.
+        let free_page_number = self.header.free_page_number;
+
+        self.write_page_header(page_number, PageHeader {
+            prev_page_number: 0,
+            next_page_number: free_page_number.unwrap_or_default(),
+
+            has_prev: false,
+            has_next: free_page_number.is_some(),
+            is_free: true,
+            checksum: 0,
+            compressed_size: None,
+
+            has_book_header: false
+        })?;
+
+        if self.bitmap.is_some() {
+            return self.write_allocation_bit(page_number, false);
+        }
+
+        self.write_free_page_number(Some(page_number))
+    }
+
+    /// Pop the first page of the free pages list.
+    fn pop_free_page(&mut self) -> std::io::Result<Option<u32>> {
+        let Some(page_number) = self.header.free_page_number else {
+            return Ok(None);
+        };
+
+        let free_header = self.read_page_header(page_number)?;
+
+        if !free_header.is_free {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("page 0x{page_number:08x} from the free pages list is not free")
+            ));
+        }
+
+        // Pop the page from the list before using it, so it's
+        // leaked instead of being used twice after a crash.
+        self.write_free_page_number(free_header.has_next.then_some(free_header.next_page_number))?;
+
+        Ok(Some(page_number))
+    }
+
+    /// Update head of the free pages list in the filesystem header.
+    fn write_free_page_number(&mut self, free_page_number: Option<u32>) -> std::io::Result<()> {
+        let header = FilesystemHeader {
+            free_page_number,
+            ..self.header
+        };
+
+        self.io.write(0, header.to_bytes())?;
+
+        self.header = header;
+
+        Ok(())
+    }
+
+    /// Find the first unused page in the allocation
+    /// bitmap and mark it as used.
+    fn pop_bitmap_page(&mut self) -> std::io::Result<Option<u32>> {
+        let Some(page_number) = self.bitmap.as_ref().and_then(AllocationBitmap::first_free) else {
+            return Ok(None);
+        };
+
+        self.write_allocation_bit(page_number, true)?;
+
+        Ok(Some(page_number))
+    }
+
+    /// Change allocation status of the page in the allocation
+    /// bitmap and write the changed byte to the IO.
+    fn write_allocation_bit(&mut self, page_number: u32, allocated: bool) -> std::io::Result<()> {
+        let Some(bitmap) = &mut self.bitmap else {
+            return Ok(());
+        };
+
+        let i = bitmap.set(page_number, allocated);
+
+        let byte = bitmap.as_bytes()[i];
+
+        let bitmap_page_number = bitmap.pages()[i / self.header.page_size as usize];
+        let offset = i % self.header.page_size as usize;
+
+        self.write_page(bitmap_page_number, offset as u64, vec![byte])?;
+
+        Ok(())
+    }
+
+    /// Append new page to the allocation bitmap if it
+    /// can't store the next appended page.
+    fn reserve_bitmap_capacity(&mut self) -> std::io::Result<()> {
+        let Some(bitmap) = &self.bitmap else {
+            return Ok(());
+        };
+
+        if bitmap.total_pages() < bitmap.capacity() {
+            return Ok(());
+        }
+
+        let last_page_number = *bitmap.pages().last()
+            .expect("Allocation bitmap must have at least one page");
+
+        let page_number = self.append_page(PageHeader {
+            prev_page_number: last_page_number,
+            next_page_number: 0,
+
+            has_prev: true,
+            has_next: false,
+            is_free: false,
+            checksum: 0,
+            compressed_size: None,
+
+            has_book_header: false
+        })?;
+
+        if let Some(bitmap) = &mut self.bitmap {
+            bitmap.push_page(page_number, self.header.page_size);
+        }
+
+        self.write_allocation_bit(page_number, true)?;
+
+        self.link_page_forward(last_page_number, page_number)
+    }
+
+    /// Read allocation bitmap from the IO if it's
+    /// referenced by the filesystem header.
+    fn load_allocation_bitmap(&mut self) -> std::io::Result<()> {
+        let Some(mut page_number) = self.header.allocation_bitmap_page_number else {
+            return Ok(());
+        };
+
+        let total_pages = self.total_pages()?;
+
+        let mut pages = Vec::new();
+        let mut bits = Vec::new();
+
+        loop {
+            if pages.len() as u64 >= total_pages {
+                return Err(Error::new(ErrorKind::InvalidData, "allocation bitmap pages chain is looped"));
+            }
+
+            let (page_header, body) = self.read_whole_page(page_number)?;
+
+            pages.push(page_number);
+            bits.extend(body);
+
+            if !page_header.has_next {
+                break;
+            }
+
+            page_number = page_header.next_page_number;
+        }
+
+        self.bitmap = Some(AllocationBitmap::new(pages, bits, total_pages));
+
+        Ok(())
+    }
+
+    /// Create allocation bitmap of the filesystem if it
+    /// doesn't exist yet and use it instead of the free
+    /// pages list.
+    ///
+    /// All the existing pages except the ones from the free
+    /// pages list are marked as used.
+    pub fn enable_allocation_bitmap(&mut self) -> std::io::Result<()> {
+        if self.bitmap.is_some() {
+            return Ok(());
+        }
+
+        if self.read_only {
+            return Err(Error::new(ErrorKind::ReadOnlyFilesystem, "can't create allocation bitmap in read-only mode"));
+        }
+
+        self.ensure_versioned("allocation bitmap")?;
+
+        let total_pages = self.total_pages()?;
+        let page_bits = self.header.page_size * 8;
+
+        // Bitmap must store bits of its own pages.
+        let bitmap_pages = total_pages.div_ceil(page_bits - 1).max(1);
+
+        let mut bitmap = AllocationBitmap::new(vec![], vec![], total_pages + bitmap_pages);
+
+        for i in 0..bitmap_pages {
+            bitmap.push_page((total_pages + i) as u32, self.header.page_size);
+        }
+
+        for page_number in 0..total_pages + bitmap_pages {
+            bitmap.set(page_number as u32, true);
+        }
+
+        let mut free_page_number = self.header.free_page_number;
+
+        while let Some(page_number) = free_page_number {
+            if !bitmap.is_allocated(page_number) {
+                return Err(Error::new(ErrorKind::InvalidData, "free pages list is looped"));
+            }
+
+            bitmap.set(page_number, false);
+
+            let page_header = self.read_page_header(page_number)?;
+
+            free_page_number = page_header.has_next.then_some(page_header.next_page_number);
+        }
+
+        let page_size = self.header.page_size as usize;
+
+        for (i, page_number) in bitmap.pages().iter().enumerate() {
+            let has_prev = i > 0;
+            let has_next = i + 1 < bitmap.pages().len();
+
+            let page_header = PageHeader {
+                prev_page_number: if has_prev { page_number - 1 } else { 0 },
+                next_page_number: if has_next { page_number + 1 } else { 0 },
+
+                has_prev,
+                has_next,
+                is_free: false,
+                checksum: self.body_checksum(&bitmap.as_bytes()[i * page_size..(i + 1) * page_size]),
+                compressed_size: None,
+
+                has_book_header: false
+            };
+
+            self.io.append(self.encode_page_header(&page_header))?;
+            self.io.append(&bitmap.as_bytes()[i * page_size..(i + 1) * page_size])?;
+        }
+
+        // Bitmap is used only after the header is updated.
+        let header = FilesystemHeader {
+            free_page_number: None,
+            allocation_bitmap_page_number: Some(total_pages as u32),
+            ..self.header
+        };
+
+        self.io.write(0, header.to_bytes())?;
+
+        self.header = header;
+        self.bitmap = Some(bitmap);
+
+        Ok(())
+    }
+
+    #[inline]
+    /// Check if pages' bodies are checksummed or compressed,
+    /// so they must be read and written entirely.
+    const fn whole_pages(&self) -> bool {
+        self.header.page_checksum.is_some() || self.header.page_compression.is_some()
+    }
+
+    #[inline]
+    /// Calculate checksum of the page's body if
+    /// page checksums are enabled, or return zero.
+    fn body_checksum(&self, body: &[u8]) -> u64 {
+        self.header.page_checksum
+            .map(|checksum| checksum.checksum(body))
+            .unwrap_or_default()
+    }
+
+    /// Read header and body of the page from the IO,
+    /// decompressing the body and verifying its checksum
+    /// if they're enabled.
+    fn read_whole_page(&mut self, page_number: u32) -> std::io::Result<(PageHeader, Vec<u8>)> {
+        let page_header_length = self.header.page_header_length();
+
+        let mut page = self.io.read(self.page_pos(page_number), page_header_length + self.header.page_size as usize)?;
+
+        let page_header = self.decode_page_header(&page);
+
+        page.drain(..page_header_length);
+
+        if let Some(compressed_size) = page_header.compressed_size {
+            let corrupted = |reason: &str| Error::new(
+                ErrorKind::InvalidData,
+                format!("page 0x{page_number:08x} is corrupted : {reason}")
+            );
+
+            let Some(compression) = self.header.page_compression else {
+                return Err(corrupted("page is compressed but page compression is disabled"));
+            };
+
+            if compressed_size as u64 > self.header.page_size {
+                return Err(corrupted("compressed body is larger than the page"));
+            }
+
+            page.truncate(compressed_size as usize);
+
+            page = compression.decompress(&page)
+                .map_err(|err| corrupted(&format!("failed to decompress body : {err}")))?;
+
+            if page.len() as u64 != self.header.page_size {
+                return Err(corrupted("decompressed body has invalid size"));
+            }
+        }
+
+        if self.header.page_checksum.is_some() && self.body_checksum(&page) != page_header.checksum {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("page 0x{page_number:08x} is corrupted : body checksum mismatch")
+            ));
+        }
+
+        Ok((page_header, page))
+    }
+
+    /// Write header and body of the page to the IO
+    /// at once, updating the body's checksum.
+    ///
+    /// If page compression is enabled and the compressed body
+    /// is smaller than the page, it's stored instead of the
+    /// original one and the rest of the page is discarded.
+    fn write_whole_page(&mut self, page_number: u32, header: PageHeader, body: Vec<u8>) -> std::io::Result<()> {
+        let compressed = match self.header.page_compression {
+            Some(compression) => Some(compression.compress(&body, self.header.page_compression_level)?)
+                .filter(|compressed| compressed.len() < body.len()),
+
+            None => None
+        };
+
+        let header = PageHeader {
+            checksum: self.body_checksum(&body),
+            compressed_size: compressed.as_ref().map(|compressed| compressed.len() as u32),
+            ..header
+        };
+
+        let stored = compressed.as_deref().unwrap_or(&body);
+
+        let mut page = Vec::with_capacity(self.header.page_header_length() + stored.len());
+
+        page.extend_from_slice(&self.encode_page_header(&header));
+        page.extend_from_slice(stored);
+
+        let page_pos = self.page_pos(page_number);
+
+        self.io.write(page_pos, &page)?;
+
+        if stored.len() < body.len() {
+            self.io.discard(page_pos + page.len() as u64, (body.len() - stored.len()) as u64)?;
+        }
+
+        self.pages.insert(page_number, CachedPage {
+            header,
+            body
+        });
+
+        Ok(())
+    }
+
+    /// Store checksums of the pages' bodies using given
+    /// algorithm, and verify them on every page read.
+    ///
+    /// Checksums can be enabled only for filesystems
+    /// without pages.
+    pub fn enable_page_checksums(&mut self, checksum: Checksum) -> std::io::Result<()> {
+        if self.header.page_checksum == Some(checksum) {
+            return Ok(());
+        }
+
+        if self.read_only {
+            return Err(Error::new(ErrorKind::ReadOnlyFilesystem, "can't enable page checksums in read-only mode"));
+        }
+
+        self.ensure_versioned("page checksums")?;
+
+        if self.total_pages()? > 0 {
+            return Err(Error::new(ErrorKind::Unsupported, "page checksums can't be changed for filesystem with pages"));
+        }
+
+        let header = FilesystemHeader {
+            page_checksum: Some(checksum),
+            ..self.header
+        };
+
+        self.io.write(0, header.to_bytes())?;
+
+        self.header = header;
+
+        Ok(())
+    }
+
+    /// Store pages' bodies compressed with given algorithm
+    /// and level if it reduces their size.
+    ///
+    /// Compression algorithm can be changed only for
+    /// filesystems without pages, while its level can be
+    /// changed at any time and is used for the following
+    /// page writes.
+    pub fn enable_page_compression(&mut self, compression: Compression, level: CompressionLevel) -> std::io::Result<()> {
+        if self.header.page_compression == Some(compression) && self.header.page_compression_level == level {
+            return Ok(());
+        }
+
+        if self.read_only {
+            return Err(Error::new(ErrorKind::ReadOnlyFilesystem, "can't enable page compression in read-only mode"));
+        }
+
+        self.ensure_versioned("page compression")?;
+
+        if self.header.page_compression != Some(compression) && self.total_pages()? > 0 {
+            return Err(Error::new(ErrorKind::Unsupported, "page compression can't be changed for filesystem with pages"));
+        }
+
+        let header = FilesystemHeader {
+            page_compression: Some(compression),
+            page_compression_level: level,
+            ..self.header
+        };
+
+        self.io.write(0, header.to_bytes())?;
+
+        self.header = header;
+
+        Ok(())
+    }
+
+    /// Read header and body of the page from the pages
+    /// cache if it's enabled, or from the IO otherwise.
+    fn read_whole_page_cached(&mut self, page_number: u32) -> std::io::Result<(PageHeader, Vec<u8>)> {
+        if self.pages.is_enabled() {
+            let page = self.read_cached_page(page_number)?;
+
+            return Ok((page.header, page.body.clone()));
+        }
+
+        self.read_whole_page(page_number)
+    }
+
+    /// Read the whole page from the pages cache,
+    /// or from the IO and put it to the cache.
+    fn read_cached_page(&mut self, page_number: u32) -> std::io::Result<&CachedPage> {
+        if self.pages.get(page_number).is_none() {
+            let (header, body) = self.read_whole_page(page_number)?;
+
+            self.pages.insert(page_number, CachedPage {
+                header,
+                body
+            });
+        }
+
+        // Page is either already cached or was just inserted.
+        Ok(self.pages.peek(page_number).expect("Page must be cached"))
+    }
+
+    fn read_page_header(&mut self, page_number: u32) -> std::io::Result<PageHeader> {
+        if self.pages.is_enabled() {
+            return Ok(self.read_cached_page(page_number)?.header);
+        }
+
+        let page_header = self.io.read(self.page_pos(page_number), self.header.page_header_length())?;
+
+        Ok(self.decode_page_header(&page_header))
+    }
+
+    fn link_page_forward(&mut self, page_number: u32, next_page_number: u32) -> std::io::Result<()> {
+        self.ensure_writable("LinkPageForward")?;
+
+        let mut page_header = self.read_page_header(page_number)?;
+
+        page_header.next_page_number = next_page_number;
+        page_header.has_next = true;
+
+        self.write_page_header(page_number, page_header)
+    }
+
+    fn write_page_header(&mut self, page_number: u32, mut header: PageHeader) -> std::io::Result<()> {
+        self.ensure_writable("WritePageHeader")?;
+
+        if header.has_book_header {
+            self.ensure_versioned("book headers")?;
+        }
+
+        // Checksum and compression of the body are managed by the worker.
+        if self.whole_pages() {
+            let stored_header = self.read_page_header(page_number)?;
+
+            header.checksum = stored_header.checksum;
+            header.compressed_size = stored_header.compressed_size;
+        } else {
+            header.compressed_size = None;
+        }
+
+        self.io.write(self.page_pos(page_number), self.encode_page_header(&header))?;
+
+        self.pages.update_header(page_number, header);
+
+        Ok(())
+    }
+
+    fn read_page(&mut self, page_number: u32, offset: u64, length: u64) -> std::io::Result<Vec<u8>> {
+        if offset >= self.header.page_size || length == 0 {
+            return Ok(vec![]);
+        }
+
+        if self.pages.is_enabled() || self.whole_pages() {
+            let page_size = self.header.page_size;
+
+            let (_, body) = self.read_whole_page_cached(page_number)?;
+
+            let offset = offset as usize;
+
+            let bytes = if offset as u64 + length > page_size {
+                // offset < page_size
+                &body[offset..]
+            } else {
+                &body[offset..offset + length as usize]
+            };
+
+            return Ok(bytes.to_vec());
+        }
+
+        let page_pos = self.body_pos(page_number);
+
+        if offset + length > self.header.page_size {
+            // offset < page_size
+            self.io.read(page_pos + offset, (self.header.page_size - offset) as usize)
+        } else {
+            self.io.read(page_pos + offset, length as usize)
+        }
+    }
+
+    fn write_page(&mut self, page_number: u32, offset: u64, bytes: Vec<u8>) -> std::io::Result<Vec<u8>> {
+        self.ensure_writable("WritePage")?;
+
+        let len = bytes.len() as u64;
+
+        if offset >= self.header.page_size {
+            return Ok(bytes);
+        }
+
+        if len == 0 {
+            return Ok(vec![]);
+        }
+
+        // Whole page is rewritten to update its checksum
+        // or compress it again.
+        if self.whole_pages() {
+            let (header, mut body) = self.read_whole_page_cached(page_number)?;
+
+            let split = std::cmp::min(len, self.header.page_size - offset) as usize;
+
+            body[offset as usize..offset as usize + split].copy_from_slice(&bytes[..split]);
+
+            self.write_whole_page(page_number, header, body)?;
+
+            return Ok(bytes[split..].to_vec());
+        }
+
+        let page_pos = self.body_pos(page_number);
+
+        if offset + len > self.header.page_size {
+            //  page: [        ]
+            // bytes:       [     ]
+            //              ^ offset
+            //                 ^ page_size
+            //
+            let split = (self.header.page_size - offset) as usize;
+
+            self.io.write(page_pos + offset, &bytes[..split])?;
+
+            self.pages.update_body(page_number, offset as usize, &bytes[..split]);
+
+            Ok(bytes[split..].to_vec())
+        }
+
+        else {
+            self.io.write(page_pos + offset, &bytes)?;
+
+            self.pages.update_body(page_number, offset as usize, &bytes);
+
+            Ok(vec![])
+        }
+    }
+
+    fn read_pages(&mut self, pages: Vec<(u32, u64, u64)>) -> std::io::Result<Vec<Vec<u8>>> {
+        // Cached pages are read from the memory and checksummed
+        // or compressed pages must be read entirely.
+        if self.pages.is_enabled() || self.whole_pages() {
+            return pages.into_iter()
+                .map(|(page_number, offset, length)| self.read_page(page_number, offset, length))
+                .collect();
+        }
+
+        let ranges = pages.into_iter()
+            .map(|(page_number, offset, length)| {
+                if offset >= self.header.page_size {
+                    return (0, 0);
+                }
+
+                let length = std::cmp::min(length, self.header.page_size - offset);
+
+                (self.body_pos(page_number) + offset, length as usize)
+            })
+            .collect::<Vec<_>>();
+
+        self.io.read_many(&ranges)
+    }
+
+    fn write_pages(&mut self, pages: Vec<(u32, u64, Vec<u8>)>) -> std::io::Result<()> {
+        self.ensure_writable("WritePages")?;
+
+        // Checksummed or compressed pages are rewritten one by one.
+        if self.whole_pages() {
+            for (page_number, offset, bytes) in pages {
+                self.write_page(page_number, offset, bytes)?;
+            }
+
+            return Ok(());
+        }
+
+        let writes = pages.into_iter()
+            .filter(|(_, offset, bytes)| *offset < self.header.page_size && !bytes.is_empty())
+            .map(|(page_number, offset, mut bytes)| {
+                bytes.truncate(std::cmp::min(bytes.len() as u64, self.header.page_size - offset) as usize);
+
+                (page_number, offset, bytes)
+            })
+            .collect::<Vec<_>>();
+
+        let ranges = writes.iter()
+            .map(|(page_number, offset, bytes)| {
+                (self.body_pos(*page_number) + offset, bytes.as_slice())
+            })
+            .collect::<Vec<_>>();
+
+        self.io.write_many(&ranges)?;
+
+        for (page_number, offset, bytes) in writes {
+            self.pages.update_body(page_number, offset as usize, &bytes);
+        }
+
+        Ok(())
+    }
+
     /// Poll filesystem task from the scheduler and execute it.
     ///
-    /// Returns error on scheduler failure.
+    /// Returns error on scheduler failure. Errors of the tasks
+    /// without response sender are recorded in the stats
+    /// (see `FilesystemStats::last_unreported_error`).
     pub fn update(&mut self) -> anyhow::Result<()> {
         if let Some(scheduler) = self.scheduler.as_mut() {
             if !scheduler.update() {
@@ -41,156 +950,312 @@ impl<T: StorageIO> FilesystemWorker<T> {
             }
         }
 
-        match self.handler.poll()? {
-            FilesystemTask::ReadFilesystemHeader { response_sender } => {
-                // let mut header = [0; FilesystemHeader::LENGTH];
+        if self.batch_size > 1 && !self.pages.is_enabled() && !self.whole_pages() {
+            let tasks = self.handler.poll_many(self.batch_size)?;
+
+            self.execute_batch(tasks);
+
+            return Ok(());
+        }
+
+        let task = self.handler.poll()?;
+
+        self.execute(task);
 
-                // header.copy_from_slice(&self.file.read(0, FilesystemHeader::LENGTH));
+        Ok(())
+    }
 
-                // let _ = response_sender.send(FilesystemHeader::from_bytes(&header));
+    /// Execute single filesystem task and record its execution time.
+    fn execute(&mut self, task: FilesystemTask) {
+        let name = task.name();
+        let started = Instant::now();
 
+        self.execute_task(task);
+
+        self.stats.record(name, started.elapsed().as_micros() as u64);
+    }
+
+    fn execute_task(&mut self, task: FilesystemTask) {
+        let name = task.name();
+
+        match task {
+            FilesystemTask::ReadFilesystemHeader { response_sender } => {
                 let _ = response_sender.send(self.header);
             }
 
-            FilesystemTask::WriteFilesystemHeader { header } => {
-                self.header = header;
+            FilesystemTask::WriteFilesystemHeader { header, response_sender } => {
+                // Format version, free pages list, allocation bitmap,
+                // page checksums and compression are managed by the worker.
+                let managed = FilesystemHeader {
+                    version: self.header.version,
+                    free_page_number: self.header.free_page_number,
+                    allocation_bitmap_page_number: self.header.allocation_bitmap_page_number,
+                    page_checksum: self.header.page_checksum,
+                    page_compression: self.header.page_compression,
+                    page_compression_level: self.header.page_compression_level,
+                    ..header
+                };
 
-                self.io.write(0, header.to_bytes());
-            }
+                let result = self.ensure_writable("WriteFilesystemHeader")
+                    .and_then(|_| {
+                        if header != managed {
+                            return Err(Error::new(
+                                ErrorKind::InvalidInput,
+                                "format version, free pages list, allocation bitmap, page checksums and compression can't be changed by the header write"
+                            ));
+                        }
 
-            FilesystemTask::CreatePage { parent_page_number, response_sender } => {
-                let page_header = PageHeader {
-                    prev_page_number: parent_page_number.unwrap_or_default(),
-                    next_page_number: 0,
+                        self.io.write(0, header.to_bytes())
+                    });
 
-                    has_prev: parent_page_number.is_some(),
-                    has_next: false
-                };
+                if result.is_ok() {
+                    if self.header.page_size != header.page_size {
+                        self.pages.set_page_size(header.page_size);
+                    }
 
-                let len = self.io.len();
+                    self.header = header;
+                }
 
-                let page_number = if len > FilesystemHeader::LENGTH as u64 {
-                    let total_pages = (len - FilesystemHeader::LENGTH as u64) / (PageHeader::LENGTH as u64 + self.header.page_size);
+                self.respond(name, response_sender, result);
+            }
 
-                    total_pages as u32
-                } else {
-                    0
-                };
+            FilesystemTask::Sync { response_sender } => {
+                let result = self.io.sync();
 
-                self.io.append(page_header.to_bytes());
-                self.io.append(vec![0; self.header.page_size as usize]);
+                self.respond(name, response_sender, result);
+            }
 
-                let page = Page::new(page_number, self.handler.clone());
+            FilesystemTask::ReadPagesCacheStats { response_sender } => {
+                let _ = response_sender.send(self.pages.stats());
+            }
 
-                let _ = response_sender.send(page);
+            FilesystemTask::ReadStats { response_sender } => {
+                let _ = response_sender.send(self.stats.clone());
             }
 
-            FilesystemTask::LinkPageForward { page_number, next_page_number } => {
-                let page_pos = FilesystemHeader::LENGTH as u64 + page_number as u64 * (PageHeader::LENGTH as u64 + self.header.page_size);
+            FilesystemTask::ReadAllocationBitmap { response_sender } => {
+                let _ = response_sender.send(self.bitmap.clone());
+            }
 
-                let mut page_header = [0; PageHeader::LENGTH];
+            FilesystemTask::CreatePage { parent_page_number, response_sender } => {
+                let _ = response_sender.send(self.create_page(parent_page_number));
+            }
 
-                page_header.copy_from_slice(&self.io.read(page_pos, PageHeader::LENGTH));
+            FilesystemTask::FreePage { page_number, response_sender } => {
+                let result = self.free_page(page_number);
 
-                let mut page_header = PageHeader::from_bytes(&page_header);
+                self.respond(name, response_sender, result);
+            }
 
-                page_header.next_page_number = next_page_number;
-                page_header.has_next = true;
+            FilesystemTask::LinkPageForward { page_number, next_page_number, response_sender } => {
+                let result = self.link_page_forward(page_number, next_page_number);
 
-                self.io.write(page_pos, page_header.to_bytes());
+                self.respond(name, response_sender, result);
             }
 
             FilesystemTask::ReadPageHeader { page_number, response_sender } => {
-                let page_pos = FilesystemHeader::LENGTH as u64 + page_number as u64 * (PageHeader::LENGTH as u64 + self.header.page_size);
+                let _ = response_sender.send(self.read_page_header(page_number));
+            }
 
-                let mut page_header = [0; PageHeader::LENGTH];
+            FilesystemTask::WritePageHeader { page_number, header, response_sender } => {
+                let result = self.write_page_header(page_number, header);
 
-                page_header.copy_from_slice(&self.io.read(page_pos, PageHeader::LENGTH));
+                self.respond(name, response_sender, result);
+            }
 
-                let _ = response_sender.send(PageHeader::from_bytes(&page_header));
+            FilesystemTask::ReadPage { page_number, offset, length, response_sender } => {
+                let _ = response_sender.send(self.read_page(page_number, offset, length));
             }
 
-            FilesystemTask::WritePageHeader { page_number, header } => {
-                let page_pos = FilesystemHeader::LENGTH as u64 + page_number as u64 * (PageHeader::LENGTH as u64 + self.header.page_size);
+            FilesystemTask::WritePage { page_number, offset, bytes, response_sender } => {
+                let result = self.write_page(page_number, offset, bytes);
 
-                self.io.write(page_pos, header.to_bytes());
+                self.respond(name, response_sender, result);
             }
 
-            FilesystemTask::ReadPage { page_number, offset, length, response_sender } => {
-                if offset >= self.header.page_size || length == 0 {
-                    let _ = response_sender.send(vec![]);
-                }
+            FilesystemTask::ReadPages { pages, response_sender } => {
+                let _ = response_sender.send(self.read_pages(pages));
+            }
+
+            FilesystemTask::WritePages { pages, response_sender } => {
+                let result = self.write_pages(pages);
+
+                self.respond(name, response_sender, result);
+            }
+        }
+    }
+
+    /// Execute polled tasks, submitting consecutive page
+    /// reads and writes which don't conflict with each
+    /// other to the IO as batches.
+    fn execute_batch(&mut self, tasks: Vec<FilesystemTask>) {
+        let mut batch = Vec::new();
 
-                // else if let Some(bytes) = self.pages.get(&page_number) {
-                //     let offset = offset as usize;
-                //     let length = length as usize;
+        let mut read_pages = HashSet::new();
+        let mut written_pages = HashSet::new();
 
-                //     let bytes = if offset + length > self.header.page_size as usize {
-                //         // offset < page_size
-                //         &bytes[offset..]
-                //     } else {
-                //         &bytes[offset..offset + length]
-                //     };
+        for task in tasks {
+            let (page_number, is_write) = match &task {
+                FilesystemTask::ReadPage { page_number, .. } => (*page_number, false),
+                FilesystemTask::WritePage { page_number, .. } => (*page_number, true),
 
-                //     let _ = response_sender.send(bytes.to_vec());
-                // }
+                _ => {
+                    self.submit_batch(std::mem::take(&mut batch));
 
-                else {
-                    let page_pos = FilesystemHeader::LENGTH as u64 + page_number as u64 * (PageHeader::LENGTH as u64 + self.header.page_size) + PageHeader::LENGTH as u64;
+                    read_pages.clear();
+                    written_pages.clear();
 
-                    let bytes = if offset + length > self.header.page_size {
-                        // offset < page_size
-                        self.io.read(page_pos + offset, (self.header.page_size - offset) as usize)
-                    } else {
-                        self.io.read(page_pos + offset, length as usize)
-                    };
+                    self.execute(task);
 
-                    let _ = response_sender.send(bytes);
+                    continue;
                 }
+            };
+
+            // Operations on the same page must be performed in order.
+            if written_pages.contains(&page_number) || (is_write && read_pages.contains(&page_number)) {
+                self.submit_batch(std::mem::take(&mut batch));
+
+                read_pages.clear();
+                written_pages.clear();
             }
 
-            FilesystemTask::WritePage { page_number, offset, bytes, response_sender } => {
-                let len = bytes.len() as u64;
+            if is_write {
+                written_pages.insert(page_number);
+            } else {
+                read_pages.insert(page_number);
+            }
 
-                if offset >= self.header.page_size {
-                    if let Some(response_sender) = response_sender {
-                        let _ = response_sender.send(bytes);
+            batch.push(task);
+        }
+
+        self.submit_batch(batch)
+    }
+
+    /// Submit page reads and writes to the IO at once
+    /// and send their results to the response senders.
+    ///
+    /// Execution time of the whole batch is recorded
+    /// for every page task of this batch.
+    fn submit_batch(&mut self, tasks: Vec<FilesystemTask>) {
+        let started = Instant::now();
+
+        let mut ops = Vec::with_capacity(tasks.len());
+        let mut batched = Vec::with_capacity(tasks.len());
+        let mut names = Vec::with_capacity(tasks.len());
+
+        for task in tasks {
+            let name = task.name();
+
+            match task {
+                FilesystemTask::ReadPage { page_number, offset, length, response_sender } => {
+                    names.push(name);
+
+                    if offset >= self.header.page_size || length == 0 {
+                        let _ = response_sender.send(Ok(vec![]));
+
+                        continue;
                     }
+
+                    let length = std::cmp::min(length, self.header.page_size - offset);
+
+                    ops.push(StorageOp::Read {
+                        offset: self.body_pos(page_number) + offset,
+                        length: length as usize
+                    });
+
+                    batched.push(BatchedTask::Read(response_sender));
                 }
 
-                else if len > 0 {
-                    let page_pos = FilesystemHeader::LENGTH as u64 + page_number as u64 * (PageHeader::LENGTH as u64 + self.header.page_size) + PageHeader::LENGTH as u64;
+                FilesystemTask::WritePage { page_number, offset, mut bytes, response_sender } => {
+                    names.push(name);
 
-                    if offset + len > self.header.page_size {
-                        //  page: [        ]
-                        // bytes:       [     ]
-                        //              ^ offset
-                        //                 ^ page_size
-                        //
-                        let split = (self.header.page_size - offset) as usize;
+                    if let Err(err) = self.ensure_writable("WritePage") {
+                        self.respond(name, response_sender, Err(err));
 
-                        self.io.write(page_pos + offset, &bytes[..split]);
+                        continue;
+                    }
 
-                        if let Some(response_sender) = response_sender {
-                            let _ = response_sender.send(bytes[split..].to_vec());
-                        }
+                    if offset >= self.header.page_size || bytes.is_empty() {
+                        self.respond(name, response_sender, Ok(bytes));
+
+                        continue;
                     }
 
-                    else {
-                        self.io.write(page_pos + offset, bytes);
+                    let split = std::cmp::min(bytes.len() as u64, self.header.page_size - offset) as usize;
+
+                    let remaining = bytes.split_off(split);
+
+                    ops.push(StorageOp::Write {
+                        offset: self.body_pos(page_number) + offset,
+                        bytes
+                    });
+
+                    batched.push(BatchedTask::Write {
+                        remaining,
+                        response_sender
+                    });
+                }
+
+                task => self.execute(task)
+            }
+        }
+
+        if ops.is_empty() {
+            self.record_batch(names, started);
+
+            return;
+        }
+
+        let mut batched = batched.into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        let mut unreported_errors = Vec::new();
+
+        self.io.submit(ops, |i, result| {
+            match batched[i].take() {
+                Some(BatchedTask::Read(response_sender)) => {
+                    let _ = response_sender.send(result);
+                }
+
+                Some(BatchedTask::Write { remaining, response_sender: Some(response_sender) }) => {
+                    let _ = response_sender.send(result.map(|_| remaining));
+                }
+
+                Some(BatchedTask::Write { response_sender: None, .. }) => {
+                    if let Err(err) = result {
+                        unreported_errors.push(err);
                     }
                 }
+
+                None => ()
             }
+        });
+
+        for err in unreported_errors {
+            self.stats.record_unreported_error("WritePage", &err);
         }
 
-        Ok(())
+        self.record_batch(names, started);
+    }
+
+    #[inline]
+    fn record_batch(&mut self, names: Vec<&'static str>, started: Instant) {
+        let micros = started.elapsed().as_micros() as u64;
+
+        for name in names {
+            self.stats.record(name, micros);
+        }
     }
 }
 
 impl<T: StorageIO + Send + Sync + 'static> FilesystemWorker<T> {
     #[inline]
     /// Spawn new thread and run worker updates in a loop.
-    pub fn daemonize(mut self) -> std::thread::JoinHandle<()> {
+    ///
+    /// Thread is stopped when the tasks scheduler is closed,
+    /// and returns an error when the scheduler has failed.
+    pub fn daemonize(mut self) -> std::thread::JoinHandle<anyhow::Result<()>> {
         if let Some(scheduler) = self.scheduler.take() {
             scheduler.daemonize();
         }
@@ -198,7 +1263,11 @@ impl<T: StorageIO + Send + Sync + 'static> FilesystemWorker<T> {
         std::thread::spawn(move || {
             loop {
                 if let Err(err) = self.update() {
-                    panic!("Failed to execute filesystem task : filesystem closed : {err}");
+                    if self.handler.is_closed() {
+                        return Ok(());
+                    }
+
+                    return Err(err);
                 }
             }
         })
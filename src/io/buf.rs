@@ -3,91 +3,177 @@ use super::prelude::*;
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /// Wrapper structure for raw storage IOs that implements
 /// read-write buffer of the first N bytes of this IO.
+///
+/// By default the buffer is write-through: every write is
+/// immediately passed to the inner IO. In write-back mode writes
+/// within the already buffered bytes are kept in memory and marked
+/// as dirty until `StorageIO::sync` is called, the buffer is dropped
+/// or the amount of dirty bytes exceeds the given limit.
+///
+/// Dirty bytes are written to the inner IO on drop without syncing
+/// it, and errors of these writes are ignored. Call `StorageIO::sync`
+/// before dropping the buffer to handle them. Clones have their own
+/// copies of dirty bytes, so sync the buffer before cloning it to not
+/// write them twice.
 pub struct BufStorageIO<T> {
     io: T,
     buf: Vec<u8>,
-    size: usize
+    size: usize,
+
+    /// Sorted non-overlapping `[start, end)` ranges of the buffer
+    /// which weren't written to the inner IO yet.
+    dirty: Vec<(usize, usize)>,
+
+    /// Total length of the dirty ranges.
+    dirty_bytes: usize,
+
+    /// Maximal amount of dirty bytes before they're synced
+    /// automatically. `None` if buffer is write-through.
+    dirty_limit: Option<usize>,
+
+    /// Write dirty ranges to the inner IO. Stored so they
+    /// can be written on drop without bounds on the struct.
+    write_dirty: DirtyWriter<T>
+}
+
+/// Function which writes dirty ranges to the inner IO.
+type DirtyWriterFn<T> = fn(&mut T, &[(u64, &[u8])]) -> std::io::Result<()>;
+
+/// Dirty ranges writer of the buffer. It's the same for all
+/// the buffers of the same inner IO type, so it doesn't take
+/// part in their comparison and hashing.
+struct DirtyWriter<T>(DirtyWriterFn<T>);
+
+impl<T> Clone for DirtyWriter<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for DirtyWriter<T> {}
+
+impl<T> PartialEq for DirtyWriter<T> {
+    #[inline]
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl<T> Eq for DirtyWriter<T> {}
+
+impl<T> std::hash::Hash for DirtyWriter<T> {
+    #[inline]
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
+
+impl<T> std::fmt::Debug for DirtyWriter<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("DirtyWriter")
+    }
 }
 
 impl<T: StorageIO> BufStorageIO<T> {
     /// Wrap given IO to buffer read/write operations.
-    pub fn new(mut io: T, size: usize) -> Self {
-        let len = io.len();
+    pub fn new(mut io: T, size: usize) -> std::io::Result<Self> {
+        let len = io.len()?;
 
         let buf = if len >= size as u64 {
-            io.read(0, size)
+            io.read(0, size)?
         } else {
-            let mut buf = Vec::with_capacity(size);
-
-            let file = io.read(0, len as usize);
-
-            buf[..len as usize].copy_from_slice(&file);
-
-            buf
+            io.read(0, len as usize)?
         };
 
-        Self {
+        Ok(Self {
             io,
             buf,
-            size
-        }
+            size,
+
+            dirty: Vec::new(),
+            dirty_bytes: 0,
+            dirty_limit: None,
+
+            write_dirty: DirtyWriter(|io, writes| io.write_many(writes))
+        })
     }
-}
 
-impl<T> StorageIO for BufStorageIO<T> where T: StorageIO {
-    type Reader = T::Reader;
+    /// Wrap given IO to buffer read/write operations
+    /// in write-back mode.
+    ///
+    /// Dirty bytes are synced automatically when their
+    /// amount exceeds `dirty_limit`.
+    pub fn write_back(io: T, size: usize, dirty_limit: usize) -> std::io::Result<Self> {
+        let mut buf = Self::new(io, size)?;
+
+        buf.dirty_limit = Some(dirty_limit);
+
+        Ok(buf)
+    }
 
     #[inline]
-    fn io(&mut self) -> &mut Self::Reader {
-        self.io.io()
+    /// Check if the buffer works in write-back mode.
+    pub const fn is_write_back(&self) -> bool {
+        self.dirty_limit.is_some()
     }
 
-    // TODO: optimize integer conversions when usize = u64
-    // #[cfg(target_pointer_width = "64")]
+    #[inline]
+    /// Get amount of buffered bytes which weren't
+    /// written to the inner IO yet.
+    pub const fn dirty_bytes(&self) -> usize {
+        self.dirty_bytes
+    }
 
-    fn read(&mut self, offset: u64, length: usize) -> Vec<u8> {
-        if let Ok(offset) = usize::try_from(offset) {
-            let n = self.buf.len();
+    /// Mark `[start, end)` range of the buffer as dirty,
+    /// merging it with overlapping and adjacent ranges.
+    fn mark_dirty(&mut self, mut start: usize, mut end: usize) {
+        let i = self.dirty.partition_point(|range| range.1 < start);
+        let mut j = i;
 
-            // [       ]
-            //    ^ offset (within the buffer)
-            if offset < n {
-                if let Some(end) = offset.checked_add(length) {
-                    // buf: [ ______   ]
-                    //        ^    ^   ^ n
-                    //        |    | end
-                    //        | offset
-                    if n >= end {
-                        // Read the whole buffer if it's available.
-                        return self.buf[offset..end].to_vec();
-                    }
+        let mut merged = 0;
 
-                    // buf: [ ______ ]
-                    //        ^      ^    ^ end
-                    //        |      | n
-                    //        | offset
-                    else {
-                        let mut result = Vec::with_capacity(length);
+        while j < self.dirty.len() && self.dirty[j].0 <= end {
+            start = start.min(self.dirty[j].0);
+            end = end.max(self.dirty[j].1);
 
-                        // Read whole available buffer.
-                        result.extend_from_slice(&self.buf[offset..]);
+            merged += self.dirty[j].1 - self.dirty[j].0;
 
-                        // Read remaining bytes from the IO.
-                        result.extend(T::read(&mut self.io, n as u64, end - n));
+            j += 1;
+        }
 
-                        return result;
-                    }
-                }
+        self.dirty_bytes = self.dirty_bytes - merged + (end - start);
+
+        self.dirty.splice(i..j, [(start, end)]);
+    }
+
+    /// Remove `[start, end)` range of the buffer from the
+    /// dirty ranges, shrinking or splitting overlapping ones.
+    fn clear_dirty(&mut self, start: usize, end: usize) {
+        let i = self.dirty.partition_point(|range| range.1 <= start);
+        let mut j = i;
+
+        let mut kept = Vec::with_capacity(2);
+
+        while j < self.dirty.len() && self.dirty[j].0 < end {
+            let (range_start, range_end) = self.dirty[j];
+
+            self.dirty_bytes -= range_end.min(end) - range_start.max(start);
+
+            if range_start < start {
+                kept.push((range_start, start));
             }
+
+            if range_end > end {
+                kept.push((end, range_end));
+            }
+
+            j += 1;
         }
 
-        // Read bytes directly from the IO.
-        self.io.read(offset, length)
+        self.dirty.splice(i..j, kept);
     }
 
-    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) {
-        let bytes = bytes.as_ref();
-
+    /// Copy bytes written to the inner IO to the buffer.
+    fn update_buf(&mut self, offset: u64, bytes: &[u8]) {
         if let Ok(offset) = usize::try_from(offset) {
             // [       ]
             //    ^ offset (within the buffer)
@@ -141,13 +227,100 @@ impl<T> StorageIO for BufStorageIO<T> where T: StorageIO {
                 }
             }
         }
+    }
+}
+
+impl<T> StorageIO for BufStorageIO<T> where T: StorageIO {
+    type Reader = T::Reader;
+
+    #[inline]
+    fn io(&mut self) -> &mut Self::Reader {
+        self.io.io()
+    }
+
+    // TODO: optimize integer conversions when usize = u64
+    // #[cfg(target_pointer_width = "64")]
+
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        if let Ok(offset) = usize::try_from(offset) {
+            let n = self.buf.len();
+
+            // [       ]
+            //    ^ offset (within the buffer)
+            if offset < n {
+                if let Some(end) = offset.checked_add(length) {
+                    // buf: [ ______   ]
+                    //        ^    ^   ^ n
+                    //        |    | end
+                    //        | offset
+                    if n >= end {
+                        // Read the whole buffer if it's available.
+                        return Ok(self.buf[offset..end].to_vec());
+                    }
+
+                    // buf: [ ______ ]
+                    //        ^      ^    ^ end
+                    //        |      | n
+                    //        | offset
+                    else {
+                        let mut result = Vec::with_capacity(length);
+
+                        // Read whole available buffer.
+                        result.extend_from_slice(&self.buf[offset..]);
+
+                        // Read remaining bytes from the IO.
+                        result.extend(T::read(&mut self.io, n as u64, end - n)?);
+
+                        return Ok(result);
+                    }
+                }
+            }
+        }
 
-        self.io.write(offset, bytes)
+        // Read bytes directly from the IO.
+        self.io.read(offset, length)
     }
 
-    fn append(&mut self, bytes: impl AsRef<[u8]>) {
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
         let bytes = bytes.as_ref();
 
+        // In write-back mode keep writes within the buffered
+        // bytes in memory. Inner IO is not extended by them
+        // so its length remains correct.
+        if let Some(dirty_limit) = self.dirty_limit {
+            if let Ok(offset) = usize::try_from(offset) {
+                if let Some(end) = offset.checked_add(bytes.len()) {
+                    if end <= self.buf.len() {
+                        self.buf[offset..end].copy_from_slice(bytes);
+
+                        if offset < end {
+                            self.mark_dirty(offset, end);
+                        }
+
+                        if self.dirty_bytes() > dirty_limit {
+                            self.sync()?;
+                        }
+
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        // Write bytes to the IO first so the buffer is not
+        // updated if the write has failed.
+        self.io.write(offset, bytes)?;
+
+        self.update_buf(offset, bytes);
+
+        Ok(())
+    }
+
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        self.io.append(bytes)?;
+
         let n = self.buf.len();
 
         if self.size > n {
@@ -161,25 +334,157 @@ impl<T> StorageIO for BufStorageIO<T> where T: StorageIO {
             }
         }
 
-        self.io.append(bytes)
+        Ok(())
     }
 
     #[inline]
-    fn len(&mut self) -> u64 {
+    fn len(&mut self) -> std::io::Result<u64> {
         if self.size != 0 && self.buf.is_empty() {
-            return 0;
+            return Ok(0);
         }
 
         self.io.len()
     }
 
     #[inline]
-    fn is_empty(&mut self) -> bool {
+    fn is_empty(&mut self) -> std::io::Result<bool> {
         if self.size == 0 {
             self.io.is_empty()
         } else {
-            self.buf.is_empty()
+            Ok(self.buf.is_empty())
+        }
+    }
+
+    /// Read buffered ranges from the memory and pass
+    /// all the other ones to the inner IO at once.
+    fn read_many(&mut self, ranges: &[(u64, usize)]) -> std::io::Result<Vec<Vec<u8>>> {
+        let mut bufs = vec![Vec::new(); ranges.len()];
+
+        let mut direct = Vec::with_capacity(ranges.len());
+        let mut direct_indices = Vec::with_capacity(ranges.len());
+
+        for (i, (offset, length)) in ranges.iter().enumerate() {
+            if *offset >= self.buf.len() as u64 {
+                direct.push((*offset, *length));
+                direct_indices.push(i);
+            } else {
+                bufs[i] = self.read(*offset, *length)?;
+            }
+        }
+
+        if !direct.is_empty() {
+            for (i, buf) in direct_indices.into_iter().zip(self.io.read_many(&direct)?) {
+                bufs[i] = buf;
+            }
+        }
+
+        Ok(bufs)
+    }
+
+    /// Pass all the writes to the inner IO at once
+    /// and copy them to the buffer.
+    ///
+    /// In write-back mode writes are performed one by one
+    /// so the buffered ones are kept in memory.
+    fn write_many<B: AsRef<[u8]>>(&mut self, writes: &[(u64, B)]) -> std::io::Result<()> {
+        if self.is_write_back() {
+            for (offset, bytes) in writes {
+                self.write(*offset, bytes)?;
+            }
+
+            return Ok(());
+        }
+
+        self.io.write_many(writes)?;
+
+        for (offset, bytes) in writes {
+            self.update_buf(*offset, bytes.as_ref());
+        }
+
+        Ok(())
+    }
+
+    /// Write all the dirty ranges to the inner IO at once
+    /// and sync it.
+    fn sync(&mut self) -> std::io::Result<()> {
+        if !self.dirty.is_empty() {
+            let writes = self.dirty.iter()
+                .map(|&(start, end)| (start as u64, &self.buf[start..end]))
+                .collect::<Vec<_>>();
+
+            // Ranges are kept on failure so they can be retried.
+            self.io.write_many(&writes)?;
+
+            self.dirty.clear();
+            self.dirty_bytes = 0;
+        }
+
+        self.io.sync()
+    }
+
+    /// Discard the range of the inner IO. Dirty bytes of the
+    /// range are dropped so they're not written over it.
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        if let Ok(start) = usize::try_from(offset) {
+            let end = usize::try_from(offset.saturating_add(length))
+                .unwrap_or(usize::MAX)
+                .min(self.buf.len());
+
+            if start < end {
+                self.clear_dirty(start, end);
+            }
+        }
+
+        self.io.discard(offset, length)
+    }
+}
+
+impl<T> Drop for BufStorageIO<T> {
+    fn drop(&mut self) {
+        if !self.dirty.is_empty() {
+            let writes = self.dirty.iter()
+                .map(|&(start, end)| (start as u64, &self.buf[start..end]))
+                .collect::<Vec<_>>();
+
+            let _ = (self.write_dirty.0)(&mut self.io, &writes);
+        }
+    }
+}
+
+impl<T> PositionalStorageIO for BufStorageIO<T> where T: PositionalStorageIO {
+    fn read_at(&self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        if let Ok(offset) = usize::try_from(offset) {
+            let n = self.buf.len();
+
+            if offset < n {
+                if let Some(end) = offset.checked_add(length) {
+                    // Read the whole buffer if it's available.
+                    if n >= end {
+                        return Ok(self.buf[offset..end].to_vec());
+                    }
+
+                    // Read available buffer and the remaining bytes from the IO.
+                    let mut result = Vec::with_capacity(length);
+
+                    result.extend_from_slice(&self.buf[offset..]);
+                    result.extend(self.io.read_at(n as u64, end - n)?);
+
+                    return Ok(result);
+                }
+            }
+        }
+
+        // Read bytes directly from the IO.
+        self.io.read_at(offset, length)
+    }
+
+    #[inline]
+    fn len_at(&self) -> std::io::Result<u64> {
+        if self.size != 0 && self.buf.is_empty() {
+            return Ok(0);
         }
+
+        self.io.len_at()
     }
 }
 
@@ -189,7 +494,7 @@ mod tests {
 
     use tinyrand::{Rand, Wyrand};
 
-    use super::{StorageIO, BufStorageIO};
+    use super::{StorageIO, PositionalStorageIO, BufStorageIO, FaultyStorageIO, StorageFault, MemoryStorageIO, StatsStorageIO};
 
     fn with_io(name: &str, size: usize, callback: impl FnOnce(File, BufStorageIO<File>)) {
         let path_1 = std::env::temp_dir().join(format!(".animefs-buf-io-test-with-{name}"));
@@ -211,7 +516,7 @@ mod tests {
             .open(&path_2)
             .expect("Failed to open file");
 
-        callback(file_1, BufStorageIO::new(file_2, size));
+        callback(file_1, BufStorageIO::new(file_2, size).unwrap());
 
         std::fs::remove_file(path_1).unwrap();
         std::fs::remove_file(path_2).unwrap();
@@ -219,55 +524,58 @@ mod tests {
 
     fn test_write_buf(size: usize) {
         with_io(&format!("write{size}"), size, |mut file, mut buf| {
-            assert_eq!(file.len(), 0);
-            assert_eq!(buf.len(), 0);
+            assert_eq!(file.len().unwrap(), 0);
+            assert_eq!(buf.len().unwrap(), 0);
 
-            assert!(file.is_empty());
-            assert!(buf.is_empty());
+            assert!(file.is_empty().unwrap());
+            assert!(buf.is_empty().unwrap());
 
             let mut rand = Wyrand::default();
 
             let bytes = vec![rand.next_lim_u16(256) as u8; rand.next_lim_usize(16)];
 
-            file.append(&bytes);
-            buf.append(bytes);
+            file.append(&bytes).unwrap();
+            buf.append(bytes).unwrap();
 
-            let len_1 = file.len() as usize;
-            let len_2 = buf.len() as usize;
+            let len_1 = file.len().unwrap() as usize;
+            let len_2 = buf.len().unwrap() as usize;
 
             assert_eq!(len_1, len_2);
-            assert_eq!(file.read(0, len_1), buf.read(0, len_2));
+            assert_eq!(file.read(0, len_1).unwrap(), buf.read(0, len_2).unwrap());
 
             for i in 0..1000 {
                 let offset = rand.next_lim_u64(256);
                 let bytes = vec![rand.next_lim_u16(256) as u8; rand.next_lim_usize(256)];
 
-                file.write(offset, &bytes);
-                buf.write(offset, bytes);
+                file.write(offset, &bytes).unwrap();
+                buf.write(offset, bytes).unwrap();
 
                 if i == 500 {
                     let bytes = vec![rand.next_lim_u16(256) as u8; rand.next_lim_usize(16)];
 
-                    file.append(&bytes);
-                    buf.append(bytes);
+                    file.append(&bytes).unwrap();
+                    buf.append(bytes).unwrap();
                 }
             }
 
             let bytes = vec![rand.next_lim_u16(256) as u8; rand.next_lim_usize(16)];
 
-            file.append(&bytes);
-            buf.append(bytes);
+            file.append(&bytes).unwrap();
+            buf.append(bytes).unwrap();
 
-            assert!(!file.is_empty());
-            assert!(!buf.is_empty());
+            assert!(!file.is_empty().unwrap());
+            assert!(!buf.is_empty().unwrap());
 
             assert!(buf.buf.len() <= size);
 
-            let len_1 = file.len() as usize;
-            let len_2 = buf.len() as usize;
+            let len_1 = file.len().unwrap() as usize;
+            let len_2 = buf.len().unwrap() as usize;
 
             assert_eq!(len_1, len_2);
-            assert_eq!(file.read(0, len_1), buf.read(0, len_2));
+            assert_eq!(file.read(0, len_1).unwrap(), buf.read(0, len_2).unwrap());
+
+            assert_eq!(buf.len_at().unwrap() as usize, len_2);
+            assert_eq!(file.read_at(0, len_1).unwrap(), buf.read_at(0, len_2).unwrap());
         });
     }
 
@@ -285,4 +593,138 @@ mod tests {
     fn write1024() {
         test_write_buf(1024);
     }
+
+    #[test]
+    fn write_back() {
+        with_io("write-back", 0, |mut file, buf| {
+            let mut rand = Wyrand::default();
+
+            let bytes = vec![rand.next_lim_u16(256) as u8; 256];
+
+            file.append(&bytes).unwrap();
+
+            let mut buf = BufStorageIO::write_back(buf.io.try_clone().unwrap(), 128, 64).unwrap();
+
+            buf.append(bytes).unwrap();
+
+            assert!(buf.is_write_back());
+
+            for _ in 0..1000 {
+                let offset = rand.next_lim_u64(256);
+                let bytes = vec![rand.next_lim_u16(256) as u8; rand.next_lim_usize(32)];
+
+                file.write(offset, &bytes).unwrap();
+                buf.write(offset, bytes).unwrap();
+
+                assert!(buf.dirty_bytes() <= 64);
+                assert_eq!(buf.dirty_bytes(), buf.dirty.iter().map(|(start, end)| end - start).sum());
+                assert_eq!(file.read(0, 512).unwrap(), buf.read(0, 512).unwrap());
+            }
+
+            buf.sync().unwrap();
+
+            assert_eq!(file.read(0, 512).unwrap(), buf.io.read(0, 512).unwrap());
+
+            buf.write(0, [1, 2, 3, 4]).unwrap();
+
+            assert_eq!(buf.dirty_bytes(), 4);
+            assert_ne!(buf.io.read(0, 4).unwrap(), &[1, 2, 3, 4]);
+
+            buf.sync().unwrap();
+
+            assert_eq!(buf.dirty_bytes(), 0);
+            assert_eq!(buf.io.read(0, 4).unwrap(), &[1, 2, 3, 4]);
+
+            buf.write(4, [5, 6, 7, 8]).unwrap();
+
+            let mut io = buf.io.try_clone().unwrap();
+
+            drop(buf);
+
+            assert_eq!(io.read(0, 8).unwrap(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+            // Discarded dirty bytes are not written back.
+            let mut buf = BufStorageIO::write_back(io.try_clone().unwrap(), 128, 64).unwrap();
+
+            buf.write(0, [9; 8]).unwrap();
+            buf.discard(2, 4).unwrap();
+
+            assert_eq!(buf.dirty_bytes(), 4);
+
+            buf.sync().unwrap();
+
+            let bytes = io.read(0, 8).unwrap();
+
+            assert_eq!(&bytes[..2], &[9, 9]);
+            assert_ne!(&bytes[2..6], &[9; 4]);
+            assert_eq!(&bytes[6..], &[9, 9]);
+        });
+    }
+
+    #[test]
+    fn write_back_failure() {
+        let io = FaultyStorageIO::new(MemoryStorageIO::from_bytes([0; 16]))
+            .with_fault(StorageFault::FailWrite(1));
+
+        let mut buf = BufStorageIO::write_back(io, 16, 16).unwrap();
+
+        buf.write(0, [1, 2]).unwrap();
+        buf.write(8, [3, 4]).unwrap();
+
+        // Dirty ranges are kept if sync fails.
+        assert!(buf.sync().is_err());
+        assert_eq!(buf.dirty_bytes(), 4);
+
+        buf.sync().unwrap();
+
+        assert_eq!(buf.dirty_bytes(), 0);
+        assert_eq!(buf.io.inner().as_bytes(), &[1, 2, 0, 0, 0, 0, 0, 0, 3, 4, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn drop_sync() {
+        let io = StatsStorageIO::new(MemoryStorageIO::from_bytes([0; 16]));
+        let stats = io.stats();
+
+        // Clean buffers don't touch the inner IO on drop.
+        drop(BufStorageIO::new(io, 16).unwrap());
+
+        assert_eq!(stats.calls(), 2);
+
+        let io = StatsStorageIO::new(MemoryStorageIO::from_bytes([0; 16]));
+        let stats = io.stats();
+
+        let mut buf = BufStorageIO::write_back(io, 16, 16).unwrap();
+
+        buf.write(0, [1, 2]).unwrap();
+
+        // Dirty bytes are written on drop without syncing the inner IO.
+        drop(buf);
+
+        assert_eq!(stats.write_many.calls(), 1);
+        assert_eq!(stats.sync.calls(), 0);
+    }
+
+    #[test]
+    fn read_write_many() {
+        with_io("many", 128, |mut file, mut buf| {
+            let mut rand = Wyrand::default();
+
+            for _ in 0..100 {
+                let writes = (0..rand.next_lim_usize(8))
+                    .map(|_| (rand.next_lim_u64(256), vec![rand.next_lim_u16(256) as u8; rand.next_lim_usize(64)]))
+                    .collect::<Vec<_>>();
+
+                file.write_many(&writes).unwrap();
+                buf.write_many(&writes).unwrap();
+            }
+
+            let ranges = (0..32)
+                .map(|_| (rand.next_lim_u64(320), rand.next_lim_usize(64)))
+                .collect::<Vec<_>>();
+
+            assert_eq!(file.read_many(&ranges).unwrap(), buf.read_many(&ranges).unwrap());
+            assert_eq!(file.read(0, 320).unwrap(), buf.read(0, 320).unwrap());
+        });
+    }
 }
@@ -0,0 +1,546 @@
+use std::io::{Error, ErrorKind};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicBool, Ordering};
+
+use super::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Fault which can be injected into the `FaultyStorageIO`.
+///
+/// Writes and reads are numbered from 0 in order they were
+/// performed. Appends are counted as writes.
+pub enum StorageFault {
+    /// Return an error from the given write without
+    /// writing anything.
+    FailWrite(u64),
+
+    /// Write only the first `bytes` bytes of the given
+    /// write and return an error.
+    TearWrite {
+        write: u64,
+        bytes: usize
+    },
+
+    /// Simulate a crash on the given write. Only the first
+    /// `torn_bytes` bytes of this write are stored and all
+    /// the following writes are silently dropped. Unsynced
+    /// writes are dropped as well if they're buffered.
+    Crash {
+        write: u64,
+        torn_bytes: usize
+    },
+
+    /// Flip a bit of the given read result. Bit index is taken
+    /// modulo the amount of read bits.
+    FlipBit {
+        read: u64,
+        bit: usize
+    }
+}
+
+#[derive(Default, Debug)]
+/// Statistics of the `FaultyStorageIO` shared between
+/// its clones, so they can be read after the IO was moved
+/// to the filesystem worker.
+pub struct FaultyStorageStats {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    crashed: AtomicBool
+}
+
+impl FaultyStorageStats {
+    #[inline]
+    /// Get amount of performed reads.
+    pub fn reads(&self) -> u64 {
+        self.reads.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    /// Get amount of performed writes.
+    pub fn writes(&self) -> u64 {
+        self.writes.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    /// Check if the scripted crash has happened.
+    pub fn crashed(&self) -> bool {
+        self.crashed.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Wrapper storage IO that can be scripted to fail, tear
+/// or drop writes and corrupt reads. Used for crash testing.
+pub struct FaultyStorageIO<T> {
+    io: T,
+    faults: Vec<StorageFault>,
+
+    /// Writes which weren't synced yet. `None` if writes
+    /// are passed to the inner IO immediately.
+    unsynced: Option<Vec<(u64, Vec<u8>)>>,
+
+    stats: Arc<FaultyStorageStats>
+}
+
+impl<T: StorageIO> FaultyStorageIO<T> {
+    #[inline]
+    /// Wrap given IO without any faults.
+    pub fn new(io: T) -> Self {
+        Self {
+            io,
+            faults: Vec::new(),
+            unsynced: None,
+            stats: Arc::new(FaultyStorageStats::default())
+        }
+    }
+
+    #[inline]
+    /// Add fault to the script.
+    pub fn with_fault(mut self, fault: StorageFault) -> Self {
+        self.faults.push(fault);
+
+        self
+    }
+
+    #[inline]
+    /// Keep writes in memory until `StorageIO::sync` is called
+    /// so they can be dropped on crash.
+    pub fn with_unsynced_buffer(mut self) -> Self {
+        self.unsynced = Some(Vec::new());
+
+        self
+    }
+
+    #[inline]
+    /// Get inner IO.
+    pub const fn inner(&self) -> &T {
+        &self.io
+    }
+
+    #[inline]
+    /// Take inner IO. Unsynced writes are dropped.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+
+    #[inline]
+    /// Get shared statistics of the IO.
+    pub fn stats(&self) -> Arc<FaultyStorageStats> {
+        self.stats.clone()
+    }
+
+    /// Crash the IO immediately: drop unsynced writes
+    /// and ignore all the following ones.
+    pub fn crash(&mut self) {
+        self.stats.crashed.store(true, Ordering::Release);
+
+        if let Some(unsynced) = &mut self.unsynced {
+            unsynced.clear();
+        }
+    }
+
+    /// Store bytes in the inner IO or in the unsynced buffer.
+    fn store(&mut self, offset: u64, bytes: &[u8]) -> std::io::Result<()> {
+        match &mut self.unsynced {
+            Some(unsynced) => {
+                unsynced.push((offset, bytes.to_vec()));
+
+                Ok(())
+            }
+
+            None => self.io.write(offset, bytes)
+        }
+    }
+}
+
+impl<T: StorageIO> StorageIO for FaultyStorageIO<T> {
+    type Reader = T::Reader;
+
+    #[inline]
+    fn io(&mut self) -> &mut Self::Reader {
+        self.io.io()
+    }
+
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let read = self.stats.reads.fetch_add(1, Ordering::AcqRel);
+
+        let mut buf = self.io.read(offset, length)?;
+
+        // Apply unsynced writes on top of the stored bytes.
+        if let Some(unsynced) = &self.unsynced {
+            let end = offset + length as u64;
+
+            for (write_offset, bytes) in unsynced {
+                let write_end = write_offset + bytes.len() as u64;
+
+                if *write_offset < end && write_end > offset {
+                    let from = std::cmp::max(offset, *write_offset);
+                    let to = std::cmp::min(end, write_end);
+
+                    buf[(from - offset) as usize..(to - offset) as usize]
+                        .copy_from_slice(&bytes[(from - write_offset) as usize..(to - write_offset) as usize]);
+                }
+            }
+        }
+
+        for fault in &self.faults {
+            if let StorageFault::FlipBit { read: fault_read, bit } = fault {
+                if *fault_read == read && !buf.is_empty() {
+                    let bit = bit % (buf.len() * 8);
+
+                    buf[bit / 8] ^= 1 << (bit % 8);
+                }
+            }
+        }
+
+        Ok(buf)
+    }
+
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        let write = self.stats.writes.fetch_add(1, Ordering::AcqRel);
+
+        if self.stats.crashed() {
+            return Ok(());
+        }
+
+        for i in 0..self.faults.len() {
+            match self.faults[i] {
+                StorageFault::FailWrite(fault_write) if fault_write == write => {
+                    return Err(Error::other(format!("injected failure of write {write}")));
+                }
+
+                StorageFault::TearWrite { write: fault_write, bytes: n } if fault_write == write => {
+                    self.store(offset, &bytes[..n.min(bytes.len())])?;
+
+                    return Err(Error::new(ErrorKind::WriteZero, format!("injected tear of write {write}")));
+                }
+
+                StorageFault::Crash { write: fault_write, torn_bytes } if fault_write == write => {
+                    self.crash();
+
+                    // Torn bytes reached the disk right before the crash.
+                    if torn_bytes > 0 {
+                        self.io.write(offset, &bytes[..torn_bytes.min(bytes.len())])?;
+                    }
+
+                    return Ok(());
+                }
+
+                _ => ()
+            }
+        }
+
+        self.store(offset, bytes)
+    }
+
+    #[inline]
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let len = self.len()?;
+
+        self.write(len, bytes)
+    }
+
+    fn len(&mut self) -> std::io::Result<u64> {
+        let mut len = self.io.len()?;
+
+        if let Some(unsynced) = &self.unsynced {
+            for (offset, bytes) in unsynced {
+                len = std::cmp::max(len, offset + bytes.len() as u64);
+            }
+        }
+
+        Ok(len)
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        if let Some(unsynced) = &mut self.unsynced {
+            for (offset, bytes) in std::mem::take(unsynced) {
+                self.io.write(offset, bytes)?;
+            }
+        }
+
+        self.io.sync()
+    }
+
+    /// Discard the range of the inner IO. Unsynced writes
+    /// are cut so they're not written over the range.
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        if self.stats.crashed() {
+            return Ok(());
+        }
+
+        let end = offset.saturating_add(length);
+
+        if let Some(unsynced) = &mut self.unsynced {
+            *unsynced = std::mem::take(unsynced).into_iter()
+                .flat_map(|(write_offset, mut bytes)| {
+                    let write_end = write_offset + bytes.len() as u64;
+
+                    if write_end <= offset || write_offset >= end {
+                        return vec![(write_offset, bytes)];
+                    }
+
+                    let mut kept = Vec::with_capacity(2);
+
+                    if write_end > end {
+                        let tail = bytes.split_off((end - write_offset) as usize);
+
+                        kept.push((end, tail));
+                    }
+
+                    if write_offset < offset {
+                        bytes.truncate((offset - write_offset) as usize);
+
+                        kept.insert(0, (write_offset, bytes));
+                    }
+
+                    kept
+                })
+                .collect();
+        }
+
+        self.io.discard(offset, length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::Path;
+    use std::fs::File;
+
+    use crate::prelude::*;
+
+    use super::*;
+
+    const ENTRIES: u64 = 8;
+    const RECORDS: u64 = 16;
+
+    type FaultyFilesystem = (
+        FilesystemDriver<FaultyStorageIO<File>>,
+        Arc<FaultyStorageStats>,
+        std::thread::JoinHandle<anyhow::Result<()>>
+    );
+
+    fn open_fs(path: &Path, truncate: bool, fault: Option<StorageFault>, unsynced: bool) -> FaultyFilesystem {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(truncate)
+            .open(path)
+            .expect("Failed to open file");
+
+        let mut io = FaultyStorageIO::new(file);
+
+        if let Some(fault) = fault {
+            io = io.with_fault(fault);
+        }
+
+        if unsynced {
+            io = io.with_unsynced_buffer();
+        }
+
+        let stats = io.stats();
+
+        let mut fs = FilesystemDriver::new(io)
+            .expect("Failed to open filesystem");
+
+        let worker = fs.daemonize()
+            .expect("Failed to daemonize filesystem");
+
+        (fs, stats, worker)
+    }
+
+    /// Stop the filesystem and wait until its IO is closed.
+    fn close_fs(fs: FilesystemDriver<FaultyStorageIO<File>>, worker: std::thread::JoinHandle<anyhow::Result<()>>) {
+        fs.close().expect("Failed to close filesystem");
+
+        worker.join()
+            .expect("Filesystem worker panicked")
+            .expect("Filesystem worker failed");
+    }
+
+    fn create_page(fs: &FilesystemDriver<FaultyStorageIO<File>>) -> anyhow::Result<Page> {
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender })?;
+
+        Ok(response_receiver.recv()??)
+    }
+
+    /// Fill filesystem tree on page 0 and B-Tree on page 1.
+    fn workload(fs: &FilesystemDriver<FaultyStorageIO<File>>) -> anyhow::Result<()> {
+        let header = fs.read_header()?;
+
+        let tree_page = create_page(fs)?;
+        let btree_page = create_page(fs)?;
+
+        let mut tree = FilesystemTree::open(tree_page.into_book()?)?;
+        let btree = BTree64::new(btree_page.number(), header.page_size, fs.handler().clone());
+
+        for i in 1..=ENTRIES {
+            tree.insert_child::<1024>(FilesystemTree::ROOT_OFFSET, FilesystemEntry::new(i, 0))?;
+        }
+
+        fs.sync()?;
+
+        for i in 0..RECORDS {
+            let key = (i * 7919) % RECORDS;
+
+            btree.insert(&key.to_be_bytes(), seahash::hash(&key.to_be_bytes()).to_be_bytes())?;
+        }
+
+        fs.sync()?;
+
+        Ok(())
+    }
+
+    /// Verify invariants of the filesystem tree and B-Tree
+    /// stored in the image. Pages which weren't written
+    /// yet are read as empty.
+    fn check(fs: &FilesystemDriver<FaultyStorageIO<File>>, path: &Path) -> anyhow::Result<()> {
+        let header = fs.read_header()?;
+
+        let len = path.metadata()?.len();
+        let pages = len.saturating_sub(header.length() as u64) / (header.page_header_length() as u64 + header.page_size);
+
+        // Every linked entry must be written and chains must not have cycles.
+        let tree = FilesystemTree::open(Page::new(0, fs.handler().clone()).into_book()?)?;
+
+        let mut visited = HashSet::new();
+        let mut offset = tree.read(FilesystemTree::ROOT_OFFSET)?.child_addr;
+
+        while offset != 0 {
+            anyhow::ensure!(visited.insert(offset), "cycle in the entries tree at offset {offset}");
+
+            let entry = tree.read(offset)?;
+
+            anyhow::ensure!((1..=ENTRIES).contains(&entry.name), "unexpected entry at offset {offset} : {entry:?}");
+
+            offset = entry.sibling_addr;
+        }
+
+        // Every B-Tree record must be fully written, sorted within its page
+        // and reference existing pages without cycles.
+        let mut visited = HashSet::new();
+        let mut queue = vec![1];
+
+        while let Some(page_number) = queue.pop() {
+            anyhow::ensure!(page_number == 1 || page_number < pages as u32, "B-Tree references missing page {page_number}");
+            anyhow::ensure!(visited.insert(page_number), "cycle in the B-Tree at page {page_number}");
+
+            let page = Page::new(page_number, fs.handler().clone()).read(0, header.page_size)?;
+
+            let mut page = page.as_slice();
+            let mut prev_key = None;
+
+            while let Some((record, remaining)) = BTreeRecord64::from_bytes(page) {
+                page = remaining;
+
+                let Some(key) = record.key else {
+                    break;
+                };
+
+                anyhow::ensure!(record.value == Some(seahash::hash(&key).to_be_bytes()), "invalid value of key {key:?}");
+                anyhow::ensure!(prev_key < Some(key), "unsorted keys on page {page_number}");
+
+                prev_key = Some(key);
+
+                queue.extend(record.left_addr);
+                queue.extend(record.right_addr);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn faults() {
+        let mut io = FaultyStorageIO::new(MemoryStorageIO::new())
+            .with_fault(StorageFault::FailWrite(1))
+            .with_fault(StorageFault::TearWrite { write: 2, bytes: 2 })
+            .with_fault(StorageFault::FlipBit { read: 0, bit: 9 });
+
+        io.write(0, [1, 2, 3, 4]).unwrap();
+
+        assert!(io.write(0, [5, 6, 7, 8]).is_err());
+        assert!(io.write(0, [9, 9, 9, 9]).is_err());
+
+        assert_eq!(io.read(0, 4).unwrap(), &[9, 11, 3, 4]);
+        assert_eq!(io.read(0, 4).unwrap(), &[9, 9, 3, 4]);
+
+        let stats = io.stats();
+
+        assert_eq!(stats.reads(), 2);
+        assert_eq!(stats.writes(), 3);
+    }
+
+    #[test]
+    fn unsynced_crash() {
+        let mut io = FaultyStorageIO::new(MemoryStorageIO::new())
+            .with_unsynced_buffer();
+
+        io.write(0, [1, 2, 3, 4]).unwrap();
+        io.sync().unwrap();
+
+        io.write(2, [5, 6, 7, 8]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 6);
+        assert_eq!(io.read(0, 6).unwrap(), &[1, 2, 5, 6, 7, 8]);
+        assert_eq!(io.inner().as_bytes(), &[1, 2, 3, 4]);
+
+        io.crash();
+        io.write(0, [0]).unwrap();
+
+        assert!(io.stats().crashed());
+        assert_eq!(io.into_inner().into_bytes(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn crash_at_every_write() {
+        let path = std::env::temp_dir().join(".animefs-fault-io-test-crash");
+
+        // Count writes of the workload without faults.
+        let (fs, stats, worker) = open_fs(&path, true, None, false);
+
+        workload(&fs).unwrap();
+        close_fs(fs, worker);
+
+        let writes = stats.writes();
+
+        let (fs, _, worker) = open_fs(&path, false, None, false);
+
+        check(&fs, &path).unwrap();
+        close_fs(fs, worker);
+
+        // Crash with writes stored immediately, and with unsynced writes
+        // kept in memory and dropped by the crash.
+        for unsynced in [false, true] {
+            for write in 1..writes {
+                for torn_bytes in [0, 4] {
+                    let (fs, stats, worker) = open_fs(&path, true, Some(StorageFault::Crash { write, torn_bytes }), unsynced);
+
+                    // Workload keeps running after the crash but its writes are dropped.
+                    let _ = workload(&fs);
+
+                    assert!(stats.crashed());
+
+                    close_fs(fs, worker);
+
+                    let (fs, _, worker) = open_fs(&path, false, None, false);
+
+                    check(&fs, &path).unwrap_or_else(|err| {
+                        panic!("Invariant violated after crash at write {write} ({torn_bytes} torn bytes, unsynced: {unsynced}) : {err}");
+                    });
+
+                    close_fs(fs, worker);
+                }
+            }
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
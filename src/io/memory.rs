@@ -0,0 +1,223 @@
+use std::io::{Cursor, Error, ErrorKind};
+
+use super::prelude::*;
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+/// Storage IO that keeps all the bytes in a growable
+/// memory buffer.
+///
+/// Useful for tests and ephemeral filesystems. The whole
+/// storage can be snapshotted by cloning the struct.
+pub struct MemoryStorageIO {
+    cursor: Cursor<Vec<u8>>,
+    limit: Option<u64>
+}
+
+impl MemoryStorageIO {
+    #[inline]
+    /// Create new empty memory storage without size limit.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    /// Create new empty memory storage which can't
+    /// grow larger than the given amount of bytes.
+    pub fn with_limit(limit: u64) -> Self {
+        Self {
+            cursor: Cursor::default(),
+            limit: Some(limit)
+        }
+    }
+
+    #[inline]
+    /// Create memory storage with the given content.
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        Self {
+            cursor: Cursor::new(bytes.into()),
+            limit: None
+        }
+    }
+
+    #[inline]
+    /// Get size limit of the storage.
+    pub const fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    #[inline]
+    /// Change size limit of the storage.
+    ///
+    /// Already stored bytes are not truncated if they're
+    /// larger than the new limit.
+    pub fn set_limit(&mut self, limit: Option<u64>) {
+        self.limit = limit;
+    }
+
+    #[inline]
+    /// Get stored bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.cursor.get_ref()
+    }
+
+    #[inline]
+    /// Take stored bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.cursor.into_inner()
+    }
+
+    /// Return error if the storage can't be extended
+    /// to the given length.
+    fn reserve(&self, len: u64) -> std::io::Result<()> {
+        match self.limit {
+            Some(limit) if len > limit => Err(Error::new(
+                ErrorKind::StorageFull,
+                format!("memory storage is limited to {limit} bytes while {len} requested")
+            )),
+
+            _ => Ok(())
+        }
+    }
+}
+
+impl StorageIO for MemoryStorageIO {
+    type Reader = Cursor<Vec<u8>>;
+
+    #[inline]
+    fn io(&mut self) -> &mut Self::Reader {
+        &mut self.cursor
+    }
+
+    #[inline]
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        self.read_at(offset, length)
+    }
+
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        let end = offset.saturating_add(bytes.len() as u64);
+
+        self.reserve(end)?;
+
+        let offset = usize::try_from(offset)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+
+        let buf = self.cursor.get_mut();
+
+        if buf.len() < offset + bytes.len() {
+            buf.resize(offset + bytes.len(), 0);
+        }
+
+        buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        self.reserve(self.cursor.get_ref().len() as u64 + bytes.len() as u64)?;
+
+        self.cursor.get_mut().extend_from_slice(bytes);
+
+        Ok(())
+    }
+
+    #[inline]
+    fn len(&mut self) -> std::io::Result<u64> {
+        self.len_at()
+    }
+
+    /// Fill the range with zeros.
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        let buf = self.cursor.get_mut();
+
+        let len = buf.len() as u64;
+
+        let start = std::cmp::min(offset, len) as usize;
+        let end = std::cmp::min(offset.saturating_add(length), len) as usize;
+
+        buf[start..end].fill(0);
+
+        Ok(())
+    }
+}
+
+impl PositionalStorageIO for MemoryStorageIO {
+    fn read_at(&self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; length];
+
+        let bytes = self.cursor.get_ref();
+
+        if let Ok(offset) = usize::try_from(offset) {
+            if offset < bytes.len() {
+                let n = std::cmp::min(bytes.len() - offset, length);
+
+                buf[..n].copy_from_slice(&bytes[offset..offset + n]);
+            }
+        }
+
+        Ok(buf)
+    }
+
+    #[inline]
+    fn len_at(&self) -> std::io::Result<u64> {
+        Ok(self.cursor.get_ref().len() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn read_write() {
+        let mut io = MemoryStorageIO::new();
+
+        assert!(io.is_empty().unwrap());
+        assert_eq!(io.read(0, 4).unwrap(), &[0, 0, 0, 0]);
+
+        io.write(0, [1, 2, 3, 4]).unwrap();
+        io.write(8, [5, 6, 7, 8]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 12);
+        assert_eq!(io.read(0, 16).unwrap(), &[1, 2, 3, 4, 0, 0, 0, 0, 5, 6, 7, 8, 0, 0, 0, 0]);
+
+        io.write(2, [9, 8, 7, 6, 5, 4, 3, 2]).unwrap();
+        io.append([1, 2]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 14);
+        assert_eq!(io.as_bytes(), &[1, 2, 9, 8, 7, 6, 5, 4, 3, 2, 7, 8, 1, 2]);
+    }
+
+    #[test]
+    fn limit() {
+        let mut io = MemoryStorageIO::with_limit(8);
+
+        io.write(0, [1, 2, 3, 4]).unwrap();
+        io.append([5, 6, 7, 8]).unwrap();
+
+        assert_eq!(io.write(6, [0, 0, 0]).unwrap_err().kind(), ErrorKind::StorageFull);
+        assert_eq!(io.append([9]).unwrap_err().kind(), ErrorKind::StorageFull);
+
+        assert_eq!(io.into_bytes(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn filesystem() {
+        let mut fs = FilesystemDriver::new(MemoryStorageIO::new())
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        fs.write_header(FilesystemHeader {
+            page_size: 123,
+            ..FilesystemHeader::default()
+        }).unwrap();
+
+        assert_eq!(fs.read_header().unwrap().page_size, 123);
+    }
+}
@@ -0,0 +1,662 @@
+use std::io::{Error, ErrorKind};
+
+use super::prelude::*;
+
+/// Callback which checks bytes read from the given offset.
+pub type MirrorVerifier = Box<dyn Fn(u64, &[u8]) -> bool + Send + Sync>;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Statistics of the mirrored storage IO.
+pub struct MirrorStats {
+    /// Amount of reads which failed or didn't pass verification.
+    pub read_errors: u64,
+
+    /// Amount of failed writes to the mirrors.
+    pub write_errors: u64,
+
+    /// Amount of ranges rewritten with bytes from another mirror.
+    pub repairs: u64
+}
+
+/// Storage IO which stores the same bytes on two or more
+/// inner storages (RAID1).
+///
+/// Writes go to all the mirrors. Without a verifier reads go to all
+/// the healthy mirrors and their results are compared: bytes returned
+/// by the most mirrors are used, and reads fail if there's no such
+/// bytes. With a verifier reads are served by the first healthy mirror,
+/// and if the read fails or its result is rejected by the verifier -
+/// the next mirror is used. In both cases the correct bytes are written
+/// back to the failed mirrors (read repair).
+///
+/// `FilesystemHeader::verify_page` can be used as the verifier
+/// of the filesystems with page checksums.
+///
+/// Mirrors which failed a write are marked as degraded and are
+/// not used for reads until `MirrorStorageIO::resync` is called.
+///
+/// Every mirror starts with a header storing the mirror epoch,
+/// which is increased and synced to the healthy mirrors when
+/// another one becomes degraded. Mirrors with an outdated epoch
+/// are marked as degraded when the storages are opened again.
+///
+/// ```text
+/// [magic: 8 bytes][epoch: u64][mirrored bytes...]
+/// ```
+pub struct MirrorStorageIO<T> {
+    mirrors: Vec<T>,
+    degraded: Vec<bool>,
+    epoch: u64,
+    verifier: Option<MirrorVerifier>,
+    stats: MirrorStats
+}
+
+impl<T: StorageIO> MirrorStorageIO<T> {
+    pub const MAGIC: [u8; 8] = *b"animefsm";
+
+    /// Length of the header stored
+    /// at the beginning of every mirror.
+    pub const HEADER_LENGTH: u64 = 16;
+
+    /// Mirror bytes between the given storages.
+    ///
+    /// Empty storages get the mirror header. Storages with
+    /// an outdated epoch, or empty ones when others aren't,
+    /// are marked as degraded.
+    pub fn new(mut mirrors: Vec<T>) -> std::io::Result<Self> {
+        if mirrors.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "at least one mirror is required"));
+        }
+
+        let epochs = mirrors.iter_mut()
+            .map(Self::read_epoch)
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        if epochs.iter().all(Option::is_none) {
+            for mirror in &mut mirrors {
+                Self::write_epoch(mirror, 0)?;
+            }
+        }
+
+        let epoch = epochs.iter()
+            .flatten()
+            .max()
+            .copied()
+            .unwrap_or_default();
+
+        let degraded = epochs.iter()
+            .map(|mirror_epoch| match mirror_epoch {
+                Some(mirror_epoch) => *mirror_epoch < epoch,
+                None => epochs.iter().any(Option::is_some)
+            })
+            .collect();
+
+        Ok(Self {
+            degraded,
+            epoch,
+            mirrors,
+            verifier: None,
+            stats: MirrorStats::default()
+        })
+    }
+
+    /// Read epoch from the header of the given mirror,
+    /// or return `None` if the mirror is empty.
+    fn read_epoch(mirror: &mut T) -> std::io::Result<Option<u64>> {
+        if mirror.len()? < Self::HEADER_LENGTH {
+            return Ok(None);
+        }
+
+        let header = mirror.read(0, Self::HEADER_LENGTH as usize)?;
+
+        if header[..8] != Self::MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "storage doesn't have the mirror header"));
+        }
+
+        let mut epoch = [0; 8];
+
+        epoch.copy_from_slice(&header[8..]);
+
+        Ok(Some(u64::from_le_bytes(epoch)))
+    }
+
+    /// Write header with given epoch to the mirror.
+    fn write_epoch(mirror: &mut T, epoch: u64) -> std::io::Result<()> {
+        let mut header = Self::MAGIC.to_vec();
+
+        header.extend_from_slice(&epoch.to_le_bytes());
+
+        mirror.write(0, header)
+    }
+
+    #[inline]
+    /// Get current epoch of the healthy mirrors.
+    pub const fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    #[inline]
+    /// Check read bytes with the given callback. It receives offset
+    /// and bytes of the read and returns `false` if they're corrupted.
+    pub fn with_verifier(mut self, verifier: impl Fn(u64, &[u8]) -> bool + Send + Sync + 'static) -> Self {
+        self.verifier = Some(Box::new(verifier));
+
+        self
+    }
+
+    #[inline]
+    /// Get inner storages.
+    pub fn mirrors(&self) -> &[T] {
+        &self.mirrors
+    }
+
+    #[inline]
+    /// Get mutable inner storages.
+    pub fn mirrors_mut(&mut self) -> &mut [T] {
+        &mut self.mirrors
+    }
+
+    #[inline]
+    /// Take inner storages.
+    pub fn into_mirrors(self) -> Vec<T> {
+        self.mirrors
+    }
+
+    #[inline]
+    /// Check if the mirror with given index failed a write.
+    pub fn is_degraded(&self, mirror: usize) -> bool {
+        self.degraded.get(mirror).copied().unwrap_or_default()
+    }
+
+    #[inline]
+    pub const fn stats(&self) -> MirrorStats {
+        self.stats
+    }
+
+    /// Mark given mirrors as degraded and store new
+    /// epoch on the healthy ones, so they're detected
+    /// as degraded after the storages are opened again.
+    fn degrade(&mut self, mut mirrors: Vec<usize>) {
+        while !mirrors.is_empty() {
+            for i in mirrors.drain(..) {
+                self.stats.write_errors += 1;
+                self.degraded[i] = true;
+            }
+
+            self.epoch += 1;
+
+            for i in self.healthy() {
+                let mirror = &mut self.mirrors[i];
+
+                if Self::write_epoch(mirror, self.epoch).and_then(|_| mirror.sync()).is_err() {
+                    mirrors.push(i);
+                }
+            }
+        }
+    }
+
+    /// Copy the whole content of the first healthy mirror,
+    /// including its header, to the degraded ones and mark
+    /// them healthy.
+    pub fn resync(&mut self) -> std::io::Result<()> {
+        let Some(source) = self.degraded.iter().position(|degraded| !degraded) else {
+            return Err(Error::other("all the mirrors are degraded"));
+        };
+
+        let len = self.mirrors[source].len()?;
+
+        for i in 0..self.mirrors.len() {
+            if !self.degraded[i] {
+                continue;
+            }
+
+            // Header is written after the bytes are synced so the
+            // mirror stays outdated if the resync is interrupted.
+            let mut offset = Self::HEADER_LENGTH;
+
+            while offset < len {
+                let n = std::cmp::min(len - offset, 1024 * 1024) as usize;
+
+                let bytes = self.mirrors[source].read(offset, n)?;
+
+                self.mirrors[i].write(offset, bytes)?;
+
+                offset += n as u64;
+            }
+
+            self.mirrors[i].sync()?;
+
+            Self::write_epoch(&mut self.mirrors[i], self.epoch)?;
+
+            self.mirrors[i].sync()?;
+
+            self.degraded[i] = false;
+        }
+
+        Ok(())
+    }
+
+    /// Get indices of the mirrors which can be used for reads.
+    fn healthy(&self) -> Vec<usize> {
+        (0..self.mirrors.len())
+            .filter(|i| !self.degraded[*i])
+            .collect()
+    }
+
+    /// Write correct bytes back to the failed mirrors.
+    fn repair(&mut self, failed: Vec<usize>, offset: u64, bytes: &[u8]) {
+        let mut degraded = Vec::new();
+
+        for i in failed {
+            if self.mirrors[i].write(Self::HEADER_LENGTH + offset, bytes).is_ok() {
+                self.stats.repairs += 1;
+            } else {
+                degraded.push(i);
+            }
+        }
+
+        self.degrade(degraded);
+    }
+
+    /// Read bytes from all the healthy mirrors
+    /// and use the ones returned by the most of them.
+    fn read_compared(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let mut reads: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut failed = Vec::new();
+        let mut last_error = None;
+
+        for i in self.healthy() {
+            match self.mirrors[i].read(Self::HEADER_LENGTH + offset, length) {
+                Ok(bytes) => reads.push((i, bytes)),
+
+                Err(err) => {
+                    self.stats.read_errors += 1;
+
+                    failed.push(i);
+
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        // Find bytes returned by the most mirrors. They're ambiguous if
+        // different bytes were returned by the same amount of mirrors.
+        let mut best: Option<(&Vec<u8>, usize)> = None;
+        let mut ambiguous = false;
+
+        for (_, bytes) in &reads {
+            let votes = reads.iter()
+                .filter(|(_, other)| other == bytes)
+                .count();
+
+            match best {
+                Some((_, best_votes)) if votes < best_votes => (),
+                Some((best_bytes, best_votes)) if votes == best_votes => ambiguous |= best_bytes != bytes,
+
+                _ => {
+                    best = Some((bytes, votes));
+                    ambiguous = false;
+                }
+            }
+        }
+
+        let Some((bytes, _)) = best else {
+            return Err(last_error.unwrap_or_else(|| Error::other("all the mirrors are degraded")));
+        };
+
+        if ambiguous {
+            self.stats.read_errors += reads.len() as u64;
+
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("mirrors returned different bytes from offset {offset}")
+            ));
+        }
+
+        let bytes = bytes.clone();
+
+        for (i, mirror_bytes) in &reads {
+            if mirror_bytes != &bytes {
+                self.stats.read_errors += 1;
+
+                failed.push(*i);
+            }
+        }
+
+        self.repair(failed, offset, &bytes);
+
+        Ok(bytes)
+    }
+}
+
+impl<T> std::fmt::Debug for MirrorStorageIO<T> where T: std::fmt::Debug {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MirrorStorageIO")
+            .field("mirrors", &self.mirrors)
+            .field("degraded", &self.degraded)
+            .field("epoch", &self.epoch)
+            .field("verifier", &self.verifier.is_some())
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl<T: StorageIO> StorageIO for MirrorStorageIO<T> {
+    type Reader = T::Reader;
+
+    #[inline]
+    /// Get reader of the first mirror.
+    fn io(&mut self) -> &mut Self::Reader {
+        self.mirrors[0].io()
+    }
+
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let Some(verifier) = &self.verifier else {
+            return self.read_compared(offset, length);
+        };
+
+        let mut failed: Vec<usize> = Vec::new();
+        let mut last_error = None;
+
+        for i in self.healthy() {
+            match self.mirrors[i].read(Self::HEADER_LENGTH + offset, length) {
+                Ok(bytes) if verifier(offset, &bytes) => {
+                    self.repair(failed, offset, &bytes);
+
+                    return Ok(bytes);
+                }
+
+                Ok(_) => {
+                    last_error = Some(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("bytes read from offset {offset} didn't pass verification on all the mirrors")
+                    ));
+                }
+
+                Err(err) => last_error = Some(err)
+            }
+
+            self.stats.read_errors += 1;
+
+            failed.push(i);
+        }
+
+        Err(last_error.unwrap_or_else(|| Error::other("all the mirrors are degraded")))
+    }
+
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        let mut written = false;
+        let mut failed = Vec::new();
+        let mut last_error = None;
+
+        for i in self.healthy() {
+            match self.mirrors[i].write(Self::HEADER_LENGTH + offset, bytes) {
+                Ok(()) => written = true,
+
+                Err(err) => {
+                    failed.push(i);
+
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        self.degrade(failed);
+
+        match last_error {
+            Some(err) if !written => Err(err),
+            _ => Ok(())
+        }
+    }
+
+    #[inline]
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        // Write to the same offset on all the mirrors
+        // even if their lengths are different.
+        let len = self.len()?;
+
+        self.write(len, bytes)
+    }
+
+    fn len(&mut self) -> std::io::Result<u64> {
+        let mut last_error = None;
+
+        for i in self.healthy() {
+            match self.mirrors[i].len() {
+                Ok(len) => return Ok(len.saturating_sub(Self::HEADER_LENGTH)),
+                Err(err) => last_error = Some(err)
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| Error::other("all the mirrors are degraded")))
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        let mut synced = false;
+        let mut failed = Vec::new();
+        let mut last_error = None;
+
+        for i in self.healthy() {
+            match self.mirrors[i].sync() {
+                Ok(()) => synced = true,
+
+                Err(err) => {
+                    failed.push(i);
+
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        self.degrade(failed);
+
+        match last_error {
+            Some(err) if !synced => Err(err),
+            _ => Ok(())
+        }
+    }
+
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        let mut discarded = false;
+        let mut failed = Vec::new();
+        let mut last_error = None;
+
+        for i in self.healthy() {
+            match self.mirrors[i].discard(Self::HEADER_LENGTH + offset, length) {
+                Ok(()) => discarded = true,
+
+                Err(err) => {
+                    failed.push(i);
+
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        self.degrade(failed);
+
+        match last_error {
+            Some(err) if !discarded => Err(err),
+            _ => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use crate::prelude::*;
+
+    use super::*;
+
+    const HEADER_LENGTH: usize = MirrorStorageIO::<MemoryStorageIO>::HEADER_LENGTH as usize;
+
+    #[test]
+    fn read_repair() {
+        let mut io = MirrorStorageIO::new(vec![MemoryStorageIO::new(), MemoryStorageIO::new(), MemoryStorageIO::new()]).unwrap();
+
+        io.write(0, [1, 2, 3, 4]).unwrap();
+        io.append([5, 6]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 6);
+
+        // Corrupt the first mirror.
+        io.mirrors_mut()[0].write(HEADER_LENGTH as u64 + 2, [0xFF]).unwrap();
+
+        assert_eq!(io.read(0, 6).unwrap(), &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(&io.mirrors()[0].as_bytes()[HEADER_LENGTH..], &[1, 2, 3, 4, 5, 6]);
+
+        // Corrupt two mirrors so all of them disagree.
+        io.mirrors_mut()[0].write(HEADER_LENGTH as u64, [0xFF]).unwrap();
+        io.mirrors_mut()[1].write(HEADER_LENGTH as u64, [0xFE]).unwrap();
+
+        assert_eq!(io.read(0, 2).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        let stats = io.stats();
+
+        assert_eq!(stats.read_errors, 4);
+        assert_eq!(stats.repairs, 1);
+    }
+
+    #[test]
+    fn degraded() {
+        // Write 0 stores the mirror header.
+        let mirrors = vec![
+            FaultyStorageIO::new(MemoryStorageIO::new()).with_fault(StorageFault::FailWrite(2)),
+            FaultyStorageIO::new(MemoryStorageIO::new())
+        ];
+
+        let mut io = MirrorStorageIO::new(mirrors).unwrap();
+
+        io.write(0, [1, 2]).unwrap();
+        io.write(2, [3, 4]).unwrap();
+        io.write(4, [5, 6]).unwrap();
+
+        assert!(io.is_degraded(0));
+        assert!(!io.is_degraded(1));
+        assert_eq!(io.epoch(), 1);
+
+        assert_eq!(io.stats().write_errors, 1);
+        assert_eq!(io.read(0, 6).unwrap(), &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(&io.mirrors()[0].inner().as_bytes()[HEADER_LENGTH..], &[1, 2]);
+
+        io.resync().unwrap();
+
+        assert!(!io.is_degraded(0));
+        assert_eq!(io.mirrors()[0].inner().as_bytes(), io.mirrors()[1].inner().as_bytes());
+    }
+
+    #[test]
+    fn reopen_degraded() {
+        let mut io = MirrorStorageIO::new(vec![MemoryStorageIO::new(), MemoryStorageIO::new()]).unwrap();
+
+        io.write(0, [1, 2, 3, 4]).unwrap();
+
+        let mut mirrors = io.into_mirrors()
+            .into_iter()
+            .map(FaultyStorageIO::new)
+            .collect::<Vec<_>>();
+
+        // First mirror fails the next write.
+        mirrors[0] = FaultyStorageIO::new(mirrors[0].inner().clone()).with_fault(StorageFault::FailWrite(0));
+
+        let mut io = MirrorStorageIO::new(mirrors).unwrap();
+
+        io.write(0, [5, 6, 7, 8]).unwrap();
+
+        assert!(io.is_degraded(0));
+
+        // Stale mirror is still degraded after reopening, so its
+        // bytes are not served even if they pass verification.
+        let mirrors = io.into_mirrors()
+            .into_iter()
+            .map(|mirror| mirror.inner().clone())
+            .collect::<Vec<_>>();
+
+        let mut io = MirrorStorageIO::new(mirrors).unwrap()
+            .with_verifier(|_, _| true);
+
+        assert!(io.is_degraded(0));
+        assert!(!io.is_degraded(1));
+        assert_eq!(io.read(0, 4).unwrap(), &[5, 6, 7, 8]);
+
+        io.resync().unwrap();
+
+        let mut io = MirrorStorageIO::new(io.into_mirrors()).unwrap();
+
+        assert!(!io.is_degraded(0));
+        assert_eq!(io.read(0, 4).unwrap(), &[5, 6, 7, 8]);
+
+        // Storages without the mirror header are rejected.
+        let err = MirrorStorageIO::new(vec![MemoryStorageIO::from_bytes([1; 32])]).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn filesystem() {
+        let paths = [
+            std::env::temp_dir().join(".animefs-mirror-io-test-1"),
+            std::env::temp_dir().join(".animefs-mirror-io-test-2")
+        ];
+
+        let files = paths.iter()
+            .map(|path| {
+                File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)
+                    .expect("Failed to open file")
+            })
+            .collect();
+
+        let mut fs = FilesystemDriver::with_page_checksums(MirrorStorageIO::new(files).unwrap(), Checksum::Xxh3)
+            .expect("Failed to open filesystem");
+
+        let worker = fs.daemonize().unwrap();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
+
+        page.write(0, vec![1; 16]).unwrap();
+
+        let header = fs.read_header().unwrap();
+
+        fs.close().unwrap();
+        worker.join().unwrap().unwrap();
+
+        // First disk went bad.
+        let offset = HEADER_LENGTH as u64 + header.length() as u64 + header.page_header_length() as u64 + 4;
+
+        File::options().write(true).open(&paths[0]).unwrap().write(offset, [0xFF]).unwrap();
+
+        let files = paths.iter()
+            .map(|path| File::options().read(true).write(true).open(path).unwrap())
+            .collect();
+
+        let io = MirrorStorageIO::new(files).unwrap()
+            .with_verifier(move |offset, bytes| header.verify_page(offset, bytes));
+
+        let mut fs = FilesystemDriver::new(io)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let page = Page::new(page.number(), fs.handler().clone());
+
+        assert_eq!(page.read(0, 16).unwrap(), vec![1; 16]);
+        assert_eq!(std::fs::read(&paths[0]).unwrap(), std::fs::read(&paths[1]).unwrap());
+
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+
+use memmap2::{MmapMut, MmapOptions};
+
+use super::prelude::*;
+
+#[derive(Debug)]
+/// Storage IO that memory maps the underlying file.
+///
+/// Reads and writes are plain memory copies without any
+/// seek syscalls. When a write or append goes beyond the end
+/// of the mapping - the file is mapped again with at least twice
+/// larger address space. The file itself is extended only to the
+/// written length, so it never contains bytes which weren't written.
+///
+/// **WARNING**: the file must not be modified by other processes
+/// (or through the `StorageIO::io` method) while it's mapped.
+pub struct MmapStorageIO {
+    file: File,
+    mmap: Option<MmapMut>,
+
+    /// Length of the file. Mapping can be larger.
+    len: u64,
+
+    /// Whether the file was extended since the last sync.
+    resized: bool
+}
+
+impl MmapStorageIO {
+    /// Memory map given file.
+    ///
+    /// File must be opened with both read and write access.
+    pub fn new(file: File) -> std::io::Result<Self> {
+        let len = file.metadata()?.len();
+
+        let mut io = Self {
+            file,
+            mmap: None,
+            len,
+            resized: false
+        };
+
+        io.remap(len)?;
+
+        Ok(io)
+    }
+
+    #[inline]
+    /// Get reference to the mapped file.
+    pub const fn file(&self) -> &File {
+        &self.file
+    }
+
+    #[inline]
+    /// Get size of the mapping.
+    pub fn capacity(&self) -> u64 {
+        self.mmap.as_ref()
+            .map(|mmap| mmap.len() as u64)
+            .unwrap_or_default()
+    }
+
+    /// Map the file again with the given capacity.
+    ///
+    /// Mapping can be larger than the file. Its part
+    /// beyond the end of the file must not be accessed.
+    fn remap(&mut self, capacity: u64) -> std::io::Result<()> {
+        let capacity = usize::try_from(capacity)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "mapping capacity doesn't fit the address space"))?;
+
+        self.mmap.take();
+
+        if capacity > 0 {
+            // Safety: file can't be changed by this process outside of this
+            // struct, and other processes are asked not to do it either.
+            self.mmap = Some(unsafe {
+                MmapOptions::new()
+                    .len(capacity)
+                    .map_mut(&self.file)?
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl StorageIO for MmapStorageIO {
+    type Reader = File;
+
+    #[inline]
+    fn io(&mut self) -> &mut Self::Reader {
+        &mut self.file
+    }
+
+    #[inline]
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        self.read_at(offset, length)
+    }
+
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        if bytes.is_empty() {
+            return Ok(());
+        }
+
+        let start = usize::try_from(offset)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "write offset doesn't fit the address space"))?;
+
+        let end = start.checked_add(bytes.len())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "write end doesn't fit the address space"))?;
+
+        let capacity = self.capacity();
+
+        if end as u64 > capacity {
+            self.remap(std::cmp::max(end as u64, capacity.saturating_mul(2)))?;
+        }
+
+        // Extend the file before touching the mapping
+        // because its part beyond the file is not accessible.
+        if end as u64 > self.len {
+            self.file.set_len(end as u64)?;
+
+            self.len = end as u64;
+            self.resized = true;
+        }
+
+        if let Some(mmap) = &mut self.mmap {
+            mmap[start..end].copy_from_slice(bytes);
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        self.write(self.len, bytes)
+    }
+
+    #[inline]
+    fn len(&mut self) -> std::io::Result<u64> {
+        Ok(self.len)
+    }
+
+    /// Flush changed memory pages and the new
+    /// length of the file to the disk.
+    fn sync(&mut self) -> std::io::Result<()> {
+        if let Some(mmap) = &self.mmap {
+            if self.len > 0 {
+                // len <= capacity <= usize::MAX because the file is mapped.
+                mmap.flush_range(0, self.len as usize)?;
+            }
+        }
+
+        if self.resized {
+            self.file.sync_data()?;
+
+            self.resized = false;
+        }
+
+        Ok(())
+    }
+
+    /// Discard the range of the mapped file. Mapping
+    /// is shared so it reflects the discarded range.
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        if offset >= self.len {
+            return Ok(());
+        }
+
+        let length = std::cmp::min(length, self.len - offset);
+
+        self.file.discard(offset, length)
+    }
+}
+
+impl PositionalStorageIO for MmapStorageIO {
+    fn read_at(&self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; length];
+
+        if let Some(mmap) = &self.mmap {
+            if offset < self.len {
+                // offset < len <= usize::MAX because the file is mapped.
+                let offset = offset as usize;
+                let n = std::cmp::min(self.len as usize - offset, length);
+
+                buf[..n].copy_from_slice(&mmap[offset..offset + n]);
+            }
+        }
+
+        Ok(buf)
+    }
+
+    #[inline]
+    fn len_at(&self) -> std::io::Result<u64> {
+        Ok(self.len)
+    }
+}
+
+impl Drop for MmapStorageIO {
+    #[inline]
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_io(name: &str) -> (MmapStorageIO, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!(".animefs-mmap-io-test-{name}"));
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .expect("Failed to open file");
+
+        (MmapStorageIO::new(file).expect("Failed to map file"), path)
+    }
+
+    #[test]
+    fn read_write() {
+        let (mut io, path) = get_io("read-write");
+
+        assert!(io.is_empty().unwrap());
+        assert_eq!(io.read(0, 4).unwrap(), &[0, 0, 0, 0]);
+
+        io.write(0, [1, 2, 3, 4]).unwrap();
+        io.write(8, [5, 6, 7, 8]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 12);
+        assert_eq!(io.read(0, 16).unwrap(), &[1, 2, 3, 4, 0, 0, 0, 0, 5, 6, 7, 8, 0, 0, 0, 0]);
+
+        io.write(2, [9, 8, 7, 6, 5, 4, 3, 2]).unwrap();
+
+        assert_eq!(io.read(0, 12).unwrap(), &[1, 2, 9, 8, 7, 6, 5, 4, 3, 2, 7, 8]);
+
+        io.sync().unwrap();
+
+        assert_eq!(path.metadata().unwrap().len(), 12);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn append() {
+        let (mut io, path) = get_io("append");
+
+        io.append([1, 2]).unwrap();
+
+        assert_eq!(io.read(0, 4).unwrap(), &[1, 2, 0, 0]);
+
+        io.append([3, 4]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 4);
+        assert_eq!(io.read(0, 4).unwrap(), &[1, 2, 3, 4]);
+
+        drop(io);
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        let mut io = MmapStorageIO::new(file).unwrap();
+
+        assert_eq!(io.len().unwrap(), 4);
+        assert_eq!(io.read(0, 4).unwrap(), &[1, 2, 3, 4]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn grow() {
+        let (mut io, path) = get_io("grow");
+
+        for i in 0..16 {
+            io.append([i; 10]).unwrap();
+        }
+
+        // Mapping is doubled instead of growing by every append.
+        assert_eq!(io.len().unwrap(), 160);
+        assert_eq!(io.capacity(), 160);
+
+        io.append([16]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 161);
+        assert_eq!(io.capacity(), 320);
+        assert_eq!(io.read(150, 16).unwrap(), &[15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 16, 0, 0, 0, 0, 0]);
+
+        // File is never extended beyond the written bytes
+        // so a crash before sync doesn't leave a zero tail.
+        assert_eq!(path.metadata().unwrap().len(), 161);
+
+        // Capacity is kept across syncs.
+        io.sync().unwrap();
+
+        assert_eq!(io.capacity(), 320);
+
+        drop(io);
+
+        assert_eq!(path.metadata().unwrap().len(), 161);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
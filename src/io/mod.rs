@@ -1,7 +1,33 @@
 pub mod storage;
 pub mod buf;
+pub mod memory;
+pub mod mmap;
+pub mod split;
+pub mod fault;
+pub mod trace;
+pub mod overlay;
+pub mod mirror;
+pub mod stripe;
+pub mod stats;
+pub mod throttle;
+
+#[cfg(target_os = "linux")]
+pub mod uring;
 
 pub mod prelude {
     pub use super::storage::*;
     pub use super::buf::*;
+    pub use super::memory::*;
+    pub use super::mmap::*;
+    pub use super::split::*;
+    pub use super::fault::*;
+    pub use super::trace::*;
+    pub use super::overlay::*;
+    pub use super::mirror::*;
+    pub use super::stripe::*;
+    pub use super::stats::*;
+    pub use super::throttle::*;
+
+    #[cfg(target_os = "linux")]
+    pub use super::uring::*;
 }
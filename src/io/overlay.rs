@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+use super::prelude::*;
+
+#[derive(Debug)]
+/// Copy-on-write storage IO which reads from an immutable
+/// base storage and redirects all the writes to a delta storage.
+///
+/// Delta storage is split into blocks of a fixed size. When
+/// a block is modified for the first time - it's copied from
+/// the base to the end of the delta and all the following reads
+/// and writes of this block go to the delta.
+///
+/// Delta layout:
+///
+/// ```text
+/// [block_size: u64][len: u64]
+/// [block_number: u64][block: block_size bytes]
+/// [block_number: u64][block: block_size bytes]
+/// ...
+/// ```
+///
+/// Base storage is never written and can be opened in read-only mode.
+pub struct OverlayStorageIO<B, D> {
+    base: B,
+    delta: D,
+
+    block_size: u64,
+
+    /// Logical length of the storage.
+    len: u64,
+
+    /// Map of the block numbers to their slots in the delta.
+    blocks: HashMap<u64, u64>
+}
+
+impl<B: StorageIO, D: StorageIO> OverlayStorageIO<B, D> {
+    pub const HEADER_LENGTH: usize = 16;
+
+    /// Open overlay over the given base storage, restoring
+    /// the blocks map from the delta storage if it's not empty.
+    pub fn open(mut base: B, mut delta: D, block_size: u64) -> std::io::Result<Self> {
+        if block_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "block size must not be zero"));
+        }
+
+        let mut blocks = HashMap::new();
+
+        let delta_len = delta.len()?;
+
+        let len = if delta_len < Self::HEADER_LENGTH as u64 {
+            let len = base.len()?;
+
+            delta.write(0, [block_size.to_le_bytes(), len.to_le_bytes()].concat())?;
+
+            len
+        }
+
+        else {
+            let header = delta.read(0, Self::HEADER_LENGTH)?;
+
+            let delta_block_size = u64::from_le_bytes(header[..8].try_into().expect("Header slice must be 8 bytes long"));
+            let len = u64::from_le_bytes(header[8..].try_into().expect("Header slice must be 8 bytes long"));
+
+            if delta_block_size != block_size {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("delta storage has block size {delta_block_size} while {block_size} requested")
+                ));
+            }
+
+            let slots = (delta_len - Self::HEADER_LENGTH as u64) / (8 + block_size);
+
+            for slot in 0..slots {
+                let block = delta.read(Self::HEADER_LENGTH as u64 + slot * (8 + block_size), 8)?;
+                let block = u64::from_le_bytes(block.try_into().expect("Block number must be 8 bytes long"));
+
+                blocks.insert(block, slot);
+            }
+
+            len
+        };
+
+        Ok(Self {
+            base,
+            delta,
+
+            block_size,
+            len,
+            blocks
+        })
+    }
+
+    #[inline]
+    pub const fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    #[inline]
+    /// Get amount of blocks stored in the delta.
+    pub fn modified_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    #[inline]
+    /// Get base storage.
+    pub const fn base(&self) -> &B {
+        &self.base
+    }
+
+    #[inline]
+    /// Get delta storage.
+    pub const fn delta(&self) -> &D {
+        &self.delta
+    }
+
+    #[inline]
+    /// Take base and delta storages.
+    pub fn into_parts(self) -> (B, D) {
+        (self.base, self.delta)
+    }
+
+    /// Write the whole content of the overlay (base with
+    /// applied delta) to the given storage, making a new base image.
+    pub fn commit(&mut self, target: &mut impl StorageIO) -> std::io::Result<()> {
+        let mut offset = 0;
+
+        while offset < self.len {
+            let n = std::cmp::min(self.block_size, self.len - offset) as usize;
+
+            target.write(offset, self.read(offset, n)?)?;
+
+            offset += n as u64;
+        }
+
+        target.sync()
+    }
+
+    #[inline]
+    /// Get position of the block's body in the delta.
+    fn slot_pos(&self, slot: u64) -> u64 {
+        Self::HEADER_LENGTH as u64 + slot * (8 + self.block_size) + 8
+    }
+
+    /// Copy block from the base to the delta if it's
+    /// not there yet, returning its slot.
+    fn reserve_block(&mut self, block: u64) -> std::io::Result<u64> {
+        if let Some(slot) = self.blocks.get(&block) {
+            return Ok(*slot);
+        }
+
+        let slot = self.blocks.len() as u64;
+
+        let mut bytes = Vec::with_capacity(8 + self.block_size as usize);
+
+        bytes.extend_from_slice(&block.to_le_bytes());
+        bytes.extend(self.base.read(block * self.block_size, self.block_size as usize)?);
+
+        self.delta.write(self.slot_pos(slot) - 8, bytes)?;
+
+        self.blocks.insert(block, slot);
+
+        Ok(slot)
+    }
+}
+
+impl<B: StorageIO, D: StorageIO> StorageIO for OverlayStorageIO<B, D> {
+    type Reader = D::Reader;
+
+    #[inline]
+    /// Get reader of the delta storage.
+    fn io(&mut self) -> &mut Self::Reader {
+        self.delta.io()
+    }
+
+    fn read(&mut self, mut offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(length);
+
+        while buf.len() < length {
+            let block = offset / self.block_size;
+            let block_offset = offset % self.block_size;
+
+            let n = std::cmp::min((self.block_size - block_offset) as usize, length - buf.len());
+
+            match self.blocks.get(&block) {
+                Some(slot) => buf.extend(self.delta.read(self.slot_pos(*slot) + block_offset, n)?),
+                None => buf.extend(self.base.read(offset, n)?)
+            }
+
+            offset += n as u64;
+        }
+
+        Ok(buf)
+    }
+
+    fn write(&mut self, mut offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let mut bytes = bytes.as_ref();
+
+        let end = offset + bytes.len() as u64;
+
+        while !bytes.is_empty() {
+            let block = offset / self.block_size;
+            let block_offset = offset % self.block_size;
+
+            let n = std::cmp::min((self.block_size - block_offset) as usize, bytes.len());
+
+            let slot = self.reserve_block(block)?;
+
+            self.delta.write(self.slot_pos(slot) + block_offset, &bytes[..n])?;
+
+            bytes = &bytes[n..];
+            offset += n as u64;
+        }
+
+        if end > self.len {
+            self.delta.write(8, end.to_le_bytes())?;
+
+            self.len = end;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        self.write(self.len, bytes)
+    }
+
+    #[inline]
+    fn len(&mut self) -> std::io::Result<u64> {
+        Ok(self.len)
+    }
+
+    #[inline]
+    /// Sync the delta storage.
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.delta.sync()
+    }
+
+    /// Discard modified blocks which are entirely covered
+    /// by the range. Base storage is never changed.
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        let first = offset.div_ceil(self.block_size);
+        let last = offset.saturating_add(length) / self.block_size;
+
+        for block in first..last {
+            if let Some(slot) = self.blocks.get(&block) {
+                self.delta.discard(self.slot_pos(*slot), self.block_size)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn read_write() {
+        let base = MemoryStorageIO::from_bytes([1, 2, 3, 4, 5, 6]);
+
+        let mut io = OverlayStorageIO::open(base, MemoryStorageIO::new(), 4).unwrap();
+
+        assert_eq!(io.len().unwrap(), 6);
+        assert_eq!(io.read(0, 8).unwrap(), &[1, 2, 3, 4, 5, 6, 0, 0]);
+
+        io.write(3, [9, 9]).unwrap();
+        io.append([7, 8, 9]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 9);
+        assert_eq!(io.modified_blocks(), 3);
+        assert_eq!(io.read(0, 10).unwrap(), &[1, 2, 3, 9, 9, 6, 7, 8, 9, 0]);
+        assert_eq!(io.base().as_bytes(), &[1, 2, 3, 4, 5, 6]);
+
+        // Restore blocks map from the delta.
+        let (base, delta) = io.into_parts();
+
+        assert!(OverlayStorageIO::open(base.clone(), delta.clone(), 8).is_err());
+
+        let mut io = OverlayStorageIO::open(base, delta, 4).unwrap();
+
+        assert_eq!(io.len().unwrap(), 9);
+        assert_eq!(io.modified_blocks(), 3);
+        assert_eq!(io.read(0, 9).unwrap(), &[1, 2, 3, 9, 9, 6, 7, 8, 9]);
+
+        let mut committed = MemoryStorageIO::new();
+
+        io.commit(&mut committed).unwrap();
+
+        assert_eq!(committed.as_bytes(), &[1, 2, 3, 9, 9, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn filesystem() {
+        let path = std::env::temp_dir().join(".animefs-overlay-io-test-base");
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .expect("Failed to open file");
+
+        let mut fs = FilesystemDriver::new(file)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        response_receiver.recv().unwrap().unwrap().write(0, vec![1; 16]).unwrap();
+
+        fs.sync().unwrap();
+
+        let image = std::fs::read(&path).unwrap();
+
+        // Modify read-only base image through the overlay.
+        let io = OverlayStorageIO::open(File::open(&path).unwrap(), MemoryStorageIO::new(), 64).unwrap();
+
+        let mut fs = FilesystemDriver::new(io)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let page = Page::new(0, fs.handler().clone());
+
+        assert_eq!(page.read(0, 16).unwrap(), vec![1; 16]);
+
+        page.write(8, vec![2; 16]).unwrap();
+
+        assert_eq!(page.read(0, 24).unwrap(), [vec![1; 8], vec![2; 16]].concat());
+        assert_eq!(std::fs::read(&path).unwrap(), image);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
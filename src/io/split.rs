@@ -0,0 +1,353 @@
+use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+
+use super::prelude::*;
+
+#[derive(Debug)]
+/// Storage IO that concatenates multiple fixed-size segment
+/// files into a single logical address space.
+///
+/// Segments are stored next to each other with numeric
+/// suffixes added to the base path: `image.000`, `image.001`, ...
+/// All the segments except the last one always have
+/// exactly `segment_size` bytes. New segments are created
+/// when writes go beyond the end of the last one.
+pub struct SplitStorageIO {
+    path: PathBuf,
+    segment_size: u64,
+    segments: Vec<File>
+}
+
+impl SplitStorageIO {
+    /// Open existing segments of the given base path
+    /// or create the first one.
+    pub fn open(path: impl Into<PathBuf>, segment_size: u64) -> std::io::Result<Self> {
+        if segment_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "segment size must not be zero"));
+        }
+
+        let mut io = Self {
+            path: path.into(),
+            segment_size,
+            segments: Vec::new()
+        };
+
+        loop {
+            let path = io.segment_path(io.segments.len());
+
+            if !path.exists() {
+                break;
+            }
+
+            let segment = File::options()
+                .read(true)
+                .write(true)
+                .open(path)?;
+
+            io.segments.push(segment);
+        }
+
+        if io.segments.is_empty() {
+            io.create_segment()?;
+        }
+
+        // Shorter segments would shift the address space
+        // of the following ones and corrupt their content.
+        let last = io.segments.len() - 1;
+
+        for (i, segment) in io.segments.iter().enumerate() {
+            let len = segment.metadata()?.len();
+
+            if (i < last && len != segment_size) || len > segment_size {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("segment {i} has {len} bytes instead of {segment_size}")
+                ));
+            }
+        }
+
+        Ok(io)
+    }
+
+    #[inline]
+    /// Get base path of the segments.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[inline]
+    /// Get size of a single segment.
+    pub const fn segment_size(&self) -> u64 {
+        self.segment_size
+    }
+
+    #[inline]
+    /// Get amount of segment files.
+    pub fn segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Get path to the segment with given number.
+    pub fn segment_path(&self, segment: usize) -> PathBuf {
+        let mut path = OsString::from(self.path.as_os_str());
+
+        path.push(format!(".{segment:03}"));
+
+        PathBuf::from(path)
+    }
+
+    /// Create new segment after the last one.
+    fn create_segment(&mut self) -> std::io::Result<()> {
+        let segment = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(self.segment_path(self.segments.len()))?;
+
+        self.segments.push(segment);
+
+        Ok(())
+    }
+
+    /// Make sure that the segment with given number exists
+    /// and all the previous ones have full size.
+    fn reserve_segment(&mut self, segment: usize) -> std::io::Result<()> {
+        while self.segments.len() <= segment {
+            if let Some(last) = self.segments.last() {
+                last.set_len(self.segment_size)?;
+            }
+
+            self.create_segment()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StorageIO for SplitStorageIO {
+    type Reader = File;
+
+    #[inline]
+    /// Get the first segment file.
+    fn io(&mut self) -> &mut Self::Reader {
+        &mut self.segments[0]
+    }
+
+    fn read(&mut self, mut offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(length);
+
+        while buf.len() < length {
+            let segment = (offset / self.segment_size) as usize;
+            let segment_offset = offset % self.segment_size;
+
+            let n = std::cmp::min((self.segment_size - segment_offset) as usize, length - buf.len());
+
+            match self.segments.get_mut(segment) {
+                Some(segment) => buf.extend(segment.read(segment_offset, n)?),
+                None => buf.resize(buf.len() + n, 0)
+            }
+
+            offset += n as u64;
+        }
+
+        Ok(buf)
+    }
+
+    fn write(&mut self, mut offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let mut bytes = bytes.as_ref();
+
+        while !bytes.is_empty() {
+            let segment = (offset / self.segment_size) as usize;
+            let segment_offset = offset % self.segment_size;
+
+            let n = std::cmp::min((self.segment_size - segment_offset) as usize, bytes.len());
+
+            self.reserve_segment(segment)?;
+
+            self.segments[segment].write(segment_offset, &bytes[..n])?;
+
+            bytes = &bytes[n..];
+            offset += n as u64;
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let len = self.len()?;
+
+        self.write(len, bytes)
+    }
+
+    fn len(&mut self) -> std::io::Result<u64> {
+        let n = self.segments.len() as u64;
+
+        match self.segments.last_mut() {
+            Some(last) => Ok((n - 1) * self.segment_size + last.len()?),
+            None => Ok(0)
+        }
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        for segment in &mut self.segments {
+            segment.sync()?;
+        }
+
+        Ok(())
+    }
+
+    fn discard(&mut self, mut offset: u64, length: u64) -> std::io::Result<()> {
+        let end = offset.saturating_add(length);
+
+        while offset < end {
+            let segment = (offset / self.segment_size) as usize;
+            let segment_offset = offset % self.segment_size;
+
+            let n = std::cmp::min(self.segment_size - segment_offset, end - offset);
+
+            // Missing segments don't store anything.
+            let Some(segment) = self.segments.get_mut(segment) else {
+                break;
+            };
+
+            segment.discard(segment_offset, n)?;
+
+            offset += n;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    use super::*;
+
+    fn remove_segments(path: &Path) {
+        for i in 0.. {
+            let segment = PathBuf::from(format!("{}.{i:03}", path.display()));
+
+            if !segment.exists() {
+                break;
+            }
+
+            std::fs::remove_file(segment).unwrap();
+        }
+    }
+
+    fn get_io(name: &str, segment_size: u64) -> (SplitStorageIO, PathBuf) {
+        let path = std::env::temp_dir().join(format!(".animefs-split-io-test-{name}"));
+
+        remove_segments(&path);
+
+        (SplitStorageIO::open(&path, segment_size).expect("Failed to open segments"), path)
+    }
+
+    #[test]
+    fn read_write() {
+        let (mut io, path) = get_io("read-write", 4);
+
+        assert!(io.is_empty().unwrap());
+        assert_eq!(io.segments(), 1);
+        assert_eq!(io.read(0, 6).unwrap(), &[0, 0, 0, 0, 0, 0]);
+
+        io.write(2, [1, 2, 3, 4]).unwrap();
+
+        assert_eq!(io.segments(), 2);
+        assert_eq!(io.len().unwrap(), 6);
+        assert_eq!(io.read(0, 8).unwrap(), &[0, 0, 1, 2, 3, 4, 0, 0]);
+
+        io.write(13, [5, 6]).unwrap();
+
+        assert_eq!(io.segments(), 4);
+        assert_eq!(io.len().unwrap(), 15);
+        assert_eq!(io.read(4, 12).unwrap(), &[3, 4, 0, 0, 0, 0, 0, 0, 0, 5, 6, 0]);
+
+        for i in 0..3 {
+            assert_eq!(io.segment_path(i).metadata().unwrap().len(), 4);
+        }
+
+        assert_eq!(io.segment_path(3).metadata().unwrap().len(), 3);
+
+        remove_segments(&path);
+    }
+
+    #[test]
+    fn append() {
+        let (mut io, path) = get_io("append", 3);
+
+        io.append([1, 2]).unwrap();
+        io.append([3, 4, 5, 6, 7]).unwrap();
+
+        assert_eq!(io.segments(), 3);
+        assert_eq!(io.len().unwrap(), 7);
+
+        drop(io);
+
+        let mut io = SplitStorageIO::open(&path, 3).unwrap();
+
+        assert_eq!(io.segments(), 3);
+        assert_eq!(io.len().unwrap(), 7);
+        assert_eq!(io.read(0, 8).unwrap(), &[1, 2, 3, 4, 5, 6, 7, 0]);
+
+        remove_segments(&path);
+    }
+
+    #[test]
+    fn truncated_segment() {
+        let (mut io, path) = get_io("truncated-segment", 4);
+
+        io.append([1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+
+        assert_eq!(io.segments(), 3);
+
+        // Middle segment lost its last byte.
+        File::options().write(true).open(io.segment_path(1)).unwrap().set_len(3).unwrap();
+
+        drop(io);
+
+        assert_eq!(SplitStorageIO::open(&path, 4).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        // Last segment can be shorter but not longer.
+        File::options().write(true).open(format!("{}.001", path.display())).unwrap().set_len(4).unwrap();
+        File::options().write(true).open(format!("{}.002", path.display())).unwrap().set_len(5).unwrap();
+
+        assert_eq!(SplitStorageIO::open(&path, 4).unwrap_err().kind(), ErrorKind::InvalidData);
+
+        remove_segments(&path);
+    }
+
+    #[test]
+    fn filesystem() {
+        let (io, path) = get_io("filesystem", 1000);
+
+        let mut fs = FilesystemDriver::new(io)
+            .expect("Failed to open filesystem");
+
+        let worker = fs.daemonize().unwrap();
+
+        let header = fs.read_header().unwrap();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
+        let book = Book::open(page, header.page_size);
+
+        book.write(0, vec![1; header.page_size as usize * 4]).unwrap();
+
+        assert_eq!(book.read(0, header.page_size * 4).unwrap(), vec![1; header.page_size as usize * 4]);
+
+        fs.close().unwrap();
+        worker.join().unwrap().unwrap();
+
+        remove_segments(&path);
+    }
+}
@@ -0,0 +1,352 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use super::prelude::*;
+
+#[derive(Default, Debug)]
+/// Counters of a single kind of storage IO operations.
+pub struct StorageOpCounters {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    bytes: AtomicU64,
+    micros: AtomicU64
+}
+
+impl StorageOpCounters {
+    #[inline]
+    /// Get amount of performed operations.
+    pub fn calls(&self) -> u64 {
+        self.calls.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    /// Get amount of failed operations.
+    pub fn errors(&self) -> u64 {
+        self.errors.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    /// Get amount of read or written bytes.
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    /// Get total time spent in the inner IO in microseconds.
+    pub fn micros(&self) -> u64 {
+        self.micros.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    pub fn reset(&self) {
+        self.calls.store(0, Ordering::Release);
+        self.errors.store(0, Ordering::Release);
+        self.bytes.store(0, Ordering::Release);
+        self.micros.store(0, Ordering::Release);
+    }
+
+    fn record(&self, started: Instant, bytes: u64, failed: bool) {
+        self.calls.fetch_add(1, Ordering::AcqRel);
+        self.bytes.fetch_add(bytes, Ordering::AcqRel);
+        self.micros.fetch_add(started.elapsed().as_micros() as u64, Ordering::AcqRel);
+
+        if failed {
+            self.errors.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+/// Statistics of the `StatsStorageIO` shared between
+/// its clones, so they can be read after the IO was moved
+/// to the filesystem worker.
+///
+/// Batched operations (`read_many`, `write_many` and `submit`)
+/// are counted as a single call with the total amount of bytes.
+pub struct StorageIOStats {
+    pub read: StorageOpCounters,
+    pub write: StorageOpCounters,
+    pub append: StorageOpCounters,
+    pub len: StorageOpCounters,
+    pub sync: StorageOpCounters,
+    pub discard: StorageOpCounters,
+    pub read_many: StorageOpCounters,
+    pub write_many: StorageOpCounters,
+    pub submit: StorageOpCounters
+}
+
+impl StorageIOStats {
+    /// Get counters of all the operations with their names.
+    pub fn ops(&self) -> [(&'static str, &StorageOpCounters); 9] {
+        [
+            ("read", &self.read),
+            ("write", &self.write),
+            ("append", &self.append),
+            ("len", &self.len),
+            ("sync", &self.sync),
+            ("discard", &self.discard),
+            ("read_many", &self.read_many),
+            ("write_many", &self.write_many),
+            ("submit", &self.submit)
+        ]
+    }
+
+    #[inline]
+    /// Get total amount of performed operations.
+    pub fn calls(&self) -> u64 {
+        self.ops().iter().map(|(_, op)| op.calls()).sum()
+    }
+
+    #[inline]
+    /// Get total amount of read and written bytes.
+    pub fn bytes(&self) -> u64 {
+        self.ops().iter().map(|(_, op)| op.bytes()).sum()
+    }
+
+    #[inline]
+    /// Get total time spent in the inner IO in microseconds.
+    pub fn micros(&self) -> u64 {
+        self.ops().iter().map(|(_, op)| op.micros()).sum()
+    }
+
+    pub fn reset(&self) {
+        for (_, op) in self.ops() {
+            op.reset();
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Wrapper storage IO which counts operations, transferred
+/// bytes and time spent in the inner IO.
+///
+/// Wrappers can be put between other storage IO layers
+/// to measure the cost of each of them.
+pub struct StatsStorageIO<T> {
+    io: T,
+    stats: Arc<StorageIOStats>
+}
+
+impl<T: StorageIO> StatsStorageIO<T> {
+    #[inline]
+    pub fn new(io: T) -> Self {
+        Self {
+            io,
+            stats: Arc::new(StorageIOStats::default())
+        }
+    }
+
+    #[inline]
+    /// Get inner IO.
+    pub const fn inner(&self) -> &T {
+        &self.io
+    }
+
+    #[inline]
+    /// Take inner IO.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+
+    #[inline]
+    /// Get shared statistics of the IO.
+    pub fn stats(&self) -> Arc<StorageIOStats> {
+        self.stats.clone()
+    }
+}
+
+impl<T: StorageIO> StorageIO for StatsStorageIO<T> {
+    type Reader = T::Reader;
+
+    #[inline]
+    fn io(&mut self) -> &mut Self::Reader {
+        self.io.io()
+    }
+
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let started = Instant::now();
+
+        let result = self.io.read(offset, length);
+
+        self.stats.read.record(started, length as u64, result.is_err());
+
+        result
+    }
+
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        let started = Instant::now();
+
+        let result = self.io.write(offset, bytes);
+
+        self.stats.write.record(started, bytes.len() as u64, result.is_err());
+
+        result
+    }
+
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        let started = Instant::now();
+
+        let result = self.io.append(bytes);
+
+        self.stats.append.record(started, bytes.len() as u64, result.is_err());
+
+        result
+    }
+
+    fn len(&mut self) -> std::io::Result<u64> {
+        let started = Instant::now();
+
+        let result = self.io.len();
+
+        self.stats.len.record(started, 0, result.is_err());
+
+        result
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        let started = Instant::now();
+
+        let result = self.io.sync();
+
+        self.stats.sync.record(started, 0, result.is_err());
+
+        result
+    }
+
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        let started = Instant::now();
+
+        let result = self.io.discard(offset, length);
+
+        self.stats.discard.record(started, 0, result.is_err());
+
+        result
+    }
+
+    fn read_many(&mut self, ranges: &[(u64, usize)]) -> std::io::Result<Vec<Vec<u8>>> {
+        let bytes = ranges.iter().map(|(_, length)| *length as u64).sum();
+
+        let started = Instant::now();
+
+        let result = self.io.read_many(ranges);
+
+        self.stats.read_many.record(started, bytes, result.is_err());
+
+        result
+    }
+
+    fn write_many<B: AsRef<[u8]>>(&mut self, writes: &[(u64, B)]) -> std::io::Result<()> {
+        let bytes = writes.iter().map(|(_, bytes)| bytes.as_ref().len() as u64).sum();
+
+        let started = Instant::now();
+
+        let result = self.io.write_many(writes);
+
+        self.stats.write_many.record(started, bytes, result.is_err());
+
+        result
+    }
+
+    fn submit(&mut self, ops: Vec<StorageOp>, mut complete: impl FnMut(usize, std::io::Result<Vec<u8>>)) {
+        let bytes = ops.iter()
+            .map(|op| match op {
+                StorageOp::Read { length, .. } => *length as u64,
+                StorageOp::Write { bytes, .. } => bytes.len() as u64
+            })
+            .sum();
+
+        let mut failed = false;
+
+        let started = Instant::now();
+
+        self.io.submit(ops, |i, result| {
+            failed |= result.is_err();
+
+            complete(i, result);
+        });
+
+        self.stats.submit.record(started, bytes, failed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn counters() {
+        let mut io = StatsStorageIO::new(FaultyStorageIO::new(MemoryStorageIO::new()).with_fault(StorageFault::FailWrite(2)));
+
+        let stats = io.stats();
+
+        io.write(0, [1, 2, 3, 4]).unwrap();
+        io.append([5, 6]).unwrap();
+
+        assert!(io.write(0, [0]).is_err());
+
+        assert_eq!(io.read(0, 8).unwrap(), &[1, 2, 3, 4, 5, 6, 0, 0]);
+        assert_eq!(io.read_many(&[(0, 2), (4, 2)]).unwrap(), vec![vec![1, 2], vec![5, 6]]);
+
+        io.write_many(&[(0, [7]), (1, [8])]).unwrap();
+        io.sync().unwrap();
+
+        assert_eq!(stats.read.calls(), 1);
+        assert_eq!(stats.read.bytes(), 8);
+        assert_eq!(stats.write.calls(), 2);
+        assert_eq!(stats.write.errors(), 1);
+        assert_eq!(stats.write.bytes(), 5);
+        assert_eq!(stats.append.bytes(), 2);
+        assert_eq!(stats.read_many.calls(), 1);
+        assert_eq!(stats.read_many.bytes(), 4);
+        assert_eq!(stats.write_many.bytes(), 2);
+        assert_eq!(stats.sync.calls(), 1);
+
+        assert_eq!(stats.calls(), 7);
+        assert_eq!(stats.bytes(), 21);
+
+        stats.reset();
+
+        assert_eq!(stats.calls(), 0);
+    }
+
+    #[test]
+    fn layers() {
+        // Measure both the filesystem and the buffer below it.
+        let disk = StatsStorageIO::new(MemoryStorageIO::new());
+        let disk_stats = disk.stats();
+
+        let io = StatsStorageIO::new(BufStorageIO::write_back(disk, 1 << 20, 1 << 20).unwrap());
+        let io_stats = io.stats();
+
+        let mut fs = FilesystemDriver::new(io)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
+
+        for i in 0..16 {
+            page.write(i * 4, vec![i as u8; 4]).unwrap();
+        }
+
+        fs.sync().unwrap();
+
+        // Header of the new filesystem is synced on creation.
+        assert!(io_stats.write.calls() >= 16);
+        assert_eq!(io_stats.sync.calls(), 2);
+
+        // Buffered writes reach the disk in larger chunks.
+        assert!(disk_stats.write.calls() + disk_stats.write_many.calls() < io_stats.write.calls());
+    }
+}
@@ -1,7 +1,25 @@
-use std::io::{Read, Seek, SeekFrom, Write};
+use std::io::{Read, Seek, SeekFrom, Write, ErrorKind};
 use std::fs::File;
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Single operation of the storage IO batch.
+pub enum StorageOp {
+    Read {
+        offset: u64,
+        length: usize
+    },
+
+    Write {
+        offset: u64,
+        bytes: Vec<u8>
+    }
+}
+
 /// General interface to provide bytes storage.
+///
+/// All the operations are fallible and return errors
+/// of the underlying IO instead of panicking, so callers
+/// can report them (e.g. "disk full") to the user.
 pub trait StorageIO {
     type Reader: Read + Write + Seek;
 
@@ -11,103 +29,135 @@ pub trait StorageIO {
     /// Low level direct read operation. Returns bytes vector
     /// with exactly requested amount of bytes. If reader is
     /// empty, zeros are returned.
-    fn read(&mut self, offset: u64, length: usize) -> Vec<u8> {
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
         let mut buf = vec![0; length];
 
         let io = self.io();
 
-        if io.seek(SeekFrom::Start(offset)).is_err() {
-            return buf;
-        }
+        io.seek(SeekFrom::Start(offset))?;
 
-        let _ = io.read_exact(&mut buf);
+        let mut n = 0;
 
-        buf
+        // Read bytes until the buffer is filled or the end
+        // of the reader is reached. Remaining bytes are zeros.
+        while n < length {
+            match io.read(&mut buf[n..]) {
+                Ok(0) => break,
+                Ok(k) => n += k,
+
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err)
+            }
+        }
+
+        Ok(buf)
     }
 
     /// Low level direct write operation. Fills file with zeros
     /// if there's no content before given offset.
-    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) {
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
         let io = self.io();
 
-        let reader_offset = io.seek(SeekFrom::Start(offset))
-            .unwrap_or_else(|err| {
-                panic!(
-                    "Failed to write {} at offset 0x{offset:08x} : seek failed : {err}",
-                    std::any::type_name::<Self::Reader>()
-                );
-            });
+        let reader_offset = io.seek(SeekFrom::Start(offset))?;
 
         if reader_offset < offset {
             // Potentially unsafe
-            io.write_all(&vec![0; (offset - reader_offset) as usize])
-                .unwrap_or_else(|err| {
-                    panic!(
-                        "Failed to write {} at offset 0x{reader_offset:08x} : write failed : {err}",
-                        std::any::type_name::<Self::Reader>()
-                    );
-                });
+            io.write_all(&vec![0; (offset - reader_offset) as usize])?;
         }
 
-        io.write_all(bytes.as_ref())
-            .unwrap_or_else(|err| {
-                panic!(
-                    "Failed to write {} at offset 0x{offset:08x} : write failed : {err}",
-                    std::any::type_name::<Self::Reader>()
-                );
-            });
-
-        io.flush().unwrap_or_else(|err| {
-            panic!(
-                "Failed to write {} at offset 0x{offset:08x} : flush failed : {err}",
-                std::any::type_name::<Self::Reader>()
-            );
-        });
+        io.write_all(bytes.as_ref())?;
+        io.flush()
     }
 
     /// Append bytes slice to the end of the IO.
-    fn append(&mut self, bytes: impl AsRef<[u8]>) {
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
         let io = self.io();
 
-        io.seek(SeekFrom::End(0))
-            .unwrap_or_else(|err| {
-                panic!(
-                    "Failed to append {} : seek failed : {err}",
-                    std::any::type_name::<Self::Reader>()
-                );
-            });
-
-        io.write_all(bytes.as_ref())
-            .unwrap_or_else(|err| {
-                panic!(
-                    "Failed to append {} : write failed : {err}",
-                    std::any::type_name::<Self::Reader>()
-                );
-            });
-
-        io.flush().unwrap_or_else(|err| {
-            panic!(
-                "Failed to append {} : flush failed : {err}",
-                std::any::type_name::<Self::Reader>()
-            );
-        });
+        io.seek(SeekFrom::End(0))?;
+        io.write_all(bytes.as_ref())?;
+        io.flush()
     }
 
     /// Get length of the buffer.
-    fn len(&mut self) -> u64 {
-        self.io().seek(SeekFrom::End(0)).unwrap_or_else(|err| {
-            panic!(
-                "Failed to get len of {} : seek failed : {err}",
-                std::any::type_name::<Self::Reader>()
-            );
-        })
+    fn len(&mut self) -> std::io::Result<u64> {
+        self.io().seek(SeekFrom::End(0))
     }
 
     #[inline]
     /// Check if the buffer is empty.
-    fn is_empty(&mut self) -> bool {
-        self.len() == 0
+    fn is_empty(&mut self) -> std::io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    #[inline]
+    /// Write all the pending changes to the underlying storage.
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.io().flush()
+    }
+
+    #[inline]
+    /// Hint that the given bytes range is not used anymore,
+    /// so the storage can release its space. Content of the
+    /// range becomes unspecified while the length of the
+    /// storage doesn't change.
+    ///
+    /// Default implementation does nothing.
+    fn discard(&mut self, _offset: u64, _length: u64) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Read multiple `(offset, length)` ranges. Returns bytes
+    /// vector for every range with exactly requested amount
+    /// of bytes, like `StorageIO::read`.
+    ///
+    /// Default implementation reads ranges one by one.
+    fn read_many(&mut self, ranges: &[(u64, usize)]) -> std::io::Result<Vec<Vec<u8>>> {
+        ranges.iter()
+            .map(|(offset, length)| self.read(*offset, *length))
+            .collect()
     }
+
+    /// Write multiple `(offset, bytes)` pairs in the given order.
+    ///
+    /// Default implementation writes them one by one.
+    fn write_many<B: AsRef<[u8]>>(&mut self, writes: &[(u64, B)]) -> std::io::Result<()> {
+        for (offset, bytes) in writes {
+            self.write(*offset, bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// Perform batch of operations, calling `complete` with index
+    /// of the operation and its result when it's finished. Write
+    /// operations return empty vectors.
+    ///
+    /// Operations can be performed concurrently and completed in
+    /// any order, so the batch must not contain conflicting ones.
+    /// Default implementation performs them one by one.
+    fn submit(&mut self, ops: Vec<StorageOp>, mut complete: impl FnMut(usize, std::io::Result<Vec<u8>>)) {
+        for (i, op) in ops.into_iter().enumerate() {
+            let result = match op {
+                StorageOp::Read { offset, length } => self.read(offset, length),
+                StorageOp::Write { offset, bytes } => self.write(offset, bytes).map(|_| vec![])
+            };
+
+            complete(i, result);
+        }
+    }
+}
+
+/// Storage IO which supports positional reads that don't move
+/// the reader's cursor, so they can be performed through a shared
+/// reference (e.g. from multiple threads at once).
+pub trait PositionalStorageIO: StorageIO {
+    /// Read bytes from the given offset. Returns bytes vector
+    /// with exactly requested amount of bytes. If there's no
+    /// content at the offset, zeros are returned.
+    fn read_at(&self, offset: u64, length: usize) -> std::io::Result<Vec<u8>>;
+
+    /// Get length of the buffer without moving the reader's cursor.
+    fn len_at(&self) -> std::io::Result<u64>;
 }
 
 impl StorageIO for File {
@@ -117,11 +167,272 @@ impl StorageIO for File {
     fn io(&mut self) -> &mut File {
         self
     }
+
+    #[cfg(unix)]
+    #[inline]
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        self.read_at(offset, length)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        // Positional writes beyond the end of the file
+        // fill the gap with zeros.
+        std::os::unix::fs::FileExt::write_all_at(self, bytes.as_ref(), offset)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let len = self.len_at()?;
+
+        std::os::unix::fs::FileExt::write_all_at(self, bytes.as_ref(), len)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    fn len(&mut self) -> std::io::Result<u64> {
+        self.len_at()
+    }
+
+    #[inline]
+    /// Flush file data to the disk.
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.sync_data()
+    }
+
+    #[cfg(target_os = "linux")]
+    /// Punch a hole in the file. Filesystems which
+    /// don't support it keep the range as is.
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        use std::os::fd::AsRawFd;
+
+        if length == 0 {
+            return Ok(());
+        }
+
+        let result = unsafe {
+            libc::fallocate(
+                self.as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                offset as libc::off_t,
+                length as libc::off_t
+            )
+        };
+
+        if result == 0 {
+            return Ok(());
+        }
+
+        let err = std::io::Error::last_os_error();
+
+        if err.raw_os_error() == Some(libc::EOPNOTSUPP) {
+            return Ok(());
+        }
+
+        Err(err)
+    }
+
+    #[cfg(unix)]
+    /// Read ranges using one `preadv` syscall
+    /// for every run of adjacent ranges.
+    fn read_many(&mut self, ranges: &[(u64, usize)]) -> std::io::Result<Vec<Vec<u8>>> {
+        let mut bufs = ranges.iter()
+            .map(|(_, length)| vec![0; *length])
+            .collect::<Vec<_>>();
+
+        let mut i = 0;
+
+        while i < ranges.len() {
+            let j = adjacent_run(i, ranges.iter().map(|(offset, length)| (*offset, *length)));
+
+            preadv_all(self, ranges[i].0, &mut bufs[i..j])?;
+
+            i = j;
+        }
+
+        Ok(bufs)
+    }
+
+    #[cfg(unix)]
+    /// Write bytes using one `pwritev` syscall
+    /// for every run of adjacent writes.
+    fn write_many<B: AsRef<[u8]>>(&mut self, writes: &[(u64, B)]) -> std::io::Result<()> {
+        let mut i = 0;
+
+        while i < writes.len() {
+            let j = adjacent_run(i, writes.iter().map(|(offset, bytes)| (*offset, bytes.as_ref().len())));
+
+            let bufs = writes[i..j].iter()
+                .map(|(_, bytes)| bytes.as_ref())
+                .collect::<Vec<_>>();
+
+            pwritev_all(self, writes[i].0, &bufs)?;
+
+            i = j;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+/// Find end of the run of adjacent `(offset, length)`
+/// ranges starting from the `start` index.
+fn adjacent_run(start: usize, ranges: impl Iterator<Item = (u64, usize)>) -> usize {
+    let mut ranges = ranges.skip(start);
+
+    let Some((offset, length)) = ranges.next() else {
+        return start;
+    };
+
+    let mut end = offset + length as u64;
+    let mut i = start + 1;
+
+    for (offset, length) in ranges {
+        if offset != end {
+            break;
+        }
+
+        end += length as u64;
+        i += 1;
+    }
+
+    i
+}
+
+#[cfg(unix)]
+/// Maximal amount of buffers passed to a single vectored syscall.
+const IOV_MAX: usize = 1024;
+
+#[cfg(unix)]
+/// Read adjacent buffers from the given offset using `preadv`.
+/// Bytes after the end of the file remain zeros.
+fn preadv_all(file: &File, mut offset: u64, bufs: &mut [Vec<u8>]) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    use std::io::IoSliceMut;
+
+    let mut slices = bufs.iter_mut()
+        .filter(|buf| !buf.is_empty())
+        .map(|buf| IoSliceMut::new(buf))
+        .collect::<Vec<_>>();
+
+    let mut slices = slices.as_mut_slice();
+
+    while !slices.is_empty() {
+        // Safety: IoSliceMut is ABI compatible with iovec on unix
+        // and all the slices point to valid mutable buffers.
+        let n = unsafe {
+            libc::preadv(
+                file.as_raw_fd(),
+                slices.as_ptr() as *const libc::iovec,
+                slices.len().min(IOV_MAX) as libc::c_int,
+                offset as libc::off_t
+            )
+        };
+
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+
+            if err.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(err);
+        }
+
+        // End of the file.
+        if n == 0 {
+            break;
+        }
+
+        IoSliceMut::advance_slices(&mut slices, n as usize);
+
+        offset += n as u64;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+/// Write adjacent buffers to the given offset using `pwritev`.
+fn pwritev_all(file: &File, mut offset: u64, bufs: &[&[u8]]) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+    use std::io::IoSlice;
+
+    let mut slices = bufs.iter()
+        .filter(|buf| !buf.is_empty())
+        .map(|buf| IoSlice::new(buf))
+        .collect::<Vec<_>>();
+
+    let mut slices = slices.as_mut_slice();
+
+    while !slices.is_empty() {
+        // Safety: IoSlice is ABI compatible with iovec on unix
+        // and all the slices point to valid buffers.
+        let n = unsafe {
+            libc::pwritev(
+                file.as_raw_fd(),
+                slices.as_ptr() as *const libc::iovec,
+                slices.len().min(IOV_MAX) as libc::c_int,
+                offset as libc::off_t
+            )
+        };
+
+        if n < 0 {
+            let err = std::io::Error::last_os_error();
+
+            if err.kind() == ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(err);
+        }
+
+        if n == 0 {
+            return Err(std::io::Error::new(ErrorKind::WriteZero, "failed to write whole buffers"));
+        }
+
+        IoSlice::advance_slices(&mut slices, n as usize);
+
+        offset += n as u64;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+impl PositionalStorageIO for File {
+    fn read_at(&self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; length];
+
+        let mut n = 0;
+
+        // Read bytes until the buffer is filled or the end
+        // of the file is reached. Remaining bytes are zeros.
+        while n < length {
+            match std::os::unix::fs::FileExt::read_at(self, &mut buf[n..], offset + n as u64) {
+                Ok(0) => break,
+                Ok(k) => n += k,
+
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err)
+            }
+        }
+
+        Ok(buf)
+    }
+
+    #[inline]
+    fn len_at(&self) -> std::io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{File, StorageIO};
+    use super::{File, StorageIO, PositionalStorageIO};
 
     fn get_io(name: &str) -> (File, std::path::PathBuf) {
         let path = std::env::temp_dir().join(format!(".animefs-io-test-{name}"));
@@ -143,8 +454,8 @@ mod tests {
 
         assert!(path.metadata().unwrap().len() == 0);
 
-        assert_eq!(io.read(0, 4), &[0, 0, 0, 0]);
-        assert_eq!(io.read(8, 4), &[0, 0, 0, 0]);
+        assert_eq!(io.read(0, 4).unwrap(), &[0, 0, 0, 0]);
+        assert_eq!(io.read(8, 4).unwrap(), &[0, 0, 0, 0]);
     }
 
     #[test]
@@ -153,14 +464,14 @@ mod tests {
 
         assert!(path.metadata().unwrap().len() == 0);
 
-        io.write(0, [1, 2, 3, 4]);
-        io.write(8, [5, 6, 7, 8]);
+        io.write(0, [1, 2, 3, 4]).unwrap();
+        io.write(8, [5, 6, 7, 8]).unwrap();
 
-        assert_eq!(io.read(0, 16), &[1, 2, 3, 4, 0, 0, 0, 0, 5, 6, 7, 8, 0, 0, 0, 0]);
+        assert_eq!(io.read(0, 16).unwrap(), &[1, 2, 3, 4, 0, 0, 0, 0, 5, 6, 7, 8, 0, 0, 0, 0]);
 
-        io.write(2, [9, 8, 7, 6, 5, 4, 3, 2]);
+        io.write(2, [9, 8, 7, 6, 5, 4, 3, 2]).unwrap();
 
-        assert_eq!(io.read(0, 12), &[1, 2, 9, 8, 7, 6, 5, 4, 3, 2, 7, 8]);
+        assert_eq!(io.read(0, 12).unwrap(), &[1, 2, 9, 8, 7, 6, 5, 4, 3, 2, 7, 8]);
     }
 
     #[test]
@@ -169,13 +480,13 @@ mod tests {
 
         assert!(path.metadata().unwrap().len() == 0);
 
-        io.append([1, 2]);
+        io.append([1, 2]).unwrap();
 
-        assert_eq!(io.read(0, 4), &[1, 2, 0, 0]);
+        assert_eq!(io.read(0, 4).unwrap(), &[1, 2, 0, 0]);
 
-        io.append([3, 4]);
+        io.append([3, 4]).unwrap();
 
-        assert_eq!(io.read(0, 4), &[1, 2, 3, 4]);
+        assert_eq!(io.read(0, 4).unwrap(), &[1, 2, 3, 4]);
     }
 
     #[test]
@@ -184,22 +495,66 @@ mod tests {
 
         assert!(path.metadata().unwrap().len() == 0);
 
-        assert!(io.is_empty());
-        assert_eq!(io.len(), 0);
+        assert!(io.is_empty().unwrap());
+        assert_eq!(io.len().unwrap(), 0);
+
+        io.append([1, 2, 3]).unwrap();
+
+        assert!(!io.is_empty().unwrap());
+        assert_eq!(io.len().unwrap(), 3);
+
+        io.write(1, [0]).unwrap();
+
+        assert!(!io.is_empty().unwrap());
+        assert_eq!(io.len().unwrap(), 3);
+
+        io.write(3, [0, 2]).unwrap();
+
+        assert!(!io.is_empty().unwrap());
+        assert_eq!(io.len().unwrap(), 5);
+    }
+
+    #[test]
+    fn read_only_errors() {
+        let (mut io, path) = get_io("read-only-errors");
+
+        io.append([1, 2, 3]).unwrap();
+
+        drop(io);
+
+        let mut io = File::open(&path).unwrap();
+
+        assert_eq!(io.read(0, 4).unwrap(), &[1, 2, 3, 0]);
+
+        assert!(io.write(0, [4]).is_err());
+        assert!(io.append([4]).is_err());
+    }
+
+    #[test]
+    fn read_write_many() {
+        let (mut io, _) = get_io("read-write-many");
+
+        io.write_many(&[(2, vec![1, 2]), (4, vec![3]), (8, vec![4, 5]), (0, vec![6])]).unwrap();
 
-        io.append([1, 2, 3]);
+        assert_eq!(io.len().unwrap(), 10);
 
-        assert!(!io.is_empty());
-        assert_eq!(io.len(), 3);
+        let bufs = io.read_many(&[(0, 3), (3, 2), (5, 0), (5, 3), (8, 4), (16, 2)]).unwrap();
+
+        assert_eq!(bufs, [vec![6, 0, 1], vec![2, 3], vec![], vec![0, 0, 0], vec![4, 5, 0, 0], vec![0, 0]]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn positional() {
+        let (mut io, path) = get_io("positional");
 
-        io.write(1, [0]);
+        io.write(4, [1, 2, 3, 4]).unwrap();
 
-        assert!(!io.is_empty());
-        assert_eq!(io.len(), 3);
+        let io = &io;
 
-        io.write(3, [0, 2]);
+        assert_eq!(io.len_at().unwrap(), 8);
+        assert_eq!(io.read_at(2, 8).unwrap(), &[0, 0, 1, 2, 3, 4, 0, 0]);
 
-        assert!(!io.is_empty());
-        assert_eq!(io.len(), 5);
+        assert_eq!(path.metadata().unwrap().len(), 8);
     }
 }
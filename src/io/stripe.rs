@@ -0,0 +1,325 @@
+use std::io::{Error, ErrorKind};
+
+use crate::filesystem::header::FilesystemHeader;
+
+use super::prelude::*;
+
+#[derive(Debug)]
+/// Storage IO which stripes the logical address space
+/// across multiple inner storages (RAID0).
+///
+/// Address space is split into stripes of a fixed size which
+/// are distributed between the storages in round-robin order:
+/// stripe 0 goes to the storage 0, stripe 1 to the storage 1
+/// and so on. Ranges spanning multiple stripes are read and
+/// written with a single vectored operation per storage.
+///
+/// Use `StripedStorageIO::for_filesystem` to align stripes
+/// with the filesystem pages so every page is stored
+/// on a single storage.
+pub struct StripedStorageIO<T> {
+    backends: Vec<T>,
+    stripe_size: u64,
+
+    /// Shift of the logical addresses before mapping
+    /// them to the stripes.
+    shift: u64
+}
+
+impl<T: StorageIO> StripedStorageIO<T> {
+    /// Stripe address space across the given storages.
+    pub fn new(backends: Vec<T>, stripe_size: u64) -> std::io::Result<Self> {
+        if backends.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "at least one backend is required"));
+        }
+
+        if stripe_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "stripe size must not be zero"));
+        }
+
+        Ok(Self {
+            backends,
+            stripe_size,
+            shift: 0
+        })
+    }
+
+    /// Stripe filesystem with the given header, storing
+    /// `pages_per_stripe` physical pages (`page_header_length + page_size`)
+    /// in every stripe.
+    ///
+    /// Filesystem header is stored at the end of the first
+    /// stripe so the first page starts at the stripe boundary.
+    pub fn for_filesystem(backends: Vec<T>, header: &FilesystemHeader, pages_per_stripe: u64) -> std::io::Result<Self> {
+        let stripe_size = pages_per_stripe * (header.page_header_length() as u64 + header.page_size);
+
+        if stripe_size < header.length() as u64 {
+            return Err(Error::new(ErrorKind::InvalidInput, "stripe can't be smaller than filesystem header"));
+        }
+
+        let mut io = Self::new(backends, stripe_size)?;
+
+        io.shift = stripe_size - header.length() as u64;
+
+        Ok(io)
+    }
+
+    #[inline]
+    pub const fn stripe_size(&self) -> u64 {
+        self.stripe_size
+    }
+
+    #[inline]
+    /// Get inner storages.
+    pub fn backends(&self) -> &[T] {
+        &self.backends
+    }
+
+    #[inline]
+    /// Take inner storages.
+    pub fn into_backends(self) -> Vec<T> {
+        self.backends
+    }
+
+    /// Split logical bytes range into `(backend, offset, length)`
+    /// chunks stored within a single stripe.
+    fn chunks(&self, offset: u64, length: usize) -> Vec<(usize, u64, usize)> {
+        let n = self.backends.len() as u64;
+
+        let mut chunks = Vec::new();
+
+        let mut address = offset + self.shift;
+        let mut remaining = length;
+
+        while remaining > 0 {
+            let stripe = address / self.stripe_size;
+            let stripe_offset = address % self.stripe_size;
+
+            let length = std::cmp::min((self.stripe_size - stripe_offset) as usize, remaining);
+
+            chunks.push((
+                (stripe % n) as usize,
+                (stripe / n) * self.stripe_size + stripe_offset,
+                length
+            ));
+
+            address += length as u64;
+            remaining -= length;
+        }
+
+        chunks
+    }
+}
+
+impl<T: StorageIO> StorageIO for StripedStorageIO<T> {
+    type Reader = T::Reader;
+
+    #[inline]
+    /// Get reader of the first storage.
+    fn io(&mut self) -> &mut Self::Reader {
+        self.backends[0].io()
+    }
+
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let chunks = self.chunks(offset, length);
+
+        let mut ranges = vec![Vec::new(); self.backends.len()];
+
+        for (backend, offset, length) in &chunks {
+            ranges[*backend].push((*offset, *length));
+        }
+
+        let mut results = Vec::with_capacity(self.backends.len());
+
+        for (backend, ranges) in self.backends.iter_mut().zip(ranges) {
+            let bufs = if ranges.is_empty() {
+                vec![]
+            } else {
+                backend.read_many(&ranges)?
+            };
+
+            results.push(bufs.into_iter());
+        }
+
+        let mut buf = Vec::with_capacity(length);
+
+        // Chunks of every storage are returned in the order they were requested.
+        for (backend, _, _) in chunks {
+            if let Some(chunk) = results[backend].next() {
+                buf.extend(chunk);
+            }
+        }
+
+        Ok(buf)
+    }
+
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let mut bytes = bytes.as_ref();
+
+        let mut writes = vec![Vec::new(); self.backends.len()];
+
+        for (backend, offset, length) in self.chunks(offset, bytes.len()) {
+            writes[backend].push((offset, &bytes[..length]));
+
+            bytes = &bytes[length..];
+        }
+
+        for (backend, writes) in self.backends.iter_mut().zip(writes) {
+            if !writes.is_empty() {
+                backend.write_many(&writes)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    #[inline]
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let len = self.len()?;
+
+        self.write(len, bytes)
+    }
+
+    fn len(&mut self) -> std::io::Result<u64> {
+        let n = self.backends.len() as u64;
+
+        let mut len = 0;
+
+        for (i, backend) in self.backends.iter_mut().enumerate() {
+            let backend_len = backend.len()?;
+
+            if backend_len == 0 {
+                continue;
+            }
+
+            // Position of the last byte of the storage
+            // in the logical address space.
+            let row = (backend_len - 1) / self.stripe_size;
+            let stripe_offset = (backend_len - 1) % self.stripe_size;
+
+            let address = (row * n + i as u64) * self.stripe_size + stripe_offset + 1;
+
+            len = len.max(address.saturating_sub(self.shift));
+        }
+
+        Ok(len)
+    }
+
+    fn sync(&mut self) -> std::io::Result<()> {
+        for backend in &mut self.backends {
+            backend.sync()?;
+        }
+
+        Ok(())
+    }
+
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        let length = usize::try_from(length)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "discarded range is too large"))?;
+
+        for (backend, offset, length) in self.chunks(offset, length) {
+            self.backends[backend].discard(offset, length as u64)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn read_write() {
+        let backends = vec![MemoryStorageIO::new(), MemoryStorageIO::new(), MemoryStorageIO::new()];
+
+        let mut io = StripedStorageIO::new(backends, 2).unwrap();
+
+        assert!(io.is_empty().unwrap());
+
+        io.write(1, [1, 2, 3, 4, 5, 6, 7]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 8);
+        assert_eq!(io.read(0, 10).unwrap(), &[0, 1, 2, 3, 4, 5, 6, 7, 0, 0]);
+
+        io.append([8, 9]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 10);
+        assert_eq!(io.read(7, 3).unwrap(), &[7, 8, 9]);
+
+        let backends = io.into_backends();
+
+        assert_eq!(backends[0].as_bytes(), &[0, 1, 6, 7]);
+        assert_eq!(backends[1].as_bytes(), &[2, 3, 8, 9]);
+        assert_eq!(backends[2].as_bytes(), &[4, 5]);
+    }
+
+    #[test]
+    fn filesystem() {
+        let paths = (0..2)
+            .map(|i| std::env::temp_dir().join(format!(".animefs-stripe-io-test-{i}")))
+            .collect::<Vec<_>>();
+
+        let files = paths.iter()
+            .map(|path| {
+                File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(path)
+                    .expect("Failed to open file")
+            })
+            .collect();
+
+        let header = FilesystemHeader::default();
+        let page_size = header.page_size;
+
+        let io = StripedStorageIO::for_filesystem(files, &header, 1).unwrap();
+
+        let mut fs = FilesystemDriver::new(io)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let book = Book::open(response_receiver.recv().unwrap().unwrap(), page_size);
+
+        let bytes = (0..page_size * 4)
+            .map(|i| (i / page_size) as u8 + 1)
+            .collect::<Vec<_>>();
+
+        book.write(0, bytes.clone()).unwrap();
+
+        assert_eq!(book.read(0, page_size * 4).unwrap(), bytes);
+
+        // Every page is stored on a single disk.
+        let physical_page_size = header.page_header_length() as u64 + page_size;
+
+        let disks = paths.iter()
+            .map(|path| std::fs::read(path).unwrap())
+            .collect::<Vec<_>>();
+
+        for page_number in 0..4 {
+            // Page k is stored in the stripe k + 1 because the
+            // first one contains filesystem header.
+            let disk = &disks[(page_number + 1) % 2];
+            let page_pos = (page_number as u64).div_ceil(2) * physical_page_size;
+
+            let body = &disk[(page_pos + header.page_header_length() as u64) as usize..(page_pos + physical_page_size) as usize];
+
+            assert!(body.iter().all(|byte| *byte == page_number as u8 + 1));
+        }
+
+        for path in paths {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,479 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::prelude::*;
+
+/// Source of time for the storage throttle.
+pub trait ThrottleClock: std::fmt::Debug + Send + Sync {
+    /// Get time passed since some fixed moment.
+    fn now(&self) -> Duration;
+
+    /// Block the current thread for the given duration.
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Clock which uses the system monotonic time.
+pub struct SystemThrottleClock(std::time::Instant);
+
+impl Default for SystemThrottleClock {
+    #[inline]
+    fn default() -> Self {
+        Self(std::time::Instant::now())
+    }
+}
+
+impl ThrottleClock for SystemThrottleClock {
+    #[inline]
+    fn now(&self) -> Duration {
+        self.0.elapsed()
+    }
+
+    #[inline]
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+/// Clock which moves only when it's advanced or slept on.
+/// Useful to throttle storages in tests and simulations.
+pub struct ManualThrottleClock(Arc<Mutex<Duration>>);
+
+impl ManualThrottleClock {
+    #[inline]
+    /// Move the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().expect("Failed to lock manual clock") += duration;
+    }
+}
+
+impl ThrottleClock for ManualThrottleClock {
+    #[inline]
+    fn now(&self) -> Duration {
+        *self.0.lock().expect("Failed to lock manual clock")
+    }
+
+    #[inline]
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// Token bucket which refills with `rate` bytes per second
+/// and stores up to one second of tokens.
+struct TokenBucket {
+    /// Bytes per second. `None` if the bucket is unlimited.
+    rate: Option<u64>,
+
+    /// Available tokens. Negative if the bucket is in debt.
+    tokens: f64,
+
+    /// Clock time of the last refill.
+    updated: Duration
+}
+
+impl TokenBucket {
+    #[inline]
+    fn new(rate: Option<u64>, now: Duration) -> Self {
+        let rate = rate.filter(|rate| *rate > 0);
+
+        Self {
+            rate,
+            tokens: rate.unwrap_or_default() as f64,
+            updated: now
+        }
+    }
+
+    fn refill(&mut self, now: Duration) {
+        if let Some(rate) = self.rate {
+            let tokens = self.tokens + now.saturating_sub(self.updated).as_secs_f64() * rate as f64;
+
+            self.tokens = tokens.min(rate as f64);
+        }
+
+        self.updated = self.updated.max(now);
+    }
+
+    fn set_rate(&mut self, rate: Option<u64>, now: Duration) {
+        let rate = rate.filter(|rate| *rate > 0);
+
+        self.refill(now);
+
+        // Unlimited bucket is considered full.
+        self.tokens = match (self.rate, rate) {
+            (Some(_), Some(rate)) => self.tokens.min(rate as f64),
+            (None, Some(rate)) => rate as f64,
+            (_, None) => 0.0
+        };
+
+        self.rate = rate;
+    }
+
+    /// Take tokens for the given amount of bytes, returning
+    /// time needed to pay the debt if there weren't enough.
+    fn take(&mut self, bytes: u64, now: Duration) -> Duration {
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+
+        self.refill(now);
+
+        self.tokens -= bytes as f64;
+
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64(-self.tokens / rate as f64)
+    }
+
+    /// Take tokens for the given amount of bytes if the bucket
+    /// is not in debt. Otherwise return time needed to pay it.
+    ///
+    /// Bucket can go in debt after this call so the operations
+    /// larger than the budget can still be performed.
+    fn try_take(&mut self, bytes: u64, now: Duration) -> Duration {
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+
+        self.refill(now);
+
+        if self.tokens < 0.0 {
+            return Duration::from_secs_f64(-self.tokens / rate as f64);
+        }
+
+        self.tokens -= bytes as f64;
+
+        Duration::ZERO
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Handle to change read and write limits at runtime.
+///
+/// Can be used by the `ThrottledStorageIO` wrapper, or by the
+/// filesystem tasks scheduler (see `FilesystemDriver::with_throttle`).
+pub struct StorageThrottle {
+    buckets: Arc<Mutex<[TokenBucket; 2]>>,
+    clock: Arc<dyn ThrottleClock>
+}
+
+impl StorageThrottle {
+    pub(crate) const READ: usize = 0;
+    pub(crate) const WRITE: usize = 1;
+
+    #[inline]
+    /// Create throttle with reads and writes limits in bytes
+    /// per second. `None` or zero means there's no limit.
+    pub fn new(read_limit: Option<u64>, write_limit: Option<u64>) -> Self {
+        Self::with_clock(read_limit, write_limit, SystemThrottleClock::default())
+    }
+
+    /// Create throttle which uses the given clock.
+    pub fn with_clock(read_limit: Option<u64>, write_limit: Option<u64>, clock: impl ThrottleClock + 'static) -> Self {
+        let now = clock.now();
+
+        Self {
+            buckets: Arc::new(Mutex::new([
+                TokenBucket::new(read_limit, now),
+                TokenBucket::new(write_limit, now)
+            ])),
+            clock: Arc::new(clock)
+        }
+    }
+
+    #[inline]
+    /// Get reads limit in bytes per second.
+    pub fn read_limit(&self) -> Option<u64> {
+        self.limit(Self::READ)
+    }
+
+    #[inline]
+    /// Get writes limit in bytes per second.
+    pub fn write_limit(&self) -> Option<u64> {
+        self.limit(Self::WRITE)
+    }
+
+    #[inline]
+    /// Change reads limit in bytes per second.
+    /// `None` or zero disables the limit.
+    pub fn set_read_limit(&self, limit: Option<u64>) {
+        self.set_limit(Self::READ, limit);
+    }
+
+    #[inline]
+    /// Change writes limit in bytes per second.
+    /// `None` or zero disables the limit.
+    pub fn set_write_limit(&self, limit: Option<u64>) {
+        self.set_limit(Self::WRITE, limit);
+    }
+
+    fn limit(&self, bucket: usize) -> Option<u64> {
+        self.buckets.lock()
+            .expect("Failed to lock throttle buckets")[bucket].rate
+    }
+
+    fn set_limit(&self, bucket: usize, limit: Option<u64>) {
+        self.buckets.lock()
+            .expect("Failed to lock throttle buckets")[bucket].set_rate(limit, self.clock.now());
+    }
+
+    /// Block the current thread until the given amount
+    /// of bytes can be transferred.
+    fn acquire(&self, bucket: usize, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+
+        let delay = self.buckets.lock()
+            .expect("Failed to lock throttle buckets")[bucket].take(bytes, self.clock.now());
+
+        if !delay.is_zero() {
+            self.clock.sleep(delay);
+        }
+    }
+
+    /// Take budget for the given amount of bytes without blocking
+    /// the current thread. Return zero duration if the bytes can
+    /// be transferred now, or time until the budget is refilled.
+    pub(crate) fn try_acquire(&self, bucket: usize, bytes: u64) -> Duration {
+        if bytes == 0 {
+            return Duration::ZERO;
+        }
+
+        self.buckets.lock()
+            .expect("Failed to lock throttle buckets")[bucket].try_take(bytes, self.clock.now())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Wrapper storage IO which limits amount of read and
+/// written bytes per second using token buckets.
+///
+/// Every bucket can accumulate up to one second of unused
+/// budget, so short bursts are not delayed. Operations
+/// larger than the available budget are performed after
+/// the missing tokens are refilled.
+///
+/// Limits can be changed at runtime using the handle
+/// returned by `ThrottledStorageIO::throttle`.
+///
+/// Throttled operations block the calling thread. To throttle
+/// the filesystem without blocking its worker use the tasks
+/// scheduler instead (see `FilesystemDriver::with_throttle`).
+pub struct ThrottledStorageIO<T> {
+    io: T,
+    throttle: StorageThrottle
+}
+
+impl<T: StorageIO> ThrottledStorageIO<T> {
+    /// Wrap given IO with reads and writes limits in bytes
+    /// per second. `None` or zero means there's no limit.
+    pub fn new(io: T, read_limit: Option<u64>, write_limit: Option<u64>) -> Self {
+        Self::with_throttle(io, StorageThrottle::new(read_limit, write_limit))
+    }
+
+    #[inline]
+    /// Wrap given IO with the given throttle.
+    pub const fn with_throttle(io: T, throttle: StorageThrottle) -> Self {
+        Self {
+            io,
+            throttle
+        }
+    }
+
+    #[inline]
+    /// Get handle to change limits of the IO.
+    pub fn throttle(&self) -> StorageThrottle {
+        self.throttle.clone()
+    }
+
+    #[inline]
+    /// Get inner IO.
+    pub const fn inner(&self) -> &T {
+        &self.io
+    }
+
+    #[inline]
+    /// Take inner IO.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl<T: StorageIO> StorageIO for ThrottledStorageIO<T> {
+    type Reader = T::Reader;
+
+    #[inline]
+    fn io(&mut self) -> &mut Self::Reader {
+        self.io.io()
+    }
+
+    #[inline]
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        self.throttle.acquire(StorageThrottle::READ, length as u64);
+
+        self.io.read(offset, length)
+    }
+
+    #[inline]
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        self.throttle.acquire(StorageThrottle::WRITE, bytes.len() as u64);
+
+        self.io.write(offset, bytes)
+    }
+
+    #[inline]
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        self.throttle.acquire(StorageThrottle::WRITE, bytes.len() as u64);
+
+        self.io.append(bytes)
+    }
+
+    #[inline]
+    fn len(&mut self) -> std::io::Result<u64> {
+        self.io.len()
+    }
+
+    #[inline]
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.io.sync()
+    }
+
+    #[inline]
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        self.io.discard(offset, length)
+    }
+
+    fn read_many(&mut self, ranges: &[(u64, usize)]) -> std::io::Result<Vec<Vec<u8>>> {
+        let bytes = ranges.iter().map(|(_, length)| *length as u64).sum();
+
+        self.throttle.acquire(StorageThrottle::READ, bytes);
+
+        self.io.read_many(ranges)
+    }
+
+    fn write_many<B: AsRef<[u8]>>(&mut self, writes: &[(u64, B)]) -> std::io::Result<()> {
+        let bytes = writes.iter().map(|(_, bytes)| bytes.as_ref().len() as u64).sum();
+
+        self.throttle.acquire(StorageThrottle::WRITE, bytes);
+
+        self.io.write_many(writes)
+    }
+
+    fn submit(&mut self, ops: Vec<StorageOp>, complete: impl FnMut(usize, std::io::Result<Vec<u8>>)) {
+        let mut read = 0;
+        let mut written = 0;
+
+        for op in &ops {
+            match op {
+                StorageOp::Read { length, .. } => read += *length as u64,
+                StorageOp::Write { bytes, .. } => written += bytes.len() as u64
+            }
+        }
+
+        self.throttle.acquire(StorageThrottle::READ, read);
+        self.throttle.acquire(StorageThrottle::WRITE, written);
+
+        self.io.submit(ops, complete);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn throttle() {
+        let clock = ManualThrottleClock::default();
+
+        let throttle = StorageThrottle::with_clock(None, Some(100_000), clock.clone());
+
+        let mut io = ThrottledStorageIO::with_throttle(MemoryStorageIO::new(), throttle.clone());
+
+        // Burst fits the bucket.
+        io.write(0, vec![1; 100_000]).unwrap();
+
+        assert_eq!(clock.now(), Duration::ZERO);
+
+        // Debt of 20 000 bytes is paid in 200ms.
+        io.write(0, vec![2; 20_000]).unwrap();
+
+        assert_eq!(clock.now(), Duration::from_millis(200));
+
+        // Reads are not limited.
+        assert_eq!(io.read(0, 100_000).unwrap()[..20_000], vec![2; 20_000]);
+        assert_eq!(clock.now(), Duration::from_millis(200));
+
+        throttle.set_write_limit(None);
+        throttle.set_read_limit(Some(1000));
+
+        assert_eq!(throttle.write_limit(), None);
+        assert_eq!(throttle.read_limit(), Some(1000));
+
+        io.write(0, vec![3; 1_000_000]).unwrap();
+
+        assert_eq!(clock.now(), Duration::from_millis(200));
+
+        io.read(0, 1500).unwrap();
+
+        assert_eq!(clock.now(), Duration::from_millis(700));
+    }
+
+    #[test]
+    fn filesystem() {
+        let clock = ManualThrottleClock::default();
+
+        let throttle = StorageThrottle::with_clock(None, None, clock.clone());
+
+        let mut fs = FilesystemDriver::with_throttle(MemoryStorageIO::new(), throttle.clone())
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let pages = (0..2)
+            .map(|_| {
+                let (response_sender, response_receiver) = flume::bounded(1);
+
+                fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+                response_receiver.recv().unwrap().unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // Limit is changed after the filesystem was started.
+        throttle.set_write_limit(Some(1000));
+
+        // Write of the whole page puts the bucket in debt for 24ms.
+        pages[0].write(0, vec![1; 1024]).unwrap();
+
+        let (write_sender, write_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::WritePage {
+            page_number: pages[0].number(),
+            offset: 0,
+            bytes: vec![2; 16],
+            response_sender: Some(write_sender)
+        }).unwrap();
+
+        // Read of another page is not blocked by the throttled write.
+        assert_eq!(pages[1].read(0, 16).unwrap(), vec![0; 16]);
+
+        assert!(write_receiver.is_empty());
+
+        clock.advance(Duration::from_millis(50));
+
+        write_receiver.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+
+        assert_eq!(pages[0].read(0, 16).unwrap(), vec![2; 16]);
+    }
+}
@@ -0,0 +1,731 @@
+use std::io::{Read, Write, Error, ErrorKind};
+
+use super::prelude::*;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Single storage IO operation recorded in the trace.
+pub enum TraceEntry {
+    Read {
+        offset: u64,
+        length: u64,
+
+        /// Seahash of the read bytes.
+        hash: Option<u64>
+    },
+
+    Write {
+        offset: u64,
+        length: u64,
+
+        /// Seahash of the written bytes.
+        hash: Option<u64>,
+
+        /// Written bytes. Required to replay the trace.
+        payload: Option<Vec<u8>>
+    },
+
+    Append {
+        length: u64,
+
+        /// Seahash of the appended bytes.
+        hash: Option<u64>,
+
+        /// Appended bytes. Required to replay the trace.
+        payload: Option<Vec<u8>>
+    },
+
+    Len {
+        len: u64
+    },
+
+    Sync,
+
+    Discard {
+        offset: u64,
+        length: u64
+    },
+
+    /// Operation which returned an error. Fields of the entry
+    /// which depend on the result (read hash, storage length)
+    /// are not stored or zeroed.
+    Failed {
+        entry: Box<TraceEntry>,
+        error: String
+    }
+}
+
+impl TraceEntry {
+    pub const KIND_MASK: u8   = 0b00000111;
+    pub const KIND_READ: u8   = 0b00000000;
+    pub const KIND_WRITE: u8  = 0b00000001;
+    pub const KIND_APPEND: u8 = 0b00000010;
+    pub const KIND_LEN: u8    = 0b00000011;
+    pub const KIND_SYNC: u8   = 0b00000100;
+    pub const KIND_DISCARD: u8 = 0b00000101;
+
+    pub const FLAG_ERROR: u8   = 0b00100000;
+    pub const FLAG_HASH: u8    = 0b01000000;
+    pub const FLAG_PAYLOAD: u8 = 0b10000000;
+
+    /// Maximal amount of bytes reserved for the payload before
+    /// it's read. Lengths are read from the trace so they can't
+    /// be trusted, and larger payloads grow as they're read.
+    const MAX_PAYLOAD_RESERVE: u64 = 1024 * 1024;
+
+    /// Read trace entry from the given reader.
+    ///
+    /// Return `None` if the reader has no more bytes.
+    pub fn read_from(reader: &mut impl Read) -> std::io::Result<Option<Self>> {
+        fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+            let mut buf = [0; 8];
+
+            reader.read_exact(&mut buf)?;
+
+            Ok(u64::from_le_bytes(buf))
+        }
+
+        fn read_hash(reader: &mut impl Read, flags: u8) -> std::io::Result<Option<u64>> {
+            if flags & TraceEntry::FLAG_HASH == 0 {
+                return Ok(None);
+            }
+
+            read_u64(reader).map(Some)
+        }
+
+        fn read_bytes(reader: &mut impl Read, length: u64) -> std::io::Result<Vec<u8>> {
+            let mut bytes = Vec::new();
+
+            bytes.try_reserve(length.min(TraceEntry::MAX_PAYLOAD_RESERVE) as usize)
+                .map_err(|err| Error::new(ErrorKind::OutOfMemory, err))?;
+
+            reader.by_ref().take(length).read_to_end(&mut bytes)?;
+
+            if bytes.len() as u64 != length {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "trace entry is truncated"));
+            }
+
+            Ok(bytes)
+        }
+
+        fn read_payload(reader: &mut impl Read, flags: u8, length: u64) -> std::io::Result<Option<Vec<u8>>> {
+            if flags & TraceEntry::FLAG_PAYLOAD == 0 {
+                return Ok(None);
+            }
+
+            read_bytes(reader, length).map(Some)
+        }
+
+        let mut flags = [0];
+
+        if reader.read(&mut flags)? == 0 {
+            return Ok(None);
+        }
+
+        let flags = flags[0];
+
+        let entry = match flags & Self::KIND_MASK {
+            Self::KIND_READ => Self::Read {
+                offset: read_u64(reader)?,
+                length: read_u64(reader)?,
+                hash: read_hash(reader, flags)?
+            },
+
+            Self::KIND_WRITE => {
+                let offset = read_u64(reader)?;
+                let length = read_u64(reader)?;
+
+                Self::Write {
+                    offset,
+                    length,
+                    hash: read_hash(reader, flags)?,
+                    payload: read_payload(reader, flags, length)?
+                }
+            }
+
+            Self::KIND_APPEND => {
+                let length = read_u64(reader)?;
+
+                Self::Append {
+                    length,
+                    hash: read_hash(reader, flags)?,
+                    payload: read_payload(reader, flags, length)?
+                }
+            }
+
+            Self::KIND_LEN => Self::Len {
+                len: read_u64(reader)?
+            },
+
+            Self::KIND_SYNC => Self::Sync,
+
+            Self::KIND_DISCARD => Self::Discard {
+                offset: read_u64(reader)?,
+                length: read_u64(reader)?
+            },
+
+            kind => return Err(Error::new(ErrorKind::InvalidData, format!("unknown trace entry kind: {kind}")))
+        };
+
+        if flags & Self::FLAG_ERROR == 0 {
+            return Ok(Some(entry));
+        }
+
+        let mut length = [0; 4];
+
+        reader.read_exact(&mut length)?;
+
+        let error = read_bytes(reader, u32::from_le_bytes(length) as u64)?;
+
+        let error = String::from_utf8(error)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+
+        Ok(Some(Self::Failed {
+            entry: Box::new(entry),
+            error
+        }))
+    }
+
+    /// Encode trace entry into bytes.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        fn push_optional(bytes: &mut Vec<u8>, hash: &Option<u64>, payload: Option<&Vec<u8>>) {
+            if let Some(hash) = hash {
+                bytes[0] |= TraceEntry::FLAG_HASH;
+
+                bytes.extend_from_slice(&hash.to_le_bytes());
+            }
+
+            if let Some(payload) = payload {
+                bytes[0] |= TraceEntry::FLAG_PAYLOAD;
+
+                bytes.extend_from_slice(payload);
+            }
+        }
+
+        match self {
+            Self::Read { offset, length, hash } => {
+                let mut bytes = Vec::with_capacity(25);
+
+                bytes.push(Self::KIND_READ);
+                bytes.extend_from_slice(&offset.to_le_bytes());
+                bytes.extend_from_slice(&length.to_le_bytes());
+
+                push_optional(&mut bytes, hash, None);
+
+                bytes
+            }
+
+            Self::Write { offset, length, hash, payload } => {
+                let mut bytes = Vec::with_capacity(25 + payload.as_ref().map(Vec::len).unwrap_or_default());
+
+                bytes.push(Self::KIND_WRITE);
+                bytes.extend_from_slice(&offset.to_le_bytes());
+                bytes.extend_from_slice(&length.to_le_bytes());
+
+                push_optional(&mut bytes, hash, payload.as_ref());
+
+                bytes
+            }
+
+            Self::Append { length, hash, payload } => {
+                let mut bytes = Vec::with_capacity(17 + payload.as_ref().map(Vec::len).unwrap_or_default());
+
+                bytes.push(Self::KIND_APPEND);
+                bytes.extend_from_slice(&length.to_le_bytes());
+
+                push_optional(&mut bytes, hash, payload.as_ref());
+
+                bytes
+            }
+
+            Self::Len { len } => {
+                let mut bytes = Vec::with_capacity(9);
+
+                bytes.push(Self::KIND_LEN);
+                bytes.extend_from_slice(&len.to_le_bytes());
+
+                bytes
+            }
+
+            Self::Sync => vec![Self::KIND_SYNC],
+
+            Self::Discard { offset, length } => {
+                let mut bytes = Vec::with_capacity(17);
+
+                bytes.push(Self::KIND_DISCARD);
+                bytes.extend_from_slice(&offset.to_le_bytes());
+                bytes.extend_from_slice(&length.to_le_bytes());
+
+                bytes
+            }
+
+            Self::Failed { entry, error } => {
+                let mut bytes = entry.to_bytes();
+
+                bytes[0] |= Self::FLAG_ERROR;
+
+                // Errors are truncated to fit the length field.
+                let error = &error.as_bytes()[..error.len().min(u32::MAX as usize)];
+
+                bytes.extend_from_slice(&(error.len() as u32).to_le_bytes());
+                bytes.extend_from_slice(error);
+
+                bytes
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Storage IO wrapper that records all the performed
+/// operations into a compact binary trace.
+///
+/// Failed operations are recorded with their errors. Written
+/// bytes are stored in the trace by default so it can be
+/// replayed later using `TraceReplayer`.
+pub struct TracingStorageIO<T, W> {
+    io: T,
+    trace: W,
+
+    hashes: bool,
+    payloads: bool
+}
+
+impl<T: StorageIO, W: Write> TracingStorageIO<T, W> {
+    #[inline]
+    /// Wrap given IO and write its trace to the given writer.
+    pub fn new(io: T, trace: W) -> Self {
+        Self {
+            io,
+            trace,
+
+            hashes: true,
+            payloads: true
+        }
+    }
+
+    #[inline]
+    /// Enable or disable hashing of read and written bytes.
+    pub fn with_hashes(mut self, hashes: bool) -> Self {
+        self.hashes = hashes;
+
+        self
+    }
+
+    #[inline]
+    /// Enable or disable storing of written bytes.
+    ///
+    /// Trace without payloads can't be replayed.
+    pub fn with_payloads(mut self, payloads: bool) -> Self {
+        self.payloads = payloads;
+
+        self
+    }
+
+    #[inline]
+    /// Get inner IO.
+    pub const fn inner(&self) -> &T {
+        &self.io
+    }
+
+    #[inline]
+    /// Get trace writer.
+    pub const fn trace(&self) -> &W {
+        &self.trace
+    }
+
+    #[inline]
+    /// Take inner IO and trace writer.
+    pub fn into_parts(self) -> (T, W) {
+        (self.io, self.trace)
+    }
+
+    #[inline]
+    fn hash(&self, bytes: &[u8]) -> Option<u64> {
+        self.hashes.then(|| seahash::hash(bytes))
+    }
+
+    #[inline]
+    fn record(&mut self, entry: TraceEntry) -> std::io::Result<()> {
+        self.trace.write_all(&entry.to_bytes())
+    }
+
+    /// Record operation with the given result. Failed operations
+    /// are recorded with their errors which are then returned.
+    fn record_result<R>(&mut self, entry: TraceEntry, result: std::io::Result<R>) -> std::io::Result<R> {
+        match result {
+            Ok(result) => {
+                self.record(entry)?;
+
+                Ok(result)
+            }
+
+            Err(err) => {
+                self.record(TraceEntry::Failed {
+                    entry: Box::new(entry),
+                    error: err.to_string()
+                })?;
+
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<T: StorageIO, W: Write> StorageIO for TracingStorageIO<T, W> {
+    type Reader = T::Reader;
+
+    #[inline]
+    fn io(&mut self) -> &mut Self::Reader {
+        self.io.io()
+    }
+
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        let result = self.io.read(offset, length);
+
+        let entry = TraceEntry::Read {
+            offset,
+            length: length as u64,
+            hash: result.as_ref().ok().and_then(|buf| self.hash(buf))
+        };
+
+        self.record_result(entry, result)
+    }
+
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        let result = self.io.write(offset, bytes);
+
+        let entry = TraceEntry::Write {
+            offset,
+            length: bytes.len() as u64,
+            hash: self.hash(bytes),
+            payload: self.payloads.then(|| bytes.to_vec())
+        };
+
+        self.record_result(entry, result)
+    }
+
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let bytes = bytes.as_ref();
+
+        let result = self.io.append(bytes);
+
+        let entry = TraceEntry::Append {
+            length: bytes.len() as u64,
+            hash: self.hash(bytes),
+            payload: self.payloads.then(|| bytes.to_vec())
+        };
+
+        self.record_result(entry, result)
+    }
+
+    fn len(&mut self) -> std::io::Result<u64> {
+        let result = self.io.len();
+
+        let entry = TraceEntry::Len {
+            len: *result.as_ref().unwrap_or(&0)
+        };
+
+        self.record_result(entry, result)
+    }
+
+    /// Sync inner IO and flush the trace writer.
+    fn sync(&mut self) -> std::io::Result<()> {
+        let result = self.io.sync();
+
+        self.record_result(TraceEntry::Sync, result)?;
+
+        self.trace.flush()
+    }
+
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        let result = self.io.discard(offset, length);
+
+        self.record_result(TraceEntry::Discard { offset, length }, result)
+    }
+}
+
+#[derive(Debug)]
+/// Iterator over entries of the binary trace.
+pub struct TraceReader<R> {
+    reader: R
+}
+
+impl<R: Read> TraceReader<R> {
+    #[inline]
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader
+        }
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = std::io::Result<TraceEntry>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        TraceEntry::read_from(&mut self.reader).transpose()
+    }
+}
+
+#[derive(Debug)]
+/// Re-apply writes of the recorded trace to a storage IO.
+pub struct TraceReplayer<R> {
+    reader: TraceReader<R>,
+    verify: bool,
+    limit: Option<u64>
+}
+
+impl<R: Read> TraceReplayer<R> {
+    #[inline]
+    pub fn new(trace: R) -> Self {
+        Self {
+            reader: TraceReader::new(trace),
+            verify: false,
+            limit: None
+        }
+    }
+
+    #[inline]
+    /// Compare recorded reads and lengths with the
+    /// replayed storage and fail on the first mismatch.
+    ///
+    /// Useful to find the moment when storage content
+    /// diverged from the one seen by the filesystem.
+    pub fn with_verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+
+        self
+    }
+
+    #[inline]
+    /// Stop after the given amount of trace entries.
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+
+        self
+    }
+
+    /// Replay the trace, returning amount of processed entries.
+    pub fn replay(self, io: &mut impl StorageIO) -> std::io::Result<u64> {
+        let mut entries = 0;
+
+        for entry in self.reader {
+            if self.limit.is_some_and(|limit| entries >= limit) {
+                break;
+            }
+
+            match entry? {
+                TraceEntry::Read { offset, length, hash } => {
+                    if let (true, Some(hash)) = (self.verify, hash) {
+                        if seahash::hash(&io.read(offset, length as usize)?) != hash {
+                            return Err(Error::new(ErrorKind::InvalidData, format!("trace entry {entries} : read of {length} bytes at offset {offset} diverged")));
+                        }
+                    }
+                }
+
+                TraceEntry::Write { offset, payload, .. } => {
+                    let Some(payload) = payload else {
+                        return Err(Error::new(ErrorKind::InvalidData, format!("trace entry {entries} : write has no payload")));
+                    };
+
+                    io.write(offset, payload)?;
+                }
+
+                TraceEntry::Append { payload, .. } => {
+                    let Some(payload) = payload else {
+                        return Err(Error::new(ErrorKind::InvalidData, format!("trace entry {entries} : append has no payload")));
+                    };
+
+                    io.append(payload)?;
+                }
+
+                TraceEntry::Len { len } => {
+                    if self.verify && io.len()? != len {
+                        return Err(Error::new(ErrorKind::InvalidData, format!("trace entry {entries} : storage length diverged")));
+                    }
+                }
+
+                TraceEntry::Sync => io.sync()?,
+
+                TraceEntry::Discard { offset, length } => io.discard(offset, length)?,
+
+                // Failed operations are not replayed.
+                TraceEntry::Failed { .. } => ()
+            }
+
+            entries += 1;
+        }
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use crate::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn entries() {
+        let entries = [
+            TraceEntry::Read { offset: 1, length: 2, hash: Some(3) },
+            TraceEntry::Read { offset: 4, length: 5, hash: None },
+            TraceEntry::Write { offset: 6, length: 3, hash: Some(7), payload: Some(vec![1, 2, 3]) },
+            TraceEntry::Write { offset: 8, length: 9, hash: None, payload: None },
+            TraceEntry::Append { length: 2, hash: None, payload: Some(vec![4, 5]) },
+            TraceEntry::Len { len: 10 },
+            TraceEntry::Sync,
+            TraceEntry::Discard { offset: 11, length: 12 },
+
+            TraceEntry::Failed {
+                entry: Box::new(TraceEntry::Write { offset: 13, length: 1, hash: None, payload: Some(vec![6]) }),
+                error: String::from("error")
+            }
+        ];
+
+        let bytes = entries.iter()
+            .flat_map(TraceEntry::to_bytes)
+            .collect::<Vec<_>>();
+
+        let parsed = TraceReader::new(bytes.as_slice())
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(parsed, entries);
+
+        // Payload length is not trusted.
+        let mut bytes = TraceEntry::Append { length: 2, hash: None, payload: Some(vec![4, 5]) }.to_bytes();
+
+        bytes[1..9].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        let err = TraceEntry::read_from(&mut bytes.as_slice()).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn failures() {
+        let io = FaultyStorageIO::new(MemoryStorageIO::new())
+            .with_fault(StorageFault::FailWrite(1));
+
+        let mut io = TracingStorageIO::new(io, Vec::new());
+
+        io.write(0, [1, 2]).unwrap();
+
+        assert!(io.write(2, [3]).is_err());
+
+        io.append([4]).unwrap();
+
+        let (io, trace) = io.into_parts();
+
+        let entries = TraceReader::new(trace.as_slice())
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(entries.len(), 3);
+
+        assert_eq!(entries[1], TraceEntry::Failed {
+            entry: Box::new(TraceEntry::Write { offset: 2, length: 1, hash: Some(seahash::hash(&[3])), payload: Some(vec![3]) }),
+            error: String::from("injected failure of write 1")
+        });
+
+        // Failed operations are skipped on replay.
+        let mut replayed = MemoryStorageIO::new();
+
+        assert_eq!(TraceReplayer::new(trace.as_slice()).replay(&mut replayed).unwrap(), 3);
+        assert_eq!(replayed.as_bytes(), io.into_inner().as_bytes());
+    }
+
+    #[test]
+    fn replay() {
+        let mut io = TracingStorageIO::new(MemoryStorageIO::new(), Vec::new());
+
+        io.write(2, [1, 2, 3]).unwrap();
+        io.append([4, 5]).unwrap();
+
+        assert_eq!(io.read(0, 6).unwrap(), &[0, 0, 1, 2, 3, 4]);
+        assert_eq!(io.len().unwrap(), 7);
+
+        let (io, trace) = io.into_parts();
+
+        let mut replayed = MemoryStorageIO::new();
+
+        assert_eq!(TraceReplayer::new(trace.as_slice()).with_verify(true).replay(&mut replayed).unwrap(), 4);
+        assert_eq!(replayed, io);
+
+        // Replay only the first write.
+        let mut replayed = MemoryStorageIO::new();
+
+        TraceReplayer::new(trace.as_slice()).with_limit(1).replay(&mut replayed).unwrap();
+
+        assert_eq!(replayed.as_bytes(), &[0, 0, 1, 2, 3]);
+
+        // Verification fails on a different storage.
+        let mut replayed = MemoryStorageIO::from_bytes([9]);
+
+        assert!(TraceReplayer::new(trace.as_slice()).with_verify(true).replay(&mut replayed).is_err());
+
+        // Trace without payloads can't be replayed.
+        let mut io = TracingStorageIO::new(MemoryStorageIO::new(), Vec::new())
+            .with_payloads(false);
+
+        io.write(0, [1]).unwrap();
+
+        let (_, trace) = io.into_parts();
+
+        assert!(TraceReplayer::new(trace.as_slice()).replay(&mut MemoryStorageIO::new()).is_err());
+    }
+
+    #[test]
+    fn filesystem() {
+        let image_path = std::env::temp_dir().join(".animefs-trace-io-test-image");
+        let trace_path = std::env::temp_dir().join(".animefs-trace-io-test-trace");
+
+        let image = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&image_path)
+            .expect("Failed to open image");
+
+        let trace = File::create(&trace_path)
+            .expect("Failed to create trace");
+
+        let mut fs = FilesystemDriver::new(TracingStorageIO::new(image, trace))
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let header = fs.read_header().unwrap();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
+        let book = Book::open(page, header.page_size);
+
+        book.write(10, vec![1; header.page_size as usize * 3]).unwrap();
+
+        fs.sync().unwrap();
+
+        let mut replayed = MemoryStorageIO::new();
+
+        TraceReplayer::new(File::open(&trace_path).unwrap())
+            .with_verify(true)
+            .replay(&mut replayed)
+            .unwrap();
+
+        assert_eq!(replayed.as_bytes(), std::fs::read(&image_path).unwrap());
+
+        std::fs::remove_file(image_path).unwrap();
+        std::fs::remove_file(trace_path).unwrap();
+    }
+}
@@ -0,0 +1,459 @@
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::os::fd::AsRawFd;
+
+use io_uring::{IoUring, opcode, types};
+
+use super::prelude::*;
+
+/// Storage IO which performs file operations through the
+/// Linux io_uring interface.
+///
+/// Operations of a `StorageIO::submit` batch are submitted to
+/// the kernel at once (up to the queue depth) and completed
+/// in any order, which allows the drive to process multiple
+/// requests in parallel. Single operations are performed
+/// as batches of one operation.
+///
+/// If the kernel fails to report completions twice in a row,
+/// the instance becomes unusable and all the following
+/// operations fail, because the kernel can still use
+/// buffers of the operations in flight.
+pub struct UringStorageIO {
+    file: File,
+    ring: IoUring,
+
+    /// Operations which were in flight when the instance
+    /// failed to wait for their completions.
+    abandoned: Option<AbandonedBatch>
+}
+
+/// Buffers of the operations which can still be used by
+/// the kernel. They're kept until the completions are
+/// received or leaked if it never happens.
+struct AbandonedBatch {
+    ops: Vec<StorageOp>,
+    buffers: Vec<Vec<u8>>,
+    in_flight: usize
+}
+
+impl UringStorageIO {
+    /// Default amount of the submission queue entries.
+    pub const DEFAULT_QUEUE_DEPTH: u32 = 64;
+
+    #[inline]
+    /// Create io_uring instance with default queue depth for the given file.
+    ///
+    /// File must be opened with both read and write access.
+    pub fn new(file: File) -> std::io::Result<Self> {
+        Self::with_queue_depth(file, Self::DEFAULT_QUEUE_DEPTH)
+    }
+
+    /// Create io_uring instance with the given queue depth
+    /// (rounded up to a power of two by the kernel).
+    pub fn with_queue_depth(file: File, queue_depth: u32) -> std::io::Result<Self> {
+        Ok(Self {
+            file,
+            ring: IoUring::new(queue_depth)?,
+            abandoned: None
+        })
+    }
+
+    #[inline]
+    /// Get reference to the underlying file.
+    pub const fn file(&self) -> &File {
+        &self.file
+    }
+
+    #[inline]
+    /// Get maximal amount of operations processed at once.
+    pub fn queue_depth(&self) -> usize {
+        self.ring.params().sq_entries() as usize
+    }
+
+    /// Perform single operation.
+    fn submit_one(&mut self, op: StorageOp) -> std::io::Result<Vec<u8>> {
+        let mut result = None;
+
+        self.submit(vec![op], |_, op_result| result = Some(op_result));
+
+        result.unwrap_or_else(|| Err(Error::other("io_uring operation was not completed")))
+    }
+}
+
+impl std::fmt::Debug for UringStorageIO {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UringStorageIO")
+            .field("file", &self.file)
+            .field("params", self.ring.params())
+            .field("is_usable", &self.abandoned.is_none())
+            .finish()
+    }
+}
+
+impl Drop for UringStorageIO {
+    fn drop(&mut self) {
+        let Some(mut abandoned) = self.abandoned.take() else {
+            return;
+        };
+
+        // Wait for the abandoned operations once more
+        // before freeing their buffers.
+        while abandoned.in_flight > 0 {
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => {
+                    let completed = self.ring.completion().count();
+
+                    abandoned.in_flight = abandoned.in_flight.saturating_sub(completed);
+                }
+
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+
+                Err(_) => {
+                    std::mem::forget(abandoned.ops);
+                    std::mem::forget(abandoned.buffers);
+
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl StorageIO for UringStorageIO {
+    type Reader = File;
+
+    #[inline]
+    fn io(&mut self) -> &mut Self::Reader {
+        &mut self.file
+    }
+
+    #[inline]
+    fn read(&mut self, offset: u64, length: usize) -> std::io::Result<Vec<u8>> {
+        self.submit_one(StorageOp::Read { offset, length })
+    }
+
+    #[inline]
+    fn write(&mut self, offset: u64, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        self.submit_one(StorageOp::Write { offset, bytes: bytes.as_ref().to_vec() })
+            .map(|_| ())
+    }
+
+    #[inline]
+    fn append(&mut self, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let len = self.len()?;
+
+        self.write(len, bytes)
+    }
+
+    #[inline]
+    fn len(&mut self) -> std::io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    #[inline]
+    fn sync(&mut self) -> std::io::Result<()> {
+        self.file.sync_data()
+    }
+
+    #[inline]
+    fn discard(&mut self, offset: u64, length: u64) -> std::io::Result<()> {
+        self.file.discard(offset, length)
+    }
+
+    fn submit(&mut self, ops: Vec<StorageOp>, mut complete: impl FnMut(usize, std::io::Result<Vec<u8>>)) {
+        if self.abandoned.is_some() {
+            for i in 0..ops.len() {
+                complete(i, Err(Error::other("io_uring instance is unusable after failing to wait for completions")));
+            }
+
+            return;
+        }
+
+        let fd = types::Fd(self.file.as_raw_fd());
+
+        // Buffers must not be moved or dropped while the kernel uses them.
+        // Vectors of the inner buffers can be moved freely since it
+        // doesn't change position of their heap allocations.
+        let mut buffers = Vec::with_capacity(ops.len());
+
+        for op in &ops {
+            match op {
+                StorageOp::Read { length, .. } => buffers.push(vec![0; *length]),
+                StorageOp::Write { .. } => buffers.push(vec![])
+            }
+        }
+
+        let queue_depth = self.queue_depth();
+
+        let mut next = 0;
+        let mut in_flight = 0;
+
+        let mut completed_ops = vec![false; ops.len()];
+        let mut failure = None;
+
+        while next < ops.len() || in_flight > 0 {
+            // Fill the submission queue. Nothing is submitted
+            // after the failure, only the completions are awaited.
+            while failure.is_none() && next < ops.len() && in_flight < queue_depth {
+                let i = next;
+
+                next += 1;
+
+                let entry = match &ops[i] {
+                    StorageOp::Read { offset, length } => {
+                        let Ok(length) = u32::try_from(*length) else {
+                            completed_ops[i] = true;
+
+                            complete(i, Err(Error::new(ErrorKind::InvalidInput, "io_uring read is too large")));
+
+                            continue;
+                        };
+
+                        opcode::Read::new(fd, buffers[i].as_mut_ptr(), length)
+                            .offset(*offset)
+                            .build()
+                    }
+
+                    StorageOp::Write { offset, bytes } => {
+                        let Ok(length) = u32::try_from(bytes.len()) else {
+                            completed_ops[i] = true;
+
+                            complete(i, Err(Error::new(ErrorKind::InvalidInput, "io_uring write is too large")));
+
+                            continue;
+                        };
+
+                        opcode::Write::new(fd, bytes.as_ptr(), length)
+                            .offset(*offset)
+                            .build()
+                    }
+                };
+
+                // Safety: buffers of the entry live until its completion
+                // is received, and the queue has space because there's
+                // less than queue_depth operations in flight.
+                unsafe {
+                    self.ring.submission()
+                        .push(&entry.user_data(i as u64))
+                        .expect("Submission queue must have free space");
+                }
+
+                in_flight += 1;
+            }
+
+            if in_flight == 0 {
+                break;
+            }
+
+            match self.ring.submit_and_wait(1) {
+                Ok(_) => (),
+
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+
+                // Operations in flight can still use their buffers,
+                // so their completions are awaited once more.
+                Err(err) if failure.is_none() => {
+                    failure = Some(err);
+
+                    continue;
+                }
+
+                Err(_) => break
+            }
+
+            let completed = self.ring.completion()
+                .map(|entry| (entry.user_data() as usize, entry.result()))
+                .collect::<Vec<_>>();
+
+            for (i, result) in completed {
+                in_flight -= 1;
+                completed_ops[i] = true;
+
+                if result < 0 {
+                    complete(i, Err(Error::from_raw_os_error(-result)));
+
+                    continue;
+                }
+
+                let n = result as usize;
+
+                let result = match &ops[i] {
+                    // Read beyond the end of the file. Remaining bytes
+                    // are either zeros or should be read again.
+                    StorageOp::Read { offset, length } if n < *length => {
+                        self.file.read_at(offset + n as u64, length - n).map(|tail| {
+                            let mut buf = std::mem::take(&mut buffers[i]);
+
+                            buf[n..].copy_from_slice(&tail);
+
+                            buf
+                        })
+                    }
+
+                    StorageOp::Read { .. } => Ok(std::mem::take(&mut buffers[i])),
+
+                    // Finish short write synchronously.
+                    StorageOp::Write { offset, bytes } => {
+                        std::os::unix::fs::FileExt::write_all_at(&self.file, &bytes[n..], offset + n as u64)
+                            .map(|_| vec![])
+                    }
+                };
+
+                complete(i, result);
+            }
+        }
+
+        let Some(err) = failure else {
+            return;
+        };
+
+        // Operations which were not submitted or completed.
+        for (i, completed) in completed_ops.into_iter().enumerate() {
+            if !completed {
+                complete(i, Err(Error::new(err.kind(), format!("failed to wait for io_uring completions : {err}"))));
+            }
+        }
+
+        // Can't wait for completions anymore. Operations in flight
+        // can still use the buffers so they're kept by the instance.
+        if in_flight > 0 {
+            self.abandoned = Some(AbandonedBatch {
+                ops,
+                buffers,
+                in_flight
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+
+    use super::*;
+
+    fn get_io(name: &str) -> (UringStorageIO, std::path::PathBuf) {
+        let path = std::env::temp_dir().join(format!(".animefs-uring-io-test-{name}"));
+
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .expect("Failed to open file");
+
+        (UringStorageIO::with_queue_depth(file, 4).expect("Failed to create io_uring"), path)
+    }
+
+    #[test]
+    fn read_write() {
+        let (mut io, path) = get_io("read-write");
+
+        assert!(io.is_empty().unwrap());
+        assert_eq!(io.read(0, 4).unwrap(), &[0, 0, 0, 0]);
+
+        io.write(0, [1, 2, 3, 4]).unwrap();
+        io.write(8, [5, 6, 7, 8]).unwrap();
+        io.append([9]).unwrap();
+
+        assert_eq!(io.len().unwrap(), 13);
+        assert_eq!(io.read(0, 16).unwrap(), &[1, 2, 3, 4, 0, 0, 0, 0, 5, 6, 7, 8, 9, 0, 0, 0]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn submit() {
+        let (mut io, path) = get_io("submit");
+
+        // More operations than the queue depth.
+        let writes = (0..16)
+            .map(|i| StorageOp::Write { offset: i * 4, bytes: vec![i as u8; 4] })
+            .collect();
+
+        let mut completed = 0;
+
+        io.submit(writes, |_, result| {
+            assert_eq!(result.unwrap(), &[]);
+
+            completed += 1;
+        });
+
+        assert_eq!(completed, 16);
+
+        let reads = (0..18)
+            .map(|i| StorageOp::Read { offset: i * 4, length: 4 })
+            .collect();
+
+        let mut buf = vec![vec![]; 18];
+
+        io.submit(reads, |i, result| buf[i] = result.unwrap());
+
+        for (i, bytes) in buf.into_iter().enumerate() {
+            assert_eq!(bytes, vec![if i < 16 { i as u8 } else { 0 }; 4]);
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unusable() {
+        let (mut io, path) = get_io("unusable");
+
+        io.abandoned = Some(AbandonedBatch {
+            ops: vec![],
+            buffers: vec![],
+            in_flight: 0
+        });
+
+        assert!(io.write(0, [1, 2, 3]).is_err());
+        assert!(io.read(0, 3).is_err());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn filesystem() {
+        let (io, path) = get_io("filesystem");
+
+        let mut fs = FilesystemDriver::with_tasks_batch(io, 16)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let header = fs.read_header().unwrap();
+
+        let books = (0..4).map(|_| {
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+            Book::open(response_receiver.recv().unwrap().unwrap(), header.page_size)
+        }).collect::<Vec<_>>();
+
+        // Write books from multiple threads so their tasks are batched.
+        std::thread::scope(|scope| {
+            for (i, book) in books.iter().enumerate() {
+                scope.spawn(move || {
+                    for j in 0..8 {
+                        book.write(j * header.page_size / 2, vec![i as u8 * 8 + j as u8; header.page_size as usize]).unwrap();
+                    }
+                });
+            }
+        });
+
+        for (i, book) in books.iter().enumerate() {
+            let buf = book.read(0, header.page_size * 4).unwrap();
+
+            for (j, chunk) in buf.chunks(header.page_size as usize / 2).enumerate() {
+                let expected = std::cmp::min(j, 7) as u8;
+
+                assert!(chunk.iter().all(|byte| *byte == i as u8 * 8 + expected));
+            }
+        }
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,5 +1,69 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+
 use crate::prelude::*;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Header of the book stored at the beginning of its
+/// entry page's body. Entry pages which store it are
+/// marked with the `PageHeader::has_book_header` flag.
+pub struct BookHeader {
+    /// Length in bytes of the data written to the book.
+    pub len: u64,
+
+    /// Amount of pages of the book.
+    pub pages: u32,
+
+    /// Number of the root page of the book's pages index,
+    /// or `None` if the index wasn't built yet.
+    pub index_page_number: Option<u32>,
+
+    /// Depth of the book's pages index.
+    pub index_depth: u8
+}
+
+impl BookHeader {
+    pub const LENGTH: usize = 18;
+
+    pub const FLAG_HAS_INDEX: u8 = 0b00000001;
+
+    /// Parse book header from the given bytes slice.
+    pub fn from_bytes(bytes: &[u8; Self::LENGTH]) -> Self {
+        Self {
+            len: u64::from_le_bytes([
+                bytes[0], bytes[1], bytes[2], bytes[3],
+                bytes[4], bytes[5], bytes[6], bytes[7]
+            ]),
+
+            pages: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+
+            index_page_number: (bytes[12] & Self::FLAG_HAS_INDEX == Self::FLAG_HAS_INDEX)
+                .then(|| u32::from_le_bytes([bytes[13], bytes[14], bytes[15], bytes[16]])),
+
+            index_depth: bytes[17]
+        }
+    }
+
+    /// Encode book header into the bytes slice.
+    pub fn to_bytes(&self) -> [u8; Self::LENGTH] {
+        let mut bytes = [0; Self::LENGTH];
+
+        bytes[0..8].copy_from_slice(&self.len.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.pages.to_le_bytes());
+
+        if let Some(index_page_number) = self.index_page_number {
+            bytes[12] |= Self::FLAG_HAS_INDEX;
+
+            bytes[13..17].copy_from_slice(&index_page_number.to_le_bytes());
+        }
+
+        bytes[17] = self.index_depth;
+
+        bytes
+    }
+}
+
 #[derive(Debug, Clone)]
 /// Book is a meta-structure that allows you to
 /// read and write data in a filesystem using
@@ -10,6 +74,26 @@ use crate::prelude::*;
 /// merge them into a single read-write buffer and
 /// automatically create new ones when needed.
 ///
+/// Books created with `Book::create` store the book header
+/// at the beginning of the entry page's body, and their data
+/// starts right after it. The header stores length of the
+/// written data, amount of pages and the pages index. Books
+/// opened from pages without the header (e.g. written with
+/// the `Page` API) are neither measured nor indexed, and
+/// their length equals to the size of all their pages.
+///
+/// Numbers of the book's pages are stored in the index,
+/// which is a radix tree of separate pages referenced by
+/// the book header. Every index page stores `page_size / 4`
+/// numbers of its children, so locating any page takes
+/// a logarithmic amount of small page reads instead of
+/// walking the whole pages chain. Index is built and updated
+/// by writes only, so reading a book never changes the
+/// filesystem, and books which don't have it are walked.
+/// Small books are not indexed at all. Book must not be
+/// written through several handles at once, otherwise
+/// every one of them can build its own index.
+///
 /// ```text
 /// +---+
 /// |   |
@@ -32,6 +116,10 @@ pub struct Book {
 }
 
 impl Book {
+    /// Books with up to this amount of pages are walked
+    /// instead of being indexed.
+    pub const INDEX_MIN_PAGES: u64 = 8;
+
     #[inline]
     pub const fn open(entry_page: Page, page_size: u64) -> Self {
         Self {
@@ -40,83 +128,696 @@ impl Book {
         }
     }
 
+    /// Create new empty book on the given page, storing
+    /// the book header at the beginning of its body.
+    pub fn create(entry_page: Page, page_size: u64) -> anyhow::Result<Self> {
+        if page_size <= BookHeader::LENGTH as u64 {
+            anyhow::bail!("Failed to create book 0x{:08x} : page is too small to store book header", entry_page.number());
+        }
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        entry_page.handler().send_high(FilesystemTask::ReadFilesystemHeader { response_sender })
+            .map_err(|err| anyhow::anyhow!("Failed to create book 0x{:08x} : filesystem closed : {err}", entry_page.number()))?;
+
+        let header = response_receiver.recv()
+            .map_err(|err| anyhow::anyhow!("Failed to create book 0x{:08x} : filesystem closed : {err}", entry_page.number()))?;
+
+        // Legacy readers would treat the book header as data.
+        if header.version == 0 {
+            anyhow::bail!("Failed to create book 0x{:08x} : book headers are not supported by the filesystem format version 0", entry_page.number());
+        }
+
+        let page_header = entry_page.read_header()?;
+
+        if page_header.has_book_header {
+            anyhow::bail!("Failed to create book 0x{:08x} : page already stores a book", entry_page.number());
+        }
+
+        let book = Self::open(entry_page, page_size);
+
+        book.write_header(&BookHeader {
+            len: 0,
+            pages: book.chain()?.len() as u32,
+            index_page_number: None,
+            index_depth: 0
+        })?;
+
+        // Page is marked only after the header is written.
+        book.entry_page.write_header(PageHeader {
+            has_book_header: true,
+            ..page_header
+        })?;
+
+        Ok(book)
+    }
+
     #[inline]
     pub const fn entry_page(&self) -> &Page {
         &self.entry_page
     }
 
+    #[inline]
+    pub const fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    /// Read header of the book, or `None` if
+    /// the entry page doesn't store it.
+    pub fn read_header(&self) -> anyhow::Result<Option<BookHeader>> {
+        if !self.entry_page.read_header()?.has_book_header {
+            return Ok(None);
+        }
+
+        let bytes = self.entry_page.read(0, BookHeader::LENGTH as u64)?;
+
+        if bytes.len() != BookHeader::LENGTH {
+            anyhow::bail!("Failed to read header of book 0x{:08x} : page is too small", self.entry_page.number());
+        }
+
+        let mut header = [0; BookHeader::LENGTH];
+
+        header.copy_from_slice(&bytes);
+
+        Ok(Some(BookHeader::from_bytes(&header)))
+    }
+
+    #[inline]
+    fn write_header(&self, header: &BookHeader) -> anyhow::Result<()> {
+        self.entry_page.write(0, header.to_bytes())?;
+
+        Ok(())
+    }
+
+    #[inline]
+    /// Get offset of the book's data in the pages chain.
+    pub(crate) const fn data_offset(header: Option<&BookHeader>) -> u64 {
+        if header.is_some() {
+            BookHeader::LENGTH as u64
+        } else {
+            0
+        }
+    }
+
+    /// Get length in bytes of the data written to the book.
+    ///
+    /// Length of the book without header equals
+    /// to the size of all its pages.
+    pub fn len(&self) -> anyhow::Result<u64> {
+        match self.read_header()? {
+            Some(header) => Ok(header.len),
+            None => Ok(self.chain()?.len() as u64 * self.page_size)
+        }
+    }
+
+    #[inline]
+    /// Check if no data was written to the book.
+    pub fn is_empty(&self) -> anyhow::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
     /// Read body with given offset and length.
     ///
     /// This method will return zeros if there's no content
-    /// on given offset.
-    pub fn read(&self, mut offset: u64, mut length: u64) -> Vec<u8> {
-        let mut page = self.entry_page.clone();
+    /// on given offset. Missing pages are not created. Bytes
+    /// of all the pages are read using a single vectored
+    /// IO operation.
+    pub fn read(&self, offset: u64, mut length: u64) -> anyhow::Result<Vec<u8>> {
+        let total = length as usize;
+
+        let header = self.read_header()?;
+
+        let mut offset = offset + Self::data_offset(header.as_ref());
 
         // Locate page at given offset.
-        while offset >= self.page_size {
-            page = page.create_next_page();
+        let mut page = self.locate(header.as_ref(), offset / self.page_size)?;
+
+        offset %= self.page_size;
+
+        // Collect pages which store requested bytes.
+        // Offset is always equal to 0 for the next pages.
+        let mut pages = Vec::new();
+
+        while let Some(current) = page {
+            let n = std::cmp::min(length, self.page_size - offset);
+
+            pages.push((current.number(), offset, n));
+
+            length -= n;
+            offset = 0;
 
-            offset -= self.page_size;
+            if length == 0 {
+                break;
+            }
+
+            page = current.read_next_page()?;
+        }
+
+        if pages.is_empty() {
+            return Ok(vec![0; total]);
         }
 
-        // Read the bytes from it.
-        let mut buf = page.read(offset, length);
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        self.entry_page.handler().send_normal(FilesystemTask::ReadPages {
+            pages,
+            response_sender
+        }).map_err(|err| {
+            anyhow::anyhow!("Failed to read book 0x{:08x} : filesystem closed : {err}", self.entry_page.number())
+        })?;
 
-        length -= buf.len() as u64;
+        let bufs = response_receiver.recv()
+            .map_err(|err| {
+                anyhow::anyhow!("Failed to read book 0x{:08x} : filesystem closed : {err}", self.entry_page.number())
+            })?
+            .with_context(|| {
+                format!("Failed to read book 0x{:08x}", self.entry_page.number())
+            })?;
 
-        // If we didn't read all the bytes - they're stored on the next page.
-        // Keep reading until we read enough.
-        while length > 0 {
-            page = page.create_next_page();
+        let mut buf = bufs.concat();
 
-            // Offset is always equal to 0 for the next pages.
-            let new_buf = page.read(0, length);
+        // Bytes of the missing pages are zeros.
+        buf.resize(total, 0);
 
-            length -= new_buf.len() as u64;
+        Ok(buf)
+    }
 
-            buf.extend(new_buf);
+    /// Read body with given offset and length within
+    /// the book's length.
+    ///
+    /// Returned buffer is shorter than requested if the
+    /// end of the book was reached, and empty if the
+    /// offset is beyond it.
+    pub fn read_clamped(&self, offset: u64, length: u64) -> anyhow::Result<Vec<u8>> {
+        let len = self.len()?;
+
+        if offset >= len {
+            return Ok(vec![]);
         }
 
-        buf
+        self.read(offset, std::cmp::min(length, len - offset))
     }
 
     /// Write data to the given offset.
     ///
-    /// This method will overwrite existing data.
-    pub fn write(&self, mut offset: u64, bytes: impl Into<Vec<u8>>) {
-        let mut page = self.entry_page.clone();
+    /// This method will overwrite existing data. Bytes
+    /// of all the pages are written using a single
+    /// vectored IO operation.
+    pub fn write(&self, offset: u64, bytes: impl Into<Vec<u8>>) -> anyhow::Result<()> {
+        let mut bytes = bytes.into();
 
-        // Locate page at given offset.
-        while offset >= self.page_size {
-            page = page.create_next_page();
+        let mut header = self.read_header()?;
+
+        // Books which lost their index are indexed again.
+        if let Some(book_header) = header.filter(|header| self.needs_index(header)) {
+            self.build_index(book_header)?;
 
-            offset -= self.page_size;
+            header = self.read_header()?;
         }
 
-        // Write the bytes to it.
-        let mut tail = page.write(offset, bytes);
+        let end = offset + bytes.len() as u64;
+
+        let mut offset = offset + Self::data_offset(header.as_ref());
+
+        // Pages of the books without header are never indexed.
+        let total_pages = header.map_or(u64::MAX, |header| header.pages as u64);
+
+        // Locate the last existing page before the given offset
+        // and create the missing ones. Books without header
+        // are walked from the entry page.
+        let mut page_index = match &header {
+            Some(header) => {
+                let Some(last_page) = (header.pages as u64).checked_sub(1) else {
+                    anyhow::bail!("Failed to write book 0x{:08x} : invalid book header : book has no pages", self.entry_page.number());
+                };
+
+                std::cmp::min(offset / self.page_size, last_page)
+            }
+
+            None => 0
+        };
 
-        // If some bytes weren't written - keep locating
-        // next pages and writing them there.
-        while !tail.is_empty() {
-            page = page.create_next_page();
-            tail = page.write(0, tail);
+        let mut page = match self.locate(header.as_ref(), page_index)? {
+            Some(page) => page,
+
+            None => {
+                page_index = 0;
+
+                self.entry_page.clone()
+            }
+        };
+
+        while page_index < offset / self.page_size {
+            page = self.create_next_page(&page, page_index, total_pages)?;
+            page_index += 1;
+        }
+
+        offset %= self.page_size;
+
+        // Split bytes between the pages, locating
+        // or creating next pages when needed.
+        let mut pages = Vec::new();
+
+        loop {
+            let n = std::cmp::min(bytes.len() as u64, self.page_size - offset) as usize;
+
+            let tail = bytes.split_off(n);
+
+            pages.push((page.number(), offset, bytes));
+
+            bytes = tail;
+            offset = 0;
+
+            if bytes.is_empty() {
+                break;
+            }
+
+            page = self.create_next_page(&page, page_index, total_pages)?;
+            page_index += 1;
+        }
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        self.entry_page.handler().send_normal(FilesystemTask::WritePages {
+            pages,
+            response_sender: Some(response_sender)
+        }).map_err(|err| {
+            anyhow::anyhow!("Failed to write book 0x{:08x} : filesystem closed : {err}", self.entry_page.number())
+        })?;
+
+        response_receiver.recv()
+            .map_err(|err| {
+                anyhow::anyhow!("Failed to write book 0x{:08x} : filesystem closed : {err}", self.entry_page.number())
+            })?
+            .with_context(|| {
+                format!("Failed to write book 0x{:08x}", self.entry_page.number())
+            })?;
+
+        match header {
+            Some(header) => {
+                let len = std::cmp::max(header.len, end);
+                let pages = std::cmp::max(total_pages, page_index + 1);
+
+                self.write_book_header(len, pages)
+            }
+
+            None => Ok(())
         }
     }
 
+    #[inline]
+    /// Write data to the end of the book.
+    pub fn append(&self, bytes: impl Into<Vec<u8>>) -> anyhow::Result<()> {
+        self.write(self.len()?, bytes)
+    }
+
     /// Get number of allocated pages.
-    pub fn pages(&self) -> u64 {
-        let mut pages = 1;
+    pub fn pages(&self) -> anyhow::Result<u64> {
+        match self.read_header()? {
+            Some(header) => Ok(header.pages as u64),
+            None => Ok(self.chain()?.len() as u64)
+        }
+    }
+
+    /// Store length and amount of pages in the book header.
+    /// Books without header are not changed.
+    pub(crate) fn write_book_header(&self, len: u64, pages: u64) -> anyhow::Result<()> {
+        let Some(header) = self.read_header()? else {
+            return Ok(());
+        };
+
+        if header.len == len && header.pages as u64 == pages {
+            return Ok(());
+        }
+
+        self.write_header(&BookHeader {
+            len,
+            pages: pages as u32,
+            ..header
+        })
+    }
+
+    #[inline]
+    /// Get amount of page numbers stored in a single index page.
+    const fn index_fanout(&self) -> u64 {
+        self.page_size / 4
+    }
+
+    #[inline]
+    /// Books with pages smaller than two page numbers
+    /// can't be indexed and are walked instead.
+    const fn is_indexed(&self) -> bool {
+        self.index_fanout() >= 2
+    }
+
+    #[inline]
+    fn page(&self, page_number: u32) -> Page {
+        Page::new(page_number, self.entry_page.handler().clone())
+    }
+
+    /// Get page with the given index in the book,
+    /// or `None` if the book doesn't have it.
+    pub fn page_at(&self, index: u64) -> anyhow::Result<Option<Page>> {
+        if index == 0 {
+            return Ok(Some(self.entry_page.clone()));
+        }
+
+        self.locate(self.read_header()?.as_ref(), index)
+    }
+
+    /// Get page with the given index in the book with
+    /// given header, using the index if there's any.
+    fn locate(&self, header: Option<&BookHeader>, index: u64) -> anyhow::Result<Option<Page>> {
+        if index == 0 {
+            return Ok(Some(self.entry_page.clone()));
+        }
+
+        if let Some(header) = header {
+            if index >= header.pages as u64 {
+                return Ok(None);
+            }
+
+            if let Some(mut node) = header.index_page_number {
+                let fanout = self.index_fanout();
+
+                for level in (0..header.index_depth as u32).rev() {
+                    node = self.read_index_entry(node, (index / fanout.pow(level)) % fanout)?;
+                }
+
+                return Ok(Some(self.page(node)));
+            }
+        }
 
         let mut page = self.entry_page.clone();
 
-        while let Some(child) = page.read_next_page() {
-            page = child;
+        for _ in 0..index {
+            let Some(next) = page.read_next_page()? else {
+                return Ok(None);
+            };
+
+            page = next;
+        }
+
+        Ok(Some(page))
+    }
+
+    /// Get the page following the given one, creating it if
+    /// it doesn't exist. New pages are added to the index.
+    pub(crate) fn create_next_page(&self, page: &Page, page_index: u64, total_pages: u64) -> anyhow::Result<Page> {
+        let next_page = page.create_next_page()?;
+
+        // Pages beyond the book's pages amount are not indexed.
+        if page_index + 1 >= total_pages {
+            self.index_insert(page_index + 1, next_page.number())?;
+        }
+
+        Ok(next_page)
+    }
+
+    fn read_index_entry(&self, page_number: u32, slot: u64) -> anyhow::Result<u32> {
+        let entry = self.page(page_number).read(slot * 4, 4)?;
+
+        Ok(u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]))
+    }
+
+    fn write_index_entry(&self, page_number: u32, slot: u64, value: u32) -> anyhow::Result<()> {
+        self.page(page_number).write(slot * 4, value.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn create_index_page(&self) -> anyhow::Result<u32> {
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        self.entry_page.handler().send_normal(FilesystemTask::CreatePage {
+            parent_page_number: None,
+            response_sender
+        }).map_err(|err| anyhow::anyhow!("Failed to create index page : filesystem closed : {err}"))?;
+
+        let page = response_receiver.recv()
+            .map_err(|err| anyhow::anyhow!("Failed to create index page : filesystem closed : {err}"))?
+            .context("Failed to create index page")?;
+
+        Ok(page.number())
+    }
+
+    #[inline]
+    /// Check if the book with given header should be indexed.
+    const fn needs_index(&self, header: &BookHeader) -> bool {
+        self.is_indexed() && header.index_page_number.is_none() && header.pages as u64 > Self::INDEX_MIN_PAGES
+    }
+
+    /// Build the index from the pages chain of the book
+    /// with given header. Must be called by writes only.
+    fn build_index(&self, header: BookHeader) -> anyhow::Result<()> {
+        let pages = self.chain()?;
+
+        let root = self.create_index_page()?;
+
+        self.write_header(&BookHeader {
+            index_page_number: Some(root),
+            index_depth: 1,
+            ..header
+        })?;
+
+        for (i, page) in pages.into_iter().enumerate() {
+            self.index_insert(i as u64, page.number())?;
+        }
+
+        Ok(())
+    }
+
+    /// Store number of the page with given index in the index,
+    /// growing the tree if needed. Pages must be inserted
+    /// in ascending order.
+    fn index_insert(&self, index: u64, page_number: u32) -> anyhow::Result<()> {
+        if !self.is_indexed() {
+            return Ok(());
+        }
+
+        // Books without header are not indexed.
+        let Some(header) = self.read_header()? else {
+            return Ok(());
+        };
+
+        // Index is built from the pages chain which already
+        // contains the page when the book becomes large enough.
+        let Some(mut root) = header.index_page_number else {
+            if index >= Self::INDEX_MIN_PAGES {
+                self.build_index(header)?;
+            }
+
+            return Ok(());
+        };
+
+        let fanout = self.index_fanout();
+
+        let mut depth = header.index_depth;
+
+        // Previous root becomes the first child of the new one.
+        // Every new root is persisted before the next one is created
+        // so retried inserts continue from the stored depth.
+        while fanout.checked_pow(depth as u32).is_some_and(|capacity| index >= capacity) {
+            let new_root = self.create_index_page()?;
+
+            self.write_index_entry(new_root, 0, root)?;
 
-            pages += 1;
+            root = new_root;
+            depth += 1;
+
+            self.write_header(&BookHeader {
+                index_page_number: Some(root),
+                index_depth: depth,
+                ..header
+            })?;
+        }
+
+        let mut node = root;
+
+        for level in (1..depth as u32).rev() {
+            let span = fanout.pow(level);
+            let slot = (index / span) % fanout;
+
+            let child = self.read_index_entry(node, slot)?;
+
+            // The first page of the subtree creates it. Existing
+            // subtree is kept if the insert is retried.
+            if index.is_multiple_of(span) && child == 0 {
+                let child = self.create_index_page()?;
+
+                self.write_index_entry(node, slot, child)?;
+
+                node = child;
+            } else {
+                node = child;
+            }
+        }
+
+        self.write_index_entry(node, index % fanout, page_number)
+    }
+
+    /// Get numbers of all the index pages
+    /// covering given amount of the book's pages.
+    fn index_pages(&self, header: Option<&BookHeader>, total_pages: u64) -> anyhow::Result<Vec<u32>> {
+        let Some(root) = header.and_then(|header| header.index_page_number) else {
+            return Ok(vec![]);
+        };
+
+        let depth = header.map(|header| header.index_depth).unwrap_or_default();
+
+        let fanout = self.index_fanout();
+
+        let mut pages = vec![root];
+        let mut nodes = vec![root];
+
+        for level in (1..depth as u32).rev() {
+            let span = fanout.pow(level);
+
+            let mut children = Vec::new();
+
+            for (i, node) in nodes.iter().enumerate() {
+                for slot in 0..fanout {
+                    if (i as u64 * fanout + slot) * span >= total_pages {
+                        break;
+                    }
+
+                    children.push(self.read_index_entry(*node, slot)?);
+                }
+            }
+
+            pages.extend_from_slice(&children);
+
+            nodes = children;
+        }
+
+        Ok(pages)
+    }
+
+    /// Read all the pages of the book in order.
+    fn chain(&self) -> anyhow::Result<Vec<Page>> {
+        let mut pages = vec![self.entry_page.clone()];
+        let mut visited = HashSet::from([self.entry_page.number()]);
+
+        while let Some(page) = pages[pages.len() - 1].read_next_page()? {
+            if !visited.insert(page.number()) {
+                anyhow::bail!(
+                    "Failed to read pages of book 0x{:08x} : page 0x{:08x} is linked twice",
+                    self.entry_page.number(),
+                    page.number()
+                );
+            }
+
+            pages.push(page);
         }
 
-        pages
+        Ok(pages)
+    }
+
+    /// Shrink the book to the given length in bytes.
+    ///
+    /// Trailing pages are unlinked from the book and freed
+    /// so they can be reused by the filesystem. Bytes after
+    /// the given length are zeroed, and the entry page is
+    /// always kept. Books which are not longer than the
+    /// given length are not changed.
+    pub fn truncate(&self, len: u64) -> anyhow::Result<()> {
+        let header = self.read_header()?;
+
+        let mut pages = self.chain()?;
+
+        let current_len = match &header {
+            Some(header) => header.len,
+            None => pages.len() as u64 * self.page_size
+        };
+
+        if len >= current_len {
+            return Ok(());
+        }
+
+        // Offset of the book's end in the pages chain.
+        let end = len + Self::data_offset(header.as_ref());
+
+        let keep = std::cmp::max(end.div_ceil(self.page_size), 1) as usize;
+
+        if keep > pages.len() {
+            return self.write_book_header(len, pages.len() as u64);
+        }
+
+        if keep == pages.len() {
+            self.write_book_header(len, keep as u64)?;
+        }
+
+        else {
+            let index_pages = self.index_pages(header.as_ref(), pages.len() as u64)?;
+
+            let new_header = BookHeader {
+                len,
+                pages: keep as u32,
+                index_page_number: None,
+                index_depth: 0
+            };
+
+            // Index is dropped before its pages are freed and
+            // rebuilt once the pages are unlinked. On failure
+            // trailing pages are left linked but not counted.
+            if header.is_some() {
+                self.write_header(&new_header)?;
+            }
+
+            for page_number in index_pages {
+                self.page(page_number).free()?;
+            }
+
+            let last_page = &pages[keep - 1];
+
+            // Unlink pages before freeing them so they're
+            // leaked rather than reused twice on failure.
+            let mut page_header = last_page.read_header()?;
+
+            page_header.next_page_number = 0;
+            page_header.has_next = false;
+
+            last_page.write_header(page_header)?;
+
+            for page in pages.split_off(keep) {
+                page.free()?;
+            }
+
+            if header.is_some() && self.needs_index(&new_header) {
+                self.build_index(new_header)?;
+            }
+        }
+
+        let offset = end - (keep as u64 - 1) * self.page_size;
+
+        if offset < self.page_size {
+            pages[keep - 1].write(offset, vec![0; (self.page_size - offset) as usize])?;
+        }
+
+        Ok(())
+    }
+
+    /// Free all the pages of the book, including
+    /// the entry page and the index pages, so they
+    /// can be reused by the filesystem.
+    pub fn delete(self) -> anyhow::Result<()> {
+        let header = self.read_header()?;
+
+        let pages = self.chain()?;
+        let index_pages = self.index_pages(header.as_ref(), pages.len() as u64)?;
+
+        // Index pages are freed after the entry page
+        // so they're leaked rather than referenced
+        // on failure.
+        for page in pages {
+            page.free()?;
+        }
+
+        for page_number in index_pages {
+            self.page(page_number).free()?;
+        }
+
+        Ok(())
     }
 }
 
@@ -128,26 +829,26 @@ mod tests {
     #[test]
     fn read() {
         with_fs("book-read", |fs, _| {
-            let header = fs.read_header();
+            let header = fs.read_header().unwrap();
 
             let mut page = Page::new(0, fs.handler().to_owned());
             let book = Book::open(page.clone(), header.page_size);
 
             for i in 1..=255 {
-                page.write(0, vec![i; header.page_size as usize]);
+                page.write(0, vec![i; header.page_size as usize]).unwrap();
 
-                page = page.create_next_page();
+                page = page.create_next_page().unwrap();
             }
 
             for i in 1..255_u8 {
                 let j = (i as u64 - 1) * header.page_size;
 
-                let page = book.read(j, header.page_size);
+                let page = book.read(j, header.page_size).unwrap();
 
                 assert_eq!(page.len() as u64, header.page_size);
                 assert_eq!(page, vec![i; header.page_size as usize]);
 
-                let page = book.read(j + i as u64, header.page_size);
+                let page = book.read(j + i as u64, header.page_size).unwrap();
 
                 let k = (header.page_size - i as u64) as usize;
 
@@ -161,30 +862,320 @@ mod tests {
     #[test]
     fn write() {
         with_fs("book-write", |fs, _| {
-            let header = fs.read_header();
+            let header = fs.read_header().unwrap();
 
             let mut page = Page::new(0, fs.handler().to_owned());
             let book = Book::open(page.clone(), header.page_size);
 
             for i in 0..=255 {
-                book.write((i as u64) * header.page_size, vec![i; header.page_size as usize]);
-                book.write((i as u64) * header.page_size, vec![!i; header.page_size as usize / 2]);
+                book.write((i as u64) * header.page_size, vec![i; header.page_size as usize]).unwrap();
+                book.write((i as u64) * header.page_size, vec![!i; header.page_size as usize / 2]).unwrap();
             }
 
             for i in 0..255 {
-                let buf = page.read(0, header.page_size);
+                let buf = page.read(0, header.page_size).unwrap();
 
                 assert_eq!(&buf[..header.page_size as usize / 2], vec![!i; header.page_size as usize / 2]);
                 assert_eq!(&buf[header.page_size as usize / 2..], vec![i; header.page_size as usize / 2]);
 
-                page = page.read_next_page().unwrap();
+                page = page.read_next_page().unwrap().unwrap();
             }
 
-            book.write(header.page_size / 2 - 1, vec![17; header.page_size as usize * 4 + 1]);
+            book.write(header.page_size / 2 - 1, vec![17; header.page_size as usize * 4 + 1]).unwrap();
 
-            let buf = book.read(header.page_size / 2 - 1, header.page_size * 4 + 1);
+            let buf = book.read(header.page_size / 2 - 1, header.page_size * 4 + 1).unwrap();
 
             assert_eq!(buf, vec![17; header.page_size as usize * 4 + 1]);
         });
     }
+
+    #[test]
+    fn len() {
+        with_fs("book-len", |fs, _| {
+            let header = fs.read_header().unwrap();
+
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+            let book = Book::create(response_receiver.recv().unwrap().unwrap(), header.page_size).unwrap();
+
+            assert!(book.is_empty().unwrap());
+            assert_eq!(book.pages().unwrap(), 1);
+            assert_eq!(book.read_clamped(0, 10).unwrap(), &[]);
+
+            let len = header.page_size + header.page_size / 2;
+
+            book.append(vec![1; len as usize]).unwrap();
+            book.append([2, 3]).unwrap();
+
+            assert_eq!(book.len().unwrap(), len + 2);
+            assert_eq!(book.pages().unwrap(), 2);
+
+            assert_eq!(book.read_clamped(len - 1, 10).unwrap(), &[1, 2, 3]);
+            assert_eq!(book.read_clamped(len + 2, 10).unwrap(), &[]);
+
+            // Reads past the end don't create pages.
+            assert_eq!(book.read(header.page_size * 4, 4).unwrap(), &[0; 4]);
+            assert_eq!(book.pages().unwrap(), 2);
+
+            // Gaps are filled with zeros.
+            book.write(header.page_size * 3, [4]).unwrap();
+
+            assert_eq!(book.len().unwrap(), header.page_size * 3 + 1);
+            assert_eq!(book.pages().unwrap(), 4);
+            assert_eq!(book.read_clamped(header.page_size * 3 - 1, 10).unwrap(), &[0, 4]);
+
+            // Overwrites don't change the length.
+            book.write(0, [5; 10]).unwrap();
+
+            assert_eq!(book.len().unwrap(), header.page_size * 3 + 1);
+
+            book.truncate(10).unwrap();
+
+            // Length is stored in the book header.
+            let book = Book::open(book.entry_page().clone(), header.page_size);
+
+            assert_eq!(book.len().unwrap(), 10);
+            assert_eq!(book.pages().unwrap(), 1);
+            assert_eq!(book.read_clamped(0, 100).unwrap(), &[5; 10]);
+
+            // Corrupted header without pages is rejected.
+            let book_header = book.read_header().unwrap().unwrap();
+
+            book.entry_page().write(0, BookHeader { pages: 0, ..book_header }.to_bytes()).unwrap();
+
+            assert!(book.write(0, [6]).is_err());
+        });
+    }
+
+    #[test]
+    fn legacy() {
+        // Version 0 header: 32 bytes pages with seahash names checksum.
+        let mut bytes = 32_u64.to_le_bytes().to_vec();
+
+        bytes.extend_from_slice(&FilesystemHeader::FLAG_NAMES_CHECKSUM_SEAHASH.to_le_bytes());
+
+        let mut fs = FilesystemDriver::new(MemoryStorageIO::from_bytes(bytes))
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
+
+        // Book headers are not supported by the legacy format.
+        assert!(Book::create(page.clone(), 32).is_err());
+
+        let page_header = page.read_header().unwrap();
+
+        assert!(!page_header.has_book_header);
+        assert_eq!(page.read(0, 32).unwrap(), vec![0; 32]);
+
+        let err = page.write_header(PageHeader { has_book_header: true, ..page_header }).unwrap_err();
+
+        assert_eq!(err.downcast_ref::<std::io::Error>().map(std::io::Error::kind), Some(std::io::ErrorKind::Unsupported));
+
+        // Books are still usable without the header.
+        let book = Book::open(page, 32);
+
+        book.write(0, vec![1; 64]).unwrap();
+
+        assert_eq!(book.read_header().unwrap(), None);
+        assert_eq!(book.read(0, 64).unwrap(), vec![1; 64]);
+    }
+
+    #[test]
+    fn index() {
+        let mut io = MemoryStorageIO::new();
+
+        // Every index page stores 8 page numbers.
+        io.write(0, FilesystemHeader { page_size: 32, ..FilesystemHeader::default() }.to_bytes()).unwrap();
+
+        let mut fs = FilesystemDriver::new(io)
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let book = Book::create(response_receiver.recv().unwrap().unwrap(), 32).unwrap();
+
+        // Every page stores bytes equal to its index.
+        let data_offset = BookHeader::LENGTH as u64;
+
+        let bytes = (data_offset..32 * 100)
+            .map(|i| (i / 32) as u8)
+            .collect::<Vec<_>>();
+
+        book.write(0, bytes).unwrap();
+
+        let header = book.read_header().unwrap().unwrap();
+
+        assert!(header.index_page_number.is_some());
+        assert_eq!(header.index_depth, 3);
+        assert_eq!(header.pages, 100);
+
+        let mut chain = vec![book.entry_page().clone()];
+
+        while let Some(page) = chain[chain.len() - 1].read_next_page().unwrap() {
+            chain.push(page);
+        }
+
+        assert_eq!(chain.len(), 100);
+
+        for (i, page) in chain.iter().enumerate() {
+            assert_eq!(book.page_at(i as u64).unwrap().unwrap().number(), page.number());
+        }
+
+        assert!(book.page_at(100).unwrap().is_none());
+
+        // Page is located without walking the chain.
+        let read_headers = |fs: &FilesystemDriver<MemoryStorageIO>| {
+            fs.stats().unwrap().task("ReadPageHeader").unwrap().count
+        };
+
+        let before = read_headers(&fs);
+
+        assert_eq!(book.read(32 * 99 - data_offset, 32).unwrap(), vec![99; 32]);
+        assert!(read_headers(&fs) - before < 5);
+
+        let create_pages = |fs: &FilesystemDriver<MemoryStorageIO>| {
+            fs.stats().unwrap().task("CreatePage").map(|task| task.count).unwrap_or_default()
+        };
+
+        // Write which failed before updating the book header
+        // is retried without replacing the index pages.
+        book.entry_page().write(0, BookHeader { pages: 64, ..header }.to_bytes()).unwrap();
+
+        let before = create_pages(&fs);
+
+        book.write(32 * 99 - data_offset, vec![99; 32]).unwrap();
+
+        assert_eq!(create_pages(&fs), before);
+        assert_eq!(book.read_header().unwrap().unwrap(), header);
+
+        for (i, page) in chain.iter().enumerate() {
+            assert_eq!(book.page_at(i as u64).unwrap().unwrap().number(), page.number());
+        }
+
+        // Index is rebuilt by truncation.
+        book.truncate(32 * 10 - data_offset).unwrap();
+
+        let header = book.read_header().unwrap().unwrap();
+
+        assert!(header.index_page_number.is_some());
+        assert_eq!(header.index_depth, 2);
+
+        assert_eq!(book.page_at(9).unwrap().unwrap().number(), chain[9].number());
+        assert!(book.page_at(10).unwrap().is_none());
+
+        // Books without index are walked by reads
+        // and indexed again by the next write.
+        book.entry_page().write(0, BookHeader { index_page_number: None, index_depth: 0, ..header }.to_bytes()).unwrap();
+
+        let before = create_pages(&fs);
+
+        assert_eq!(book.page_at(9).unwrap().unwrap().number(), chain[9].number());
+        assert_eq!(book.read(32 * 9 - data_offset, 32).unwrap(), vec![9; 32]);
+
+        assert_eq!(create_pages(&fs), before);
+        assert_eq!(book.read_header().unwrap().unwrap().index_page_number, None);
+
+        book.write(0, [0]).unwrap();
+
+        assert_eq!(book.read_header().unwrap().unwrap().index_depth, 2);
+        assert_eq!(book.page_at(9).unwrap().unwrap().number(), chain[9].number());
+
+        // Freed pages are reused by the book.
+        book.append(vec![100; 32 * 90]).unwrap();
+
+        assert_eq!(book.read(32 * 99 - data_offset, 32).unwrap(), vec![100; 32]);
+        assert_eq!(fs.read_header().unwrap().free_page_number, None);
+
+        book.delete().unwrap();
+    }
+
+    #[test]
+    fn truncate() {
+        with_fs("book-truncate", |fs, _| {
+            let header = fs.read_header().unwrap();
+
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+            let book = Book::create(response_receiver.recv().unwrap().unwrap(), header.page_size).unwrap();
+
+            // Data fills exactly 4 pages after the book header.
+            let data_len = header.page_size as usize * 4 - BookHeader::LENGTH;
+
+            book.write(0, vec![1; data_len]).unwrap();
+
+            let len = header.page_size + header.page_size / 2;
+
+            book.truncate(len).unwrap();
+
+            assert_eq!(book.pages().unwrap(), 2);
+
+            let buf = book.read(0, header.page_size * 2).unwrap();
+
+            assert_eq!(&buf[..len as usize], vec![1; len as usize]);
+            assert_eq!(&buf[len as usize..], vec![0; (header.page_size * 2 - len) as usize]);
+
+            assert!(fs.read_header().unwrap().free_page_number.is_some());
+
+            // Freed pages are reused when the book grows.
+            book.write(0, vec![2; data_len]).unwrap();
+
+            assert_eq!(book.pages().unwrap(), 4);
+            assert_eq!(fs.read_header().unwrap().free_page_number, None);
+
+            book.truncate(header.page_size * 10).unwrap();
+
+            assert_eq!(book.pages().unwrap(), 4);
+
+            book.truncate(0).unwrap();
+
+            assert_eq!(book.pages().unwrap(), 1);
+            assert_eq!(book.read(0, header.page_size).unwrap(), vec![0; header.page_size as usize]);
+
+            let entry_page = book.entry_page().clone();
+
+            book.delete().unwrap();
+
+            assert!(entry_page.read_header().unwrap().is_free);
+            assert!(entry_page.free().is_err());
+
+            // Books without header are truncated by their pages.
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+            let entry_page = response_receiver.recv().unwrap().unwrap();
+
+            let mut page = entry_page.clone();
+
+            for _ in 0..3 {
+                page.write(0, vec![3; header.page_size as usize]).unwrap();
+
+                page = page.create_next_page().unwrap();
+            }
+
+            let book = Book::open(entry_page, header.page_size);
+
+            assert_eq!(book.len().unwrap(), header.page_size * 4);
+
+            book.truncate(header.page_size + 1).unwrap();
+
+            assert_eq!(book.pages().unwrap(), 2);
+            assert_eq!(book.len().unwrap(), header.page_size * 2);
+            assert_eq!(book.read(header.page_size, 2).unwrap(), &[3, 0]);
+        });
+    }
 }
@@ -0,0 +1,319 @@
+use std::collections::{HashMap, BTreeMap};
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Maximal size of the pages cache.
+pub enum PagesCacheLimit {
+    /// Limit amount of cached pages.
+    Pages(usize),
+
+    /// Limit amount of bytes used by cached pages
+    /// (page header + page body).
+    Bytes(u64)
+}
+
+impl Default for PagesCacheLimit {
+    #[inline]
+    fn default() -> Self {
+        Self::Pages(0)
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Statistics of the pages cache usage.
+pub struct PagesCacheStats {
+    /// Amount of page reads served from the cache.
+    pub hits: u64,
+
+    /// Amount of page reads which required IO operation.
+    pub misses: u64,
+
+    /// Amount of currently cached pages.
+    pub pages: usize,
+
+    /// Amount of bytes used by currently cached pages.
+    pub bytes: u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+/// Page stored in the pages cache.
+pub struct CachedPage {
+    pub header: PageHeader,
+    pub body: Vec<u8>
+}
+
+#[derive(Debug, Clone)]
+/// Least recently used filesystem pages cache.
+pub struct PagesCache {
+    limit: PagesCacheLimit,
+    page_size: u64,
+
+    /// Cached pages with their last use tick.
+    pages: HashMap<u32, (u64, CachedPage)>,
+
+    /// Pages numbers ordered by their last use tick.
+    order: BTreeMap<u64, u32>,
+
+    tick: u64,
+    hits: u64,
+    misses: u64
+}
+
+impl PagesCache {
+    #[inline]
+    pub fn new(limit: PagesCacheLimit, page_size: u64) -> Self {
+        Self {
+            limit,
+            page_size,
+
+            pages: HashMap::new(),
+            order: BTreeMap::new(),
+
+            tick: 0,
+            hits: 0,
+            misses: 0
+        }
+    }
+
+    #[inline]
+    pub const fn limit(&self) -> PagesCacheLimit {
+        self.limit
+    }
+
+    /// Change limit of the cache, evicting least
+    /// recently used pages if needed.
+    pub fn set_limit(&mut self, limit: PagesCacheLimit) {
+        self.limit = limit;
+
+        self.evict(0);
+    }
+
+    #[inline]
+    /// Change size of the cached pages body.
+    ///
+    /// This will clear the cache.
+    pub fn set_page_size(&mut self, page_size: u64) {
+        self.page_size = page_size;
+
+        self.clear();
+    }
+
+    /// Get maximal amount of pages which can be cached.
+    pub fn capacity(&self) -> usize {
+        match self.limit {
+            PagesCacheLimit::Pages(pages) => pages,
+            PagesCacheLimit::Bytes(bytes) => {
+                (bytes / (PageHeader::LENGTH as u64 + self.page_size)) as usize
+            }
+        }
+    }
+
+    #[inline]
+    /// Check if the cache can store any page.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity() > 0
+    }
+
+    /// Get cached page, marking it as recently used.
+    pub fn get(&mut self, page_number: u32) -> Option<&CachedPage> {
+        match self.pages.get_mut(&page_number) {
+            Some((tick, page)) => {
+                self.hits += 1;
+                self.tick += 1;
+
+                self.order.remove(tick);
+                self.order.insert(self.tick, page_number);
+
+                *tick = self.tick;
+
+                Some(page)
+            }
+
+            None => {
+                self.misses += 1;
+
+                None
+            }
+        }
+    }
+
+    #[inline]
+    /// Get cached page without marking it as recently used
+    /// and updating cache statistics.
+    pub fn peek(&self, page_number: u32) -> Option<&CachedPage> {
+        self.pages.get(&page_number).map(|(_, page)| page)
+    }
+
+    /// Put page to the cache, replacing the already
+    /// cached one and evicting least recently used
+    /// pages if needed.
+    pub fn insert(&mut self, page_number: u32, page: CachedPage) {
+        if let Some((tick, _)) = self.pages.remove(&page_number) {
+            self.order.remove(&tick);
+        }
+
+        if !self.is_enabled() {
+            return;
+        }
+
+        self.evict(1);
+
+        self.tick += 1;
+
+        self.order.insert(self.tick, page_number);
+        self.pages.insert(page_number, (self.tick, page));
+    }
+
+    /// Update header of the cached page if it's cached.
+    pub fn update_header(&mut self, page_number: u32, header: PageHeader) {
+        if let Some((_, page)) = self.pages.get_mut(&page_number) {
+            page.header = header;
+        }
+    }
+
+    /// Update body of the cached page if it's cached.
+    pub fn update_body(&mut self, page_number: u32, offset: usize, bytes: &[u8]) {
+        if let Some((_, page)) = self.pages.get_mut(&page_number) {
+            page.body[offset..offset + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+
+    #[inline]
+    /// Remove all the pages from the cache.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.order.clear();
+    }
+
+    /// Get cache usage statistics.
+    pub fn stats(&self) -> PagesCacheStats {
+        PagesCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            pages: self.pages.len(),
+            bytes: self.pages.len() as u64 * (PageHeader::LENGTH as u64 + self.page_size)
+        }
+    }
+
+    /// Evict least recently used pages until there's
+    /// space for `reserve` more pages.
+    fn evict(&mut self, reserve: usize) {
+        let capacity = self.capacity();
+
+        while !self.pages.is_empty() && self.pages.len() + reserve > capacity {
+            if let Some((_, page_number)) = self.order.pop_first() {
+                self.pages.remove(&page_number);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(i: u8) -> CachedPage {
+        CachedPage {
+            header: PageHeader {
+                prev_page_number: i as u32,
+                next_page_number: 0,
+
+                has_prev: true,
+                has_next: false,
+                is_free: false,
+                checksum: 0,
+                compressed_size: None,
+
+                has_book_header: false
+            },
+
+            body: vec![i; 16]
+        }
+    }
+
+    #[test]
+    fn lru() {
+        let mut cache = PagesCache::new(PagesCacheLimit::Pages(2), 16);
+
+        cache.insert(1, page(1));
+        cache.insert(2, page(2));
+
+        assert_eq!(cache.get(1), Some(&page(1)));
+
+        // 2 is the least recently used page.
+        cache.insert(3, page(3));
+
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(1), Some(&page(1)));
+        assert_eq!(cache.get(3), Some(&page(3)));
+
+        cache.update_body(3, 4, &[0, 0]);
+        cache.update_header(3, page(4).header);
+
+        let cached = cache.get(3).unwrap();
+
+        assert_eq!(cached.header, page(4).header);
+        assert_eq!(&cached.body[2..8], &[3, 3, 0, 0, 3, 3]);
+
+        let stats = cache.stats();
+
+        assert_eq!(stats.hits, 4);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.pages, 2);
+        assert_eq!(stats.bytes, 2 * (PageHeader::LENGTH as u64 + 16));
+    }
+
+    #[test]
+    fn bytes_limit() {
+        let mut cache = PagesCache::new(PagesCacheLimit::Bytes(3 * (PageHeader::LENGTH as u64 + 16) - 1), 16);
+
+        assert_eq!(cache.capacity(), 2);
+
+        for i in 0..8 {
+            cache.insert(i as u32, page(i));
+        }
+
+        assert_eq!(cache.stats().pages, 2);
+
+        cache.set_limit(PagesCacheLimit::Pages(0));
+
+        assert!(!cache.is_enabled());
+        assert_eq!(cache.stats().pages, 0);
+    }
+
+    #[test]
+    fn filesystem() {
+        let mut fs = FilesystemDriver::with_pages_cache(MemoryStorageIO::new(), PagesCacheLimit::Pages(4))
+            .expect("Failed to open filesystem");
+
+        fs.daemonize();
+
+        let header = fs.read_header().unwrap();
+
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+        let page = response_receiver.recv().unwrap().unwrap();
+        let book = Book::open(page.clone(), header.page_size);
+
+        book.write(0, vec![1; header.page_size as usize * 8]).unwrap();
+        book.write(header.page_size / 2, vec![2; header.page_size as usize * 4]).unwrap();
+
+        let buf = book.read(0, header.page_size * 8).unwrap();
+
+        let n = header.page_size as usize;
+
+        assert_eq!(buf[..n / 2], vec![1; n / 2]);
+        assert_eq!(buf[n / 2..n / 2 + n * 4], vec![2; n * 4]);
+        assert_eq!(buf[n / 2 + n * 4..], vec![1; n * 4 - n / 2]);
+
+        assert_eq!(book.pages().unwrap(), 8);
+
+        let stats = fs.pages_cache_stats().unwrap();
+
+        assert!(stats.hits > 0);
+        assert_eq!(stats.pages, 4);
+    }
+}
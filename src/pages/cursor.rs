@@ -0,0 +1,316 @@
+use std::io::{Read, Write, Seek, SeekFrom, BufRead, Error, ErrorKind};
+
+use crate::prelude::*;
+
+/// Convert filesystem error into the IO error,
+/// keeping kind of the underlying IO error.
+fn io_error(err: anyhow::Error) -> Error {
+    let kind = err.downcast_ref::<Error>()
+        .map(Error::kind)
+        .unwrap_or(ErrorKind::Other);
+
+    Error::new(kind, err)
+}
+
+#[derive(Debug, Clone)]
+/// Cursor over the book implementing standard
+/// `Read`, `Write`, `Seek` and `BufRead` traits.
+///
+/// Cursor remembers the last accessed page, so sequential
+/// reads and writes don't locate it in the book's index
+/// on every call. Reads and writes are performed
+/// within a single page per call.
+///
+/// Length of the book is read when the cursor is created
+/// and updated by its writes, so the book must not be
+/// extended by anybody else while the cursor is used.
+pub struct BookCursor {
+    book: Book,
+    page_size: u64,
+
+    /// Offset of the book's data in the pages chain.
+    data_offset: u64,
+
+    len: u64,
+    pages: u64,
+    position: u64,
+
+    /// Last accessed page and its index in the book.
+    page: Page,
+    page_index: u64,
+
+    /// Bytes of the book starting from `buf_start`
+    /// returned by `BufRead::fill_buf`.
+    buf: Vec<u8>,
+    buf_start: u64
+}
+
+impl BookCursor {
+    pub fn new(book: Book) -> anyhow::Result<Self> {
+        let header = book.read_header()?;
+
+        Ok(Self {
+            page: book.entry_page().clone(),
+            page_index: 0,
+
+            data_offset: Book::data_offset(header.as_ref()),
+
+            len: book.len()?,
+            pages: book.pages()?,
+            position: 0,
+
+            buf: Vec::new(),
+            buf_start: 0,
+
+            page_size: book.page_size(),
+            book
+        })
+    }
+
+    #[inline]
+    pub const fn book(&self) -> &Book {
+        &self.book
+    }
+
+    #[inline]
+    pub fn into_book(self) -> Book {
+        self.book
+    }
+
+    #[inline]
+    /// Get current position of the cursor in the book.
+    pub const fn position(&self) -> u64 {
+        self.position
+    }
+
+    #[inline]
+    /// Get length of the book known to the cursor.
+    pub const fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get page storing the byte at given position. Pages
+    /// which are not next to the last accessed one are located
+    /// using the book's index. Missing pages are created
+    /// if `create` is set.
+    fn locate(&mut self, position: u64, create: bool) -> anyhow::Result<Option<Page>> {
+        let target = (position + self.data_offset) / self.page_size;
+
+        if target != self.page_index && target != self.page_index + 1 {
+            // Entry page always exists even if the header is corrupted.
+            let page_index = std::cmp::min(target, self.pages.saturating_sub(1));
+
+            match self.book.page_at(page_index)? {
+                Some(page) => {
+                    self.page = page;
+                    self.page_index = page_index;
+                }
+
+                None => {
+                    self.page = self.book.entry_page().clone();
+                    self.page_index = 0;
+                }
+            }
+        }
+
+        while self.page_index < target {
+            let page = if create {
+                self.book.create_next_page(&self.page, self.page_index, self.pages)?
+            } else {
+                match self.page.read_next_page()? {
+                    Some(page) => page,
+                    None => return Ok(None)
+                }
+            };
+
+            self.page = page;
+            self.page_index += 1;
+        }
+
+        Ok(Some(self.page.clone()))
+    }
+}
+
+impl Read for BookCursor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+
+        let n = std::cmp::min(available.len(), buf.len());
+
+        buf[..n].copy_from_slice(&available[..n]);
+
+        self.consume(n);
+
+        Ok(n)
+    }
+}
+
+impl BufRead for BookCursor {
+    /// Read the rest of the current page within the book's length.
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        let buf_end = self.buf_start + self.buf.len() as u64;
+
+        if self.position < self.buf_start || self.position >= buf_end {
+            self.buf.clear();
+            self.buf_start = self.position;
+
+            if self.position >= self.len {
+                return Ok(&[]);
+            }
+
+            let offset = (self.position + self.data_offset) % self.page_size;
+            let length = std::cmp::min(self.page_size - offset, self.len - self.position);
+
+            self.buf = match self.locate(self.position, false).map_err(io_error)? {
+                Some(page) => page.read(offset, length).map_err(io_error)?,
+
+                // Pages which weren't created are read as zeros.
+                None => vec![0; length as usize]
+            };
+        }
+
+        Ok(&self.buf[(self.position - self.buf_start) as usize..])
+    }
+
+    #[inline]
+    fn consume(&mut self, amount: usize) {
+        self.position += amount as u64;
+    }
+}
+
+impl Write for BookCursor {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let page = self.locate(self.position, true)
+            .map_err(io_error)?
+            .expect("Missing pages must be created");
+
+        let offset = (self.position + self.data_offset) % self.page_size;
+        let n = std::cmp::min(buf.len() as u64, self.page_size - offset) as usize;
+
+        page.write(offset, &buf[..n]).map_err(io_error)?;
+
+        self.buf.clear();
+
+        self.position += n as u64;
+
+        let len = std::cmp::max(self.len, self.position);
+        let pages = std::cmp::max(self.pages, self.page_index + 1);
+
+        if len != self.len || pages != self.pages {
+            self.book.write_book_header(len, pages).map_err(io_error)?;
+
+            self.len = len;
+            self.pages = pages;
+        }
+
+        Ok(n)
+    }
+
+    #[inline]
+    /// Writes are passed to the filesystem immediately.
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for BookCursor {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset)     => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset)
+        };
+
+        let Some(position) = position else {
+            return Err(Error::new(ErrorKind::InvalidInput, "invalid seek to a negative or overflowing position"));
+        };
+
+        self.position = position;
+
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filesystem::driver::tests::with_fs;
+
+    use super::*;
+
+    #[test]
+    fn read_write_seek() {
+        with_fs("book-cursor", |fs, _| {
+            let header = fs.read_header().unwrap();
+
+            let (response_sender, response_receiver) = flume::bounded(1);
+
+            fs.handler().send_normal(FilesystemTask::CreatePage { parent_page_number: None, response_sender }).unwrap();
+
+            let book = Book::create(response_receiver.recv().unwrap().unwrap(), header.page_size).unwrap();
+
+            let mut cursor = BookCursor::new(book.clone()).unwrap();
+
+            let lines = (0..1000)
+                .map(|i| format!("line {i}\n"))
+                .collect::<String>();
+
+            std::io::copy(&mut lines.as_bytes(), &mut cursor).unwrap();
+
+            assert_eq!(cursor.len(), lines.len() as u64);
+            assert_eq!(book.len().unwrap(), lines.len() as u64);
+            assert_eq!(book.pages().unwrap(), (lines.len() as u64 + BookHeader::LENGTH as u64).div_ceil(header.page_size));
+
+            cursor.rewind().unwrap();
+
+            let mut buf = String::new();
+
+            cursor.read_to_string(&mut buf).unwrap();
+
+            assert_eq!(buf, lines);
+
+            cursor.seek(SeekFrom::Start(header.page_size * 2)).unwrap();
+
+            let mut byte = [0];
+
+            cursor.read_exact(&mut byte).unwrap();
+
+            assert_eq!(byte[0], lines.as_bytes()[header.page_size as usize * 2]);
+
+            // Lines spanning multiple pages are read entirely.
+            cursor.rewind().unwrap();
+
+            let read_lines = BufRead::lines(&mut cursor)
+                .collect::<std::io::Result<Vec<_>>>()
+                .unwrap();
+
+            assert_eq!(read_lines.len(), 1000);
+            assert!(read_lines.iter().enumerate().all(|(i, line)| *line == format!("line {i}")));
+
+            // Overwrite the end of the book and extend it.
+            cursor.seek(SeekFrom::End(-2)).unwrap();
+            cursor.write_all(b"!!\nend").unwrap();
+
+            assert_eq!(book.len().unwrap(), lines.len() as u64 + 4);
+            assert_eq!(book.read_clamped(lines.len() as u64 - 4, 10).unwrap(), b"99!!\nend");
+
+            cursor.seek(SeekFrom::Current(-3)).unwrap();
+
+            let mut buf = Vec::new();
+
+            cursor.read_to_end(&mut buf).unwrap();
+
+            assert_eq!(buf, b"end");
+
+            assert_eq!(cursor.seek(SeekFrom::End(-1000000)).unwrap_err().kind(), ErrorKind::InvalidInput);
+        });
+    }
+}
@@ -1,7 +1,11 @@
 pub mod page;
 pub mod book;
+pub mod cache;
+pub mod cursor;
 
 pub mod prelude {
     pub use super::page::*;
     pub use super::book::*;
+    pub use super::cache::*;
+    pub use super::cursor::*;
 }
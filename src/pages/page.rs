@@ -1,6 +1,6 @@
-use crate::prelude::*;
+use anyhow::Context;
 
-// TODO: implement pages cache in addition to the IO buffer.
+use crate::prelude::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct PageHeader {
@@ -8,14 +8,42 @@ pub struct PageHeader {
     pub next_page_number: u32,
 
     pub has_prev: bool,
-    pub has_next: bool
+    pub has_next: bool,
+
+    /// Page is stored in the free pages list. Next page
+    /// number points to the next free page in this case.
+    pub is_free: bool,
+
+    /// Checksum of the page's body. Managed by the filesystem
+    /// worker and equals zero if page checksums are disabled.
+    pub checksum: u64,
+
+    /// Size of the compressed page's body stored on disk.
+    /// Managed by the filesystem worker and equals `None`
+    /// if the body is stored uncompressed.
+    pub compressed_size: Option<u32>,
+
+    /// Page's body starts with the book header.
+    /// Managed by the `Book`.
+    pub has_book_header: bool
 }
 
 impl PageHeader {
-    pub const LENGTH: usize = 9;
+    /// Length of the page header with all the fields.
+    pub const LENGTH: usize = 21;
 
-    pub const FLAG_HAS_PREV: u8 = 0b00000001;
-    pub const FLAG_HAS_NEXT: u8 = 0b00000010;
+    /// Length of the page header without checksum
+    /// and compressed size.
+    pub const BASE_LENGTH: usize = 9;
+
+    /// Length of the page header without compressed size.
+    pub const CHECKSUM_LENGTH: usize = 17;
+
+    pub const FLAG_HAS_PREV: u8        = 0b00000001;
+    pub const FLAG_HAS_NEXT: u8        = 0b00000010;
+    pub const FLAG_IS_FREE: u8         = 0b00000100;
+    pub const FLAG_IS_COMPRESSED: u8   = 0b00001000;
+    pub const FLAG_HAS_BOOK_HEADER: u8 = 0b00010000;
 
     /// Parse page header from the given bytes slice.
     pub fn from_bytes(bytes: &[u8; Self::LENGTH]) -> Self {
@@ -30,7 +58,18 @@ impl PageHeader {
             next_page_number: u32::from_le_bytes(next_page_number),
 
             has_prev: bytes[8] & Self::FLAG_HAS_PREV == Self::FLAG_HAS_PREV,
-            has_next: bytes[8] & Self::FLAG_HAS_NEXT == Self::FLAG_HAS_NEXT
+            has_next: bytes[8] & Self::FLAG_HAS_NEXT == Self::FLAG_HAS_NEXT,
+            is_free: bytes[8] & Self::FLAG_IS_FREE == Self::FLAG_IS_FREE,
+
+            checksum: u64::from_le_bytes([
+                bytes[9],  bytes[10], bytes[11], bytes[12],
+                bytes[13], bytes[14], bytes[15], bytes[16]
+            ]),
+
+            compressed_size: (bytes[8] & Self::FLAG_IS_COMPRESSED == Self::FLAG_IS_COMPRESSED)
+                .then(|| u32::from_le_bytes([bytes[17], bytes[18], bytes[19], bytes[20]])),
+
+            has_book_header: bytes[8] & Self::FLAG_HAS_BOOK_HEADER == Self::FLAG_HAS_BOOK_HEADER
         }
     }
 
@@ -49,6 +88,22 @@ impl PageHeader {
             bytes[8] |= Self::FLAG_HAS_NEXT;
         }
 
+        if self.is_free {
+            bytes[8] |= Self::FLAG_IS_FREE;
+        }
+
+        bytes[9..17].copy_from_slice(&self.checksum.to_le_bytes());
+
+        if let Some(compressed_size) = self.compressed_size {
+            bytes[8] |= Self::FLAG_IS_COMPRESSED;
+
+            bytes[17..21].copy_from_slice(&compressed_size.to_le_bytes());
+        }
+
+        if self.has_book_header {
+            bytes[8] |= Self::FLAG_HAS_BOOK_HEADER;
+        }
+
         bytes
     }
 }
@@ -73,92 +128,98 @@ impl Page {
         self.page_number
     }
 
+    #[inline]
+    pub const fn handler(&self) -> &FilesystemTasksHandler {
+        &self.handler
+    }
+
     /// Convert current page into a book.
-    pub fn into_book(self) -> Book {
+    pub fn into_book(self) -> anyhow::Result<Book> {
         let (response_sender, response_receiver) = flume::bounded(1);
 
         self.handler.send_normal(FilesystemTask::ReadFilesystemHeader { response_sender })
-            .unwrap_or_else(|err| {
-                panic!("Failed to read filesystem header : filesystem closed : {err}");
-            });
+            .map_err(|err| anyhow::anyhow!("Failed to read filesystem header : filesystem closed : {err}"))?;
 
         let header = response_receiver.recv()
-            .unwrap_or_else(|err| {
-                panic!("Failed to read filesystem header : filesystem closed : {err}");
-            });
+            .map_err(|err| anyhow::anyhow!("Failed to read filesystem header : filesystem closed : {err}"))?;
 
-        Book::open(self, header.page_size)
+        Ok(Book::open(self, header.page_size))
     }
 
     /// Read header of the page.
-    pub fn read_header(&self) -> PageHeader {
+    pub fn read_header(&self) -> anyhow::Result<PageHeader> {
         let (response_sender, response_receiver) = flume::bounded(1);
 
         self.handler.send_normal(FilesystemTask::ReadPageHeader {
             page_number: self.page_number,
             response_sender
-        }).unwrap_or_else(|err| {
-            panic!(
-                "Failed to read header of page 0x{:08x} : filesystem closed : {err}",
-                self.page_number
-            );
-        });
+        }).map_err(|err| {
+            anyhow::anyhow!("Failed to read header of page 0x{:08x} : filesystem closed : {err}", self.page_number)
+        })?;
 
         response_receiver.recv()
-            .unwrap_or_else(|err| {
-                panic!(
-                    "Failed to read header of page 0x{:08x} : filesystem closed : {err}",
-                    self.page_number
-                );
+            .map_err(|err| {
+                anyhow::anyhow!("Failed to read header of page 0x{:08x} : filesystem closed : {err}", self.page_number)
+            })?
+            .with_context(|| {
+                format!("Failed to read header of page 0x{:08x}", self.page_number)
             })
     }
 
     /// Write header of the page.
-    pub fn write_header(&self, header: PageHeader) {
+    pub fn write_header(&self, header: PageHeader) -> anyhow::Result<()> {
+        let (response_sender, response_receiver) = flume::bounded(1);
+
         self.handler.send_normal(FilesystemTask::WritePageHeader {
             page_number: self.page_number,
-            header
-        }).unwrap_or_else(|err| {
-            panic!(
-                "Failed to write header of page 0x{:08x} : filesystem closed : {err}",
-                self.page_number
-            );
-        });
+            header,
+            response_sender: Some(response_sender)
+        }).map_err(|err| {
+            anyhow::anyhow!("Failed to write header of page 0x{:08x} : filesystem closed : {err}", self.page_number)
+        })?;
+
+        response_receiver.recv()
+            .map_err(|err| {
+                anyhow::anyhow!("Failed to write header of page 0x{:08x} : filesystem closed : {err}", self.page_number)
+            })?
+            .with_context(|| {
+                format!("Failed to write header of page 0x{:08x}", self.page_number)
+            })
     }
 
     /// Try reading the next page if it exists.
-    pub fn read_next_page(&self) -> Option<Page> {
-        let header = self.read_header();
+    pub fn read_next_page(&self) -> anyhow::Result<Option<Page>> {
+        let header = self.read_header()?;
 
         if !header.has_next {
-            return None;
+            return Ok(None);
         }
 
-        Some(Self {
+        Ok(Some(Self {
             page_number: header.next_page_number,
             handler: self.handler.clone()
-        })
+        }))
     }
 
     /// Try reading the previous page if it exists.
-    pub fn read_prev_page(&self) -> Option<Page> {
-        let header = self.read_header();
+    pub fn read_prev_page(&self) -> anyhow::Result<Option<Page>> {
+        let header = self.read_header()?;
 
         if !header.has_prev {
-            return None;
+            return Ok(None);
         }
 
-        Some(Self {
+        Ok(Some(Self {
             page_number: header.prev_page_number,
             handler: self.handler.clone()
-        })
+        }))
     }
 
     /// Read next page if it exists or create a new one
     /// and link it with the current one.
-    pub fn create_next_page(&self) -> Page {
-        if let Some(page) = self.read_next_page() {
-            return page;
+    pub fn create_next_page(&self) -> anyhow::Result<Page> {
+        if let Some(page) = self.read_next_page()? {
+            return Ok(page);
         }
 
         let (response_sender, response_receiver) = flume::bounded(1);
@@ -166,27 +227,66 @@ impl Page {
         self.handler.send_normal(FilesystemTask::CreatePage {
             parent_page_number: Some(self.page_number),
             response_sender
-        }).unwrap_or_else(|err| {
-            panic!("Failed to create page : filesystem closed : {err}");
-        });
+        }).map_err(|err| anyhow::anyhow!("Failed to create page : filesystem closed : {err}"))?;
 
         let page = response_receiver.recv()
-            .unwrap_or_else(|err| {
-                panic!("Failed to create page : filesystem closed : {err}");
-            });
+            .map_err(|err| anyhow::anyhow!("Failed to create page : filesystem closed : {err}"))?
+            .context("Failed to create page")?;
+
+        let (response_sender, response_receiver) = flume::bounded(1);
 
         self.handler.send_normal(FilesystemTask::LinkPageForward {
             page_number: self.page_number,
-            next_page_number: page.page_number
-        }).unwrap_or_else(|err| {
-            panic!(
+            next_page_number: page.page_number,
+            response_sender: Some(response_sender)
+        }).map_err(|err| {
+            anyhow::anyhow!(
                 "Failed to link page {:08x} with the newly created {:08x} : filesystem closed : {err}",
                 self.page_number,
                 page.page_number
-            );
-        });
+            )
+        })?;
+
+        response_receiver.recv()
+            .map_err(|err| {
+                anyhow::anyhow!(
+                    "Failed to link page {:08x} with the newly created {:08x} : filesystem closed : {err}",
+                    self.page_number,
+                    page.page_number
+                )
+            })?
+            .with_context(|| {
+                format!(
+                    "Failed to link page {:08x} with the newly created {:08x}",
+                    self.page_number,
+                    page.page_number
+                )
+            })?;
+
+        Ok(page)
+    }
 
-        page
+    /// Return page to the free pages list so it can be
+    /// reused by the filesystem.
+    ///
+    /// Page is not unlinked from its neighbours.
+    pub fn free(self) -> anyhow::Result<()> {
+        let (response_sender, response_receiver) = flume::bounded(1);
+
+        self.handler.send_normal(FilesystemTask::FreePage {
+            page_number: self.page_number,
+            response_sender: Some(response_sender)
+        }).map_err(|err| {
+            anyhow::anyhow!("Failed to free page 0x{:08x} : filesystem closed : {err}", self.page_number)
+        })?;
+
+        response_receiver.recv()
+            .map_err(|err| {
+                anyhow::anyhow!("Failed to free page 0x{:08x} : filesystem closed : {err}", self.page_number)
+            })?
+            .with_context(|| {
+                format!("Failed to free page 0x{:08x}", self.page_number)
+            })
     }
 
     /// Read page body with given offset and length.
@@ -195,7 +295,7 @@ impl Page {
     /// on given offset. Returned buffer can be smaller than
     /// the requested length if you've reached the end
     /// of the page.
-    pub fn read(&self, offset: u64, length: u64) -> Vec<u8> {
+    pub fn read(&self, offset: u64, length: u64) -> anyhow::Result<Vec<u8>> {
         let (response_sender, response_receiver) = flume::bounded(1);
 
         self.handler.send_normal(FilesystemTask::ReadPage {
@@ -203,19 +303,16 @@ impl Page {
             offset,
             length,
             response_sender
-        }).unwrap_or_else(|err| {
-            panic!(
-                "Failed to read page 0x{:08x} : filesystem closed : {err}",
-                self.page_number
-            );
-        });
+        }).map_err(|err| {
+            anyhow::anyhow!("Failed to read page 0x{:08x} : filesystem closed : {err}", self.page_number)
+        })?;
 
         response_receiver.recv()
-            .unwrap_or_else(|err| {
-                panic!(
-                    "Failed to read page 0x{:08x} : filesystem closed : {err}",
-                    self.page_number
-                );
+            .map_err(|err| {
+                anyhow::anyhow!("Failed to read page 0x{:08x} : filesystem closed : {err}", self.page_number)
+            })?
+            .with_context(|| {
+                format!("Failed to read page 0x{:08x}", self.page_number)
             })
     }
 
@@ -223,7 +320,7 @@ impl Page {
     /// if the end of the page was reached.
     ///
     /// This method will overwrite existing data.
-    pub fn write(&self, offset: u64, bytes: impl Into<Vec<u8>>) -> Vec<u8> {
+    pub fn write(&self, offset: u64, bytes: impl Into<Vec<u8>>) -> anyhow::Result<Vec<u8>> {
         let (response_sender, response_receiver) = flume::bounded(1);
 
         self.handler.send_normal(FilesystemTask::WritePage {
@@ -231,13 +328,16 @@ impl Page {
             offset,
             bytes: bytes.into(),
             response_sender: Some(response_sender)
-        }).unwrap_or_else(|err| {
-            panic!(
-                "Failed to write page 0x{:08x} : filesystem closed : {err}",
-                self.page_number
-            );
-        });
-
-        response_receiver.recv().unwrap_or_default()
+        }).map_err(|err| {
+            anyhow::anyhow!("Failed to write page 0x{:08x} : filesystem closed : {err}", self.page_number)
+        })?;
+
+        response_receiver.recv()
+            .map_err(|err| {
+                anyhow::anyhow!("Failed to write page 0x{:08x} : filesystem closed : {err}", self.page_number)
+            })?
+            .with_context(|| {
+                format!("Failed to write page 0x{:08x}", self.page_number)
+            })
     }
 }